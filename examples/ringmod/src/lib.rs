@@ -1,6 +1,6 @@
 extern crate ladspa;
 
-use ladspa::{Plugin, PluginDescriptor, Port, PortConnection, Data};
+use ladspa::{Plugin, PluginDescriptor, PluginError, Port, PortConnection, Data};
 use std::default::Default;
 
 struct RingMod {
@@ -8,11 +8,11 @@ struct RingMod {
     sample_rate: u64,
 }
 
-fn new_ringmod(_: &PluginDescriptor, sample_rate: u64) -> Box<Plugin + Send> {
-    Box::new(RingMod {
+fn new_ringmod(_: &PluginDescriptor, sample_rate: u64) -> Result<Box<Plugin + Send>, PluginError> {
+    Ok(Box::new(RingMod {
         time: 0,
         sample_rate: sample_rate,
-    })
+    }))
 }
 
 impl Plugin for RingMod {
@@ -28,39 +28,42 @@ impl Plugin for RingMod {
         }
         self.time += sample_count as u64;
     }
-    fn activate(&mut self) {
+    fn activate(&mut self) -> Result<(), PluginError> {
         self.time = 0;
+        Ok(())
     }
 }
 
 #[no_mangle]
-pub extern fn get_ladspa_descriptor(index: u64) -> Option<PluginDescriptor> {
+pub fn get_ladspa_descriptor(index: u64) -> Option<PluginDescriptor> {
     match index {
         0 => {
             Some(PluginDescriptor {
                 unique_id: 401,
-                label: "ring_mod",
+                label: "ring_mod".into(),
                 properties: ladspa::PROP_NONE,
-                name: "Mono Ring Modulator",
-                maker: "Noah Weninger",
-                copyright: "None",
+                name: "Mono Ring Modulator".into(),
+                maker: "Noah Weninger".into(),
+                copyright: "None".into(),
                 ports: vec![Port {
-                    name: "Audio In",
+                    name: "Audio In".into(),
                     desc: ladspa::PortDescriptor::AudioInput,
                     .. Default::default()
                 }, Port {
-                    name: "Audio Out",
+                    name: "Audio Out".into(),
                     desc: ladspa::PortDescriptor::AudioOutput,
                     .. Default::default()
                 }, Port {
-                    name: "Frequency",
+                    name: "Frequency".into(),
                     desc: ladspa::PortDescriptor::ControlInput,
                     hint: Some(ladspa::HINT_SAMPLE_RATE | ladspa::HINT_LOGARITHMIC),
                     default: Some(ladspa::DefaultValue::Value440),
                     lower_bound: Some(0.0),
                     upper_bound: Some(0.5),
+                    aliases: vec![],
                 }],
-                new: new_ringmod
+                new: Box::new(new_ringmod),
+                presets: vec![],
             })
         },
         _ => None
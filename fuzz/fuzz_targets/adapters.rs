@@ -0,0 +1,34 @@
+// Drives a small expression plugin, wrapped in every adapter this crate provides, through the C
+// interface.
+
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate ladspa;
+
+use std::sync::OnceLock;
+
+use ladspa::{block, bypass, expr, fuzz, mix, oversample};
+use ladspa::host::Descriptor;
+
+const SOURCE: &str = "
+input in
+control f = 440 [20, 20000] log
+control drive = 1 [0, 4] integer
+y = y + 0.01 * (in * drive - y)
+out = y * sin(2 * pi * f * t)
+";
+
+static DESCRIPTOR: OnceLock<Descriptor> = OnceLock::new();
+
+fuzz_target!(|data: &[u8]| {
+    let desc = DESCRIPTOR.get_or_init(|| {
+        let plugin = expr::descriptor(1, "fuzz".into(), "Fuzz".into(), "".into(), "".into(), SOURCE)
+            .unwrap();
+        let plugin = block::wrap(plugin, 2, "block".into(), 64, 32);
+        let factor = oversample::Factor::Control { max: 4 };
+        let plugin = oversample::wrap(plugin, 3, "oversampled".into(), factor);
+        let plugin = bypass::wrap(plugin, 4, "bypass".into(), 0.01);
+        Descriptor::from_native(mix::wrap(plugin, 5, "mix".into()))
+    });
+    fuzz::run(desc, data);
+});
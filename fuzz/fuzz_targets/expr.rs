@@ -0,0 +1,15 @@
+// Compiles arbitrary text as an expression plugin, which should only ever fail with an error.
+
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate ladspa;
+
+use std::str;
+
+use ladspa::expr::Program;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(source) = str::from_utf8(data) {
+        let _ = Program::compile(source);
+    }
+});
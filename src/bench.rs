@@ -0,0 +1,308 @@
+//! Measuring how long a plugin takes to run, to check the cost model of
+//! ```PROP_HARD_REALTIME_CAPABLE```.
+//!
+//! A hard realtime capable plugin's ```run``` should take roughly A + B * SampleCount time
+//! regardless of the signal it processes. ```run``` times calls to ```run``` through the C
+//! interface across a range of block sizes and input signals, fits A and B to the timings for
+//! each signal by least squares, and flags block sizes at which the time taken depends on the
+//! signal. Denormal numbers are the usual cause: many processors handle them far more slowly,
+//! and filters and reverbs produce them as their output decays towards silence.
+//!
+//! ```rust,ignore
+//! let report = ladspa::bench::run(&Descriptor::from_native(my_plugin()), &Benchmark::default())?;
+//! println!("{}", report);
+//! assert!(report.signal_dependent().is_empty());
+//! ```
+//!
+//! Timings are affected by everything else running on the machine, so run benchmarks on an idle
+//! machine with optimizations enabled.
+//!
+//! This module needs the ```testing``` feature.
+
+use std::error;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use ffi;
+use host::{Descriptor, Instance};
+use super::{Data, PortDescriptor, PROP_HARD_REALTIME_CAPABLE};
+
+/// The ways a benchmark can fail.
+#[derive(Debug)]
+pub enum Error {
+    /// The plugin panicked.
+    Panic(String),
+    /// The plugin couldn't be instantiated.
+    Instantiate,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Panic(ref msg) => write!(f, "{}", msg),
+            Error::Instantiate => write!(f, "failed to instantiate"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// The signal fed to every audio input while timing.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Signal {
+    Silence,
+
+    /// White noise between -0.5 and 0.5.
+    Noise,
+
+    /// Silence after a burst of noise, so that any internal state decays towards zero through
+    /// the denormal range.
+    Decay,
+
+    /// White noise scaled into the denormal range.
+    Denormals,
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Signal::Silence => "silence",
+            Signal::Noise => "noise",
+            Signal::Decay => "decay",
+            Signal::Denormals => "denormals",
+        };
+        f.pad(name)
+    }
+}
+
+/// What to measure.
+#[derive(Clone, Debug)]
+pub struct Benchmark {
+    pub sample_rate: u64,
+
+    /// The values of ```sample_count``` to time ```run``` with.
+    pub block_sizes: Vec<usize>,
+
+    pub signals: Vec<Signal>,
+
+    /// How many times each call is timed. The median time is used.
+    pub repetitions: usize,
+
+    /// How many samples are processed before timing starts, so that caches are warm and the
+    /// ```Decay``` signal has decayed.
+    pub warm_up: usize,
+
+    /// The largest ratio between the times taken on different signals at the same block size
+    /// which isn't reported as signal dependent.
+    pub max_ratio: f64,
+}
+
+impl Default for Benchmark {
+    fn default() -> Benchmark {
+        Benchmark {
+            sample_rate: 48000,
+            block_sizes: vec![1, 16, 64, 256, 1024, 4096],
+            signals: vec![Signal::Silence, Signal::Noise, Signal::Decay, Signal::Denormals],
+            repetitions: 101,
+            warm_up: 48000,
+            max_ratio: 1.5,
+        }
+    }
+}
+
+/// The fitted cost of a call to ```run```.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Fit {
+    /// The fixed cost of each call, in seconds.
+    pub a: f64,
+
+    /// The cost of each sample, in seconds.
+    pub b: f64,
+
+    /// The largest difference between a measured time and the fit, relative to the measured
+    /// time. Large values mean the time taken doesn't grow linearly with the block size.
+    pub max_residual: f64,
+}
+
+impl Fit {
+    /// The predicted time taken to run ```sample_count``` samples, in seconds.
+    pub fn predict(&self, sample_count: usize) -> f64 {
+        self.a + self.b * sample_count as f64
+    }
+}
+
+impl fmt::Display for Fit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "A = {:.0}ns, B = {:.2}ns/sample (worst fit {:.0}%)",
+               self.a * 1e9,
+               self.b * 1e9,
+               self.max_residual * 100.0)
+    }
+}
+
+/// The timings of a plugin.
+#[derive(Clone, Debug)]
+pub struct Report {
+    pub label: String,
+    pub hard_realtime_capable: bool,
+    pub block_sizes: Vec<usize>,
+    pub max_ratio: f64,
+
+    /// For each signal, the median time taken at each block size and the fit to those times.
+    pub signals: Vec<(Signal, Vec<Duration>, Fit)>,
+}
+
+impl Report {
+    /// The block sizes at which the time taken on different signals differs by more than
+    /// ```max_ratio```, with the ratio between the slowest and fastest signal.
+    pub fn signal_dependent(&self) -> Vec<(usize, f64)> {
+        (0..self.block_sizes.len()).filter_map(|i| {
+            let times = self.signals.iter().map(|s| s.1[i].as_secs_f64());
+            let (fastest, slowest) = times.fold((f64::INFINITY, 0.0f64), |(lo, hi), t| (lo.min(t), hi.max(t)));
+            let ratio = slowest / fastest;
+            if fastest > 0.0 && ratio > self.max_ratio { Some((self.block_sizes[i], ratio)) } else { None }
+        }).collect()
+    }
+
+    /// Whether the plugin claims ```PROP_HARD_REALTIME_CAPABLE``` but its timing depends on
+    /// the signal.
+    pub fn violates_hard_realtime(&self) -> bool {
+        self.hard_realtime_capable && !self.signal_dependent().is_empty()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.label)?;
+        for &(signal, ref times, ref fit) in &self.signals {
+            writeln!(f, "  {:<10} {}", signal, fit)?;
+            for (size, time) in self.block_sizes.iter().zip(times) {
+                writeln!(f, "    {:>6} samples: {:>10.0}ns", size, time.as_secs_f64() * 1e9)?;
+            }
+        }
+        for (size, ratio) in self.signal_dependent() {
+            writeln!(f, "  at {} samples the slowest signal takes {:.1} times as long as the fastest", size, ratio)?;
+        }
+        if self.violates_hard_realtime() {
+            writeln!(f, "  claims PROP_HARD_REALTIME_CAPABLE, but its timing depends on the signal")?;
+        }
+        Ok(())
+    }
+}
+
+/// Times a plugin's ```run``` with every signal and block size in ```bench```.
+pub fn run(desc: &Descriptor, bench: &Benchmark) -> Result<Report, Error> {
+    let signals = bench.signals.iter()
+        .map(|&signal| {
+            let times = time(desc, bench, signal)?;
+            let fit = fit(&bench.block_sizes, &times);
+            Ok((signal, times, fit))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    Ok(Report {
+        label: desc.label(),
+        hard_realtime_capable: desc.properties().contains(PROP_HARD_REALTIME_CAPABLE),
+        block_sizes: bench.block_sizes.clone(),
+        max_ratio: bench.max_ratio,
+        signals: signals,
+    })
+}
+
+// Returns the median time taken at each block size with one signal.
+fn time(desc: &Descriptor, bench: &Benchmark, signal: Signal) -> Result<Vec<Duration>, Error> {
+    let max_block_size = bench.block_sizes.iter().cloned().max().unwrap_or(0).max(1);
+    let instance = Instance::new(desc, bench.sample_rate, max_block_size);
+    suppressed()?;
+    let mut instance = instance.map_err(|_| Error::Instantiate)?;
+    let inputs: Vec<usize> = (0..desc.port_count())
+        .filter(|&i| desc.port_descriptor(i) == PortDescriptor::AudioInput)
+        .collect();
+
+    instance.activate();
+    suppressed()?;
+    let mut state = 1u32;
+    let mut fill = |instance: &mut Instance, scale: Data| {
+        for &port in &inputs {
+            for x in instance.audio_mut(port) {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                *x = (state as Data / u32::MAX as Data - 0.5) * scale;
+            }
+        }
+    };
+    fill(&mut instance, match signal {
+        Signal::Silence => 0.0,
+        Signal::Noise | Signal::Decay => 1.0,
+        Signal::Denormals => 1e-39,
+    });
+    if signal == Signal::Decay {
+        instance.run(max_block_size);
+        suppressed()?;
+        fill(&mut instance, 0.0);
+    }
+    let mut warmed = 0;
+    while warmed < bench.warm_up {
+        instance.run(max_block_size);
+        suppressed()?;
+        warmed += max_block_size;
+    }
+
+    let mut times = vec![Vec::with_capacity(bench.repetitions); bench.block_sizes.len()];
+    for _ in 0..bench.repetitions.max(1) {
+        // Interleaving the block sizes spreads any slowdown of the machine across all of them.
+        for (&size, times) in bench.block_sizes.iter().zip(&mut times) {
+            let start = Instant::now();
+            instance.run(size);
+            times.push(start.elapsed());
+            suppressed()?;
+        }
+    }
+    drop(instance);
+    suppressed()?;
+    Ok(times.into_iter().map(|mut times| {
+        times.sort();
+        times[times.len() / 2]
+    }).collect())
+}
+
+/// Fits A + B * block size to the times taken at each block size by least squares, as ```run```
+/// does. Each time is weighted by the inverse of its square so that the relative error is
+/// minimized, as otherwise the largest blocks would determine the fit and A would be meaningless.
+/// Times of zero are left out.
+pub fn fit(block_sizes: &[usize], times: &[Duration]) -> Fit {
+    let points: Vec<(f64, f64)> = block_sizes.iter()
+        .zip(times)
+        .map(|(&x, t)| (x as f64, t.as_secs_f64()))
+        .filter(|&(_, y)| y > 0.0)
+        .collect();
+    let (mut w, mut sx, mut sy, mut sxx, mut sxy) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    for &(x, y) in &points {
+        let weight = 1.0 / (y * y);
+        w += weight;
+        sx += weight * x;
+        sy += weight * y;
+        sxx += weight * x * x;
+        sxy += weight * x * y;
+    }
+    let determinant = w * sxx - sx * sx;
+    let (a, b) = if determinant > 0.0 {
+        let b = (w * sxy - sx * sy) / determinant;
+        ((sy - b * sx) / w, b)
+    } else if w > 0.0 {
+        (sy / w, 0.0)
+    } else {
+        (0.0, 0.0)
+    };
+    let max_residual = points.iter().map(|&(x, y)| (a + b * x - y).abs() / y).fold(0.0, f64::max);
+    Fit { a: a, b: b, max_residual: max_residual }
+}
+
+// Fails if the plugin panicked during the last call.
+fn suppressed() -> Result<(), Error> {
+    match ffi::take_suppressed_panic() {
+        Some(msg) => Err(Error::Panic(msg)),
+        None => Ok(()),
+    }
+}
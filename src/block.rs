@@ -0,0 +1,205 @@
+//! Running a plugin on blocks of a fixed size.
+//!
+//! Hosts may call ```run``` with any ```sample_count```, including 1. Frame-based plugins such as
+//! FFT processors can be wrapped with ```wrap``` so that they always see ```block_size``` samples.
+//! Each time ```hop``` new input samples have arrived, the inner plugin is run on the most recent
+//! ```block_size``` input samples. Its outputs are overlap-added at ```hop``` sample intervals,
+//! so with a ```hop``` smaller than ```block_size``` the inner plugin is responsible for any
+//! windowing. Control inputs are read, and control outputs written, once per inner block.
+//!
+//! The output is delayed by ```latency(block_size)``` samples.
+
+use std::borrow::Cow;
+use std::cmp;
+use std::sync::Arc;
+
+use buffers::PortBuffers;
+use super::{Data, Plugin, PluginDescriptor, PluginError, Port, PortConnection, PortDescriptor};
+
+/// Returns the latency added by running a plugin with the given block size, or 0 for the invalid
+/// size 0.
+pub fn latency(block_size: usize) -> usize {
+    block_size.saturating_sub(1)
+}
+
+/// Wraps a plugin so that it is always run on ```block_size``` samples, advancing by ```hop```
+/// samples each time. The returned descriptor has the ports of ```desc```, but its output is
+/// delayed, so it's a different plugin to hosts and is given its own ```unique_id``` and
+/// ```label```. Instances fail to be created unless ```0 < hop <= block_size```.
+pub fn wrap(desc: PluginDescriptor,
+            unique_id: u64,
+            label: Cow<'static, str>,
+            block_size: usize,
+            hop: usize)
+            -> PluginDescriptor {
+    let ports = desc.ports.clone();
+    let presets = desc.presets.clone();
+    let inner = Arc::new(desc);
+    PluginDescriptor {
+        unique_id: unique_id,
+        label: label,
+        properties: inner.properties,
+        name: inner.name.clone(),
+        maker: inner.maker.clone(),
+        copyright: inner.copyright.clone(),
+        ports: ports,
+        new: Box::new(move |_: &PluginDescriptor, sample_rate: u64| {
+            let plugin = FixedBlock::new(&inner, sample_rate, block_size, hop)?;
+            Ok(Box::new(plugin) as Box<Plugin + Send>)
+        }),
+        presets: presets,
+    }
+}
+
+/// A plugin which is always run on blocks of the same size. See the module documentation for
+/// details.
+pub struct FixedBlock {
+    plugin: Box<Plugin + Send>,
+    ports: Vec<Port>,
+    buffers: PortBuffers,
+    block_size: usize,
+    hop: usize,
+    // The number of input samples received since the inner plugin was last run.
+    fill: usize,
+    // The position of the next sample to output from `finished`.
+    read: usize,
+    // For each audio output, the overlap-add accumulator and the samples ready to be output.
+    accumulated: Vec<Vec<Data>>,
+    finished: Vec<Vec<Data>>,
+}
+
+impl FixedBlock {
+    /// Instantiates ```inner```. Fails unless ```0 < hop <= block_size```.
+    pub fn new(inner: &PluginDescriptor,
+               sample_rate: u64,
+               block_size: usize,
+               hop: usize)
+               -> Result<FixedBlock, PluginError> {
+        if hop == 0 || hop > block_size {
+            let msg = format!("hop {} isn't between 1 and the block size {}", hop, block_size);
+            return Err(msg.into());
+        }
+        let outputs = inner.ports.iter().filter(|p| p.desc == PortDescriptor::AudioOutput).count();
+        Ok(FixedBlock {
+            plugin: (inner.new)(inner, sample_rate)?,
+            ports: inner.ports.clone(),
+            buffers: PortBuffers::new(&inner.ports, block_size),
+            block_size: block_size,
+            hop: hop,
+            fill: 0,
+            read: 1,
+            accumulated: vec![vec![0.0; block_size]; outputs],
+            finished: vec![vec![0.0; hop]; outputs],
+        })
+    }
+
+    // Runs the inner plugin on the current frame and overlap-adds its output.
+    fn run_block<'a>(&mut self, ports: &[&'a PortConnection<'a>]) {
+        for (i, port) in self.ports.iter().enumerate() {
+            if port.desc == PortDescriptor::ControlInput {
+                self.buffers.set_control(i, *ports[i].unwrap_control());
+            }
+        }
+
+        self.buffers.run(&mut *self.plugin, self.block_size);
+
+        let (block_size, hop) = (self.block_size, self.hop);
+        let mut output = 0;
+        for (i, port) in self.ports.iter().enumerate() {
+            match port.desc {
+                PortDescriptor::ControlOutput => {
+                    **ports[i].unwrap_control_mut() = self.buffers.control(i);
+                }
+                PortDescriptor::AudioOutput => {
+                    let acc = &mut self.accumulated[output];
+                    for (x, y) in acc.iter_mut().zip(self.buffers.audio(i)) {
+                        *x += *y;
+                    }
+                    self.finished[output].copy_from_slice(&acc[..hop]);
+                    acc.copy_within(hop.., 0);
+                    for x in &mut acc[block_size - hop..] {
+                        *x = 0.0;
+                    }
+                    output += 1;
+                }
+                PortDescriptor::AudioInput => {
+                    // Slide the frame along to make room for the next hop.
+                    self.buffers.audio_mut(i).copy_within(hop.., 0);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Plugin for FixedBlock {
+    fn activate(&mut self) -> Result<(), PluginError> {
+        for (i, port) in self.ports.iter().enumerate() {
+            if port.desc.is_audio() {
+                for x in self.buffers.audio_mut(i) {
+                    *x = 0.0;
+                }
+            }
+        }
+        for buf in self.accumulated.iter_mut().chain(self.finished.iter_mut()) {
+            for x in buf {
+                *x = 0.0;
+            }
+        }
+        self.fill = 0;
+        self.read = 1;
+        self.plugin.activate()
+    }
+
+    fn run<'a>(&mut self, sample_count: usize, ports: &[&'a PortConnection<'a>]) {
+        let (block_size, hop) = (self.block_size, self.hop);
+        let mut offset = 0;
+        while offset < sample_count {
+            // Process up to the point where the next frame is complete.
+            let count = cmp::min(sample_count - offset, hop - self.fill);
+            for (i, port) in self.ports.iter().enumerate() {
+                if port.desc == PortDescriptor::AudioInput {
+                    let start = block_size - hop + self.fill;
+                    self.buffers.audio_mut(i)[start..start + count]
+                        .copy_from_slice(&ports[i].unwrap_audio()[offset..offset + count]);
+                }
+            }
+            self.fill += count;
+
+            // Samples are output after the input at the same position is consumed, so the last
+            // sample of a completed frame comes from the newly finished block.
+            let from_old = if self.fill == hop { count - 1 } else { count };
+            let mut output = 0;
+            for (i, port) in self.ports.iter().enumerate() {
+                if port.desc == PortDescriptor::AudioOutput {
+                    ports[i].unwrap_audio_mut()[offset..offset + from_old]
+                        .copy_from_slice(&self.finished[output][self.read..self.read + from_old]);
+                    output += 1;
+                }
+            }
+            self.read += from_old;
+
+            if self.fill == hop {
+                self.run_block(ports);
+                let mut output = 0;
+                for (i, port) in self.ports.iter().enumerate() {
+                    if port.desc == PortDescriptor::AudioOutput {
+                        ports[i].unwrap_audio_mut()[offset + count - 1] = self.finished[output][0];
+                        output += 1;
+                    }
+                }
+                self.fill = 0;
+                self.read = 1;
+            }
+            offset += count;
+        }
+    }
+
+    fn deactivate(&mut self) {
+        self.plugin.deactivate();
+    }
+
+    fn latency(&self) -> usize {
+        latency(self.block_size) + self.plugin.latency()
+    }
+}
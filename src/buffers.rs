@@ -0,0 +1,87 @@
+// Buffers for running a Plugin directly from Rust, used by the plugin adapters. Unlike
+// host::Instance, the plugin is driven through the Plugin trait rather than the C interface.
+
+use std::cell::RefCell;
+use std::mem;
+
+use super::{Data, Plugin, Port, PortConnection, PortData, PortDescriptor};
+
+pub struct PortBuffers {
+    ports: Vec<Port>,
+    block_size: usize,
+    audio: Vec<Vec<Data>>,
+    control: Vec<Data>,
+    // Storage reused between calls to run so that it never allocates. Always empty between calls.
+    connections: Vec<PortConnection<'static>>,
+    refs: Vec<&'static PortConnection<'static>>,
+}
+
+impl PortBuffers {
+    // Allocates block_size samples for each audio port. Control inputs start at zero.
+    pub fn new(ports: &[Port], block_size: usize) -> PortBuffers {
+        PortBuffers {
+            ports: ports.to_vec(),
+            block_size: block_size,
+            audio: ports.iter()
+                .map(|port| if port.desc.is_audio() { vec![0.0; block_size] } else { Vec::new() })
+                .collect(),
+            control: vec![0.0; ports.len()],
+            connections: Vec::with_capacity(ports.len()),
+            refs: Vec::with_capacity(ports.len()),
+        }
+    }
+
+    pub fn audio(&self, port: usize) -> &[Data] {
+        &self.audio[port]
+    }
+
+    pub fn audio_mut(&mut self, port: usize) -> &mut [Data] {
+        &mut self.audio[port]
+    }
+
+    pub fn control(&self, port: usize) -> Data {
+        self.control[port]
+    }
+
+    pub fn set_control(&mut self, port: usize, value: Data) {
+        self.control[port] = value;
+    }
+
+    // Runs the plugin on the first sample_count samples of each audio buffer.
+    pub fn run(&mut self, plugin: &mut Plugin, sample_count: usize) {
+        assert!(sample_count <= self.block_size, "block too large for port buffers");
+        unsafe {
+            // The stored vectors are empty, so only their allocations are reused here.
+            let mut connections: Vec<PortConnection> =
+                mem::transmute(mem::replace(&mut self.connections, Vec::new()));
+            for ((port, audio), control) in self.ports.iter()
+                .zip(self.audio.iter_mut())
+                .zip(self.control.iter_mut()) {
+                let data = match port.desc {
+                    PortDescriptor::AudioInput => PortData::AudioInput(&audio[..sample_count]),
+                    PortDescriptor::AudioOutput => {
+                        PortData::AudioOutput(RefCell::new(&mut audio[..sample_count]))
+                    }
+                    PortDescriptor::ControlInput => PortData::ControlInput(control),
+                    PortDescriptor::ControlOutput => PortData::ControlOutput(RefCell::new(control)),
+                    PortDescriptor::Invalid => panic!("Invalid port descriptor!"),
+                };
+                connections.push(PortConnection {
+                    port: port,
+                    data: data,
+                });
+            }
+
+            let mut refs: Vec<&PortConnection> = mem::transmute(mem::replace(&mut self.refs, Vec::new()));
+            // Plugin::run requires the references to live as long as the port data, so borrow
+            // through a pointer rather than tying the port data to this stack frame.
+            refs.extend(connections.iter().map(|conn| &*(conn as *const PortConnection)));
+            plugin.run(sample_count, &refs);
+            refs.clear();
+            self.refs = mem::transmute(refs);
+
+            connections.clear();
+            self.connections = mem::transmute(connections);
+        }
+    }
+}
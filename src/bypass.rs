@@ -0,0 +1,222 @@
+//! Switching a plugin on and off without clicks.
+//!
+//! ```wrap``` adds a toggled "Bypass" control input to a plugin, numbered if the plugin already has
+//! a port with that name. Changing it crossfades between the processed output and the dry input
+//! over a configurable time. The dry input is delayed by the latency of the inner plugin so that
+//! the two line up. Once fully bypassed, the inner plugin is no longer run, and it is deactivated
+//! and activated again before being heard again, so that it starts from a clean state rather than
+//! the one it was left in.
+//!
+//! Each audio output is paired with the audio input at the same position among the inputs.
+//! Outputs without a matching input fade to silence.
+
+use std::borrow::Cow;
+use std::cmp;
+use std::sync::Arc;
+
+use buffers::PortBuffers;
+use delay::Delay;
+use diagnostics;
+use super::{unused_port_name, Data, DefaultValue, Plugin, PluginDescriptor, PluginError, Port,
+            PortConnection, PortDescriptor, HINT_TOGGLED};
+
+/// The number of samples processed at a time.
+const BLOCK_SIZE: usize = 256;
+
+/// Wraps a plugin with a "Bypass" control input, crossfading over ```fade``` seconds. The returned
+/// descriptor has the ports of ```desc```, followed by the "Bypass" port, so it's a different
+/// plugin to hosts and is given its own ```unique_id``` and ```label```.
+pub fn wrap(desc: PluginDescriptor,
+            unique_id: u64,
+            label: Cow<'static, str>,
+            fade: f32)
+            -> PluginDescriptor {
+    let mut ports = desc.ports.clone();
+    ports.push(Port {
+        name: unused_port_name(&desc.ports, "Bypass"),
+        desc: PortDescriptor::ControlInput,
+        hint: Some(HINT_TOGGLED),
+        default: Some(DefaultValue::Value0),
+        ..Default::default()
+    });
+    let presets = desc.presets.clone();
+    let inner = Arc::new(desc);
+    PluginDescriptor {
+        unique_id: unique_id,
+        label: label,
+        properties: inner.properties,
+        name: inner.name.clone(),
+        maker: inner.maker.clone(),
+        copyright: inner.copyright.clone(),
+        ports: ports,
+        new: Box::new(move |_: &PluginDescriptor, sample_rate: u64| {
+            Ok(Box::new(Bypassable::new(&inner, sample_rate, fade)?) as Box<Plugin + Send>)
+        }),
+        presets: presets,
+    }
+}
+
+/// A plugin which can be bypassed without clicks. Its ports are those of the inner plugin,
+/// followed by the "Bypass" port.
+///
+/// Most of the time it's simpler to use ```wrap```, but this can also be returned from your own
+/// ```PluginDescriptor::new```.
+pub struct Bypassable {
+    plugin: Box<Plugin + Send>,
+    ports: Vec<Port>,
+    buffers: PortBuffers,
+    // The audio input ports, and the one each audio output takes its dry signal from.
+    inputs: Vec<usize>,
+    dry: Vec<Option<usize>>,
+    // A delay line for each audio input, aligning the dry signal with the processed one.
+    delays: Vec<Delay>,
+    // How much of the processed signal is heard, from 0 when bypassed to 1.
+    mix: Data,
+    step: Data,
+    // Whether the inner plugin has stopped being run since it was activated, and whether activating
+    // it again failed, in which case it stays bypassed until the host activates it.
+    idle: bool,
+    failed: bool,
+}
+
+impl Bypassable {
+    /// Instantiates ```inner```, crossfading over ```fade``` seconds.
+    pub fn new(inner: &PluginDescriptor,
+               sample_rate: u64,
+               fade: f32)
+               -> Result<Bypassable, PluginError> {
+        let inputs: Vec<usize> = (0..inner.ports.len())
+            .filter(|&i| inner.ports[i].desc == PortDescriptor::AudioInput)
+            .collect();
+        let outputs = inner.ports.iter().filter(|p| p.desc == PortDescriptor::AudioOutput).count();
+        let samples = fade * sample_rate as f32;
+        Ok(Bypassable {
+            plugin: (inner.new)(inner, sample_rate)?,
+            ports: inner.ports.clone(),
+            buffers: PortBuffers::new(&inner.ports, BLOCK_SIZE),
+            dry: (0..outputs).map(|i| if i < inputs.len() { Some(i) } else { None }).collect(),
+            delays: inputs.iter().map(|_| Delay::new()).collect(),
+            inputs: inputs,
+            mix: 1.0,
+            step: if samples >= 1.0 { 1.0 / samples } else { 1.0 },
+            idle: false,
+            failed: false,
+        })
+    }
+
+    /// Returns true once the crossfade to the dry signal has finished.
+    pub fn is_bypassed(&self) -> bool {
+        self.mix == 0.0
+    }
+}
+
+impl Plugin for Bypassable {
+    fn activate(&mut self) -> Result<(), PluginError> {
+        self.idle = false;
+        self.failed = false;
+        self.plugin.activate()?;
+        let latency = self.plugin.latency();
+        for delay in &mut self.delays {
+            delay.reset();
+            delay.reserve(latency);
+        }
+        Ok(())
+    }
+
+    fn run<'a>(&mut self, sample_count: usize, ports: &[&'a PortConnection<'a>]) {
+        let mut target = if *ports[self.ports.len()].unwrap_control() > 0.0 { 0.0 } else { 1.0 };
+        // The inner plugin's state is stale after a bypass, so it starts again when un-bypassed.
+        if target > 0.0 && self.idle && !self.failed {
+            self.plugin.deactivate();
+            match self.plugin.activate() {
+                Ok(()) => self.idle = false,
+                Err(err) => {
+                    let msg = format!("can't stop bypassing, so staying bypassed: {}", err);
+                    diagnostics::report(&msg);
+                    self.failed = true;
+                }
+            }
+        }
+        if self.failed {
+            target = 0.0;
+        }
+        let step = if target > self.mix { self.step } else { -self.step };
+        let latency = self.plugin.latency();
+        for delay in &mut self.delays {
+            delay.set_len(latency);
+        }
+
+        let mut offset = 0;
+        while offset < sample_count {
+            let count = cmp::min(BLOCK_SIZE, sample_count - offset);
+            let skip = self.mix == 0.0 && target == 0.0;
+
+            for (i, port) in self.ports.iter().enumerate() {
+                match port.desc {
+                    PortDescriptor::ControlInput => {
+                        self.buffers.set_control(i, *ports[i].unwrap_control());
+                    }
+                    PortDescriptor::AudioInput => {
+                        self.buffers.audio_mut(i)[..count]
+                            .copy_from_slice(&ports[i].unwrap_audio()[offset..offset + count]);
+                    }
+                    _ => {}
+                }
+            }
+
+            if skip {
+                self.idle = true;
+            } else {
+                self.buffers.run(&mut *self.plugin, count);
+            }
+
+            let mut output = 0;
+            for (i, port) in self.ports.iter().enumerate() {
+                match port.desc {
+                    PortDescriptor::ControlOutput if !skip => {
+                        **ports[i].unwrap_control_mut() = self.buffers.control(i);
+                    }
+                    PortDescriptor::AudioOutput => {
+                        let dry = self.dry[output]
+                            .map(|input| (&self.delays[input], self.buffers.audio(self.inputs[input])));
+                        let wet = self.buffers.audio(i);
+                        let mut out = ports[i].unwrap_audio_mut();
+                        // Every output fades along the same curve.
+                        let mut mix = self.mix;
+                        for (k, y) in out[offset..offset + count].iter_mut().enumerate() {
+                            let dry = dry.map_or(0.0, |(delay, input)| delay.get(input, k));
+                            *y = if skip { dry } else { wet[k] * mix + dry * (1.0 - mix) };
+                            mix = approach(mix, target, step);
+                        }
+                        output += 1;
+                    }
+                    _ => {}
+                }
+            }
+
+            for (delay, &port) in self.delays.iter_mut().zip(&self.inputs) {
+                delay.push(&self.buffers.audio(port)[..count]);
+            }
+            for _ in 0..count {
+                self.mix = approach(self.mix, target, step);
+            }
+            offset += count;
+        }
+    }
+
+    fn deactivate(&mut self) {
+        if !self.failed {
+            self.plugin.deactivate();
+        }
+    }
+
+    fn latency(&self) -> usize {
+        self.plugin.latency()
+    }
+}
+
+// Moves value one step towards target without overshooting.
+fn approach(value: Data, target: Data, step: Data) -> Data {
+    let next = value + step;
+    if (step > 0.0 && next > target) || (step < 0.0 && next < target) { target } else { next }
+}
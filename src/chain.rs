@@ -0,0 +1,302 @@
+//! Plugin chains described in TOML or JSON files.
+//!
+//! A chain lists the plugins to load, the control values to give them and optionally how their
+//! ports are connected:
+//!
+//! ```toml
+//! [[plugin]]
+//! name = "gain"
+//! library = "amp.so"
+//! label = "amp_mono"
+//! controls = { Gain = 0.5 }
+//!
+//! [[plugin]]
+//! library = "/usr/lib/ladspa/delay.so"
+//! id = 1043
+//! ```
+//!
+//! Plugins are identified by ```label``` or by unique ```id```. Library paths without a directory
+//! are searched for in ```LADSPA_PATH``` and the usual system locations.
+//!
+//! Without any ```[[connect]]``` tables, the plugins are chained in order: the graph inputs feed
+//! the audio inputs of the first plugin, each plugin's audio outputs feed the next plugin's audio
+//! inputs, and the audio outputs of the last plugin become the graph outputs. Explicit routing
+//! names ports as ```node.port```, where ```node``` is a plugin's ```name``` or position and
+//! ```port``` is a port name or index. The graph's own ports are ```input.N``` and ```output.N```:
+//!
+//! ```toml
+//! inputs = 1
+//! outputs = 1
+//!
+//! [[connect]]
+//! from = "input.0"
+//! to = "gain.Input"
+//!
+//! [[connect]]
+//! from = "gain.Output"
+//! to = "output.0"
+//! ```
+
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde_json;
+use toml;
+
+use graph::{self, Graph, NodeId};
+use host::{self, Descriptor, Library};
+use super::{Data, PortDescriptor};
+
+/// The ways loading or building a chain can fail.
+#[derive(Debug)]
+pub enum Error {
+    /// The chain file could not be read or parsed.
+    Parse(String),
+    /// A library could not be loaded.
+    Host(host::Error),
+    /// The plugins could not be connected.
+    Graph(graph::Error),
+    /// No plugin in the library matches the requested label or ID.
+    PluginNotFound(String),
+    /// A control value or connection refers to a port or node that doesn't exist.
+    UnknownPort(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Parse(ref msg) => write!(f, "invalid chain: {}", msg),
+            Error::Host(ref err) => err.fmt(f),
+            Error::Graph(ref err) => err.fmt(f),
+            Error::PluginNotFound(ref msg) => write!(f, "plugin not found: {}", msg),
+            Error::UnknownPort(ref msg) => write!(f, "unknown port: {}", msg),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<host::Error> for Error {
+    fn from(err: host::Error) -> Error {
+        Error::Host(err)
+    }
+}
+
+impl From<graph::Error> for Error {
+    fn from(err: graph::Error) -> Error {
+        Error::Graph(err)
+    }
+}
+
+/// A parsed chain file.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Chain {
+    /// The number of graph inputs. Defaults to the audio inputs of the first plugin.
+    #[serde(default)]
+    pub inputs: Option<usize>,
+
+    /// The number of graph outputs. Defaults to the audio outputs of the last plugin.
+    #[serde(default)]
+    pub outputs: Option<usize>,
+
+    #[serde(default, rename = "plugin")]
+    pub plugins: Vec<ChainPlugin>,
+
+    #[serde(default, rename = "connect")]
+    pub connections: Vec<Connection>,
+}
+
+/// A plugin within a chain.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ChainPlugin {
+    /// Used to refer to this plugin in connections.
+    #[serde(default)]
+    pub name: Option<String>,
+
+    pub library: PathBuf,
+
+    #[serde(default)]
+    pub label: Option<String>,
+
+    #[serde(default)]
+    pub id: Option<u64>,
+
+    /// Values for control inputs, by port name.
+    #[serde(default)]
+    pub controls: BTreeMap<String, Data>,
+}
+
+/// Connects two ports, named as ```node.port```.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Connection {
+    pub from: String,
+    pub to: String,
+}
+
+enum Endpoint {
+    Graph(usize),
+    Node(NodeId, usize),
+}
+
+impl Chain {
+    /// Parses a chain from TOML.
+    pub fn from_toml(text: &str) -> Result<Chain, Error> {
+        toml::from_str(text).map_err(|e| Error::Parse(e.to_string()))
+    }
+
+    /// Parses a chain from JSON.
+    pub fn from_json(text: &str) -> Result<Chain, Error> {
+        serde_json::from_str(text).map_err(|e| Error::Parse(e.to_string()))
+    }
+
+    /// Reads a chain file, which is parsed as JSON if it has a ```.json``` extension and TOML
+    /// otherwise.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Chain, Error> {
+        let path = path.as_ref();
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .map_err(|e| Error::Parse(format!("{}: {}", path.display(), e)))?;
+        if path.extension().map_or(false, |ext| ext == "json") {
+            Chain::from_json(&text)
+        } else {
+            Chain::from_toml(&text)
+        }
+    }
+
+    /// Loads every plugin and connects them into a graph.
+    pub fn build(&self, sample_rate: u64, max_block_size: usize) -> Result<Graph, Error> {
+        let mut libraries: Vec<(PathBuf, Arc<Library>)> = Vec::new();
+        let mut descriptors = Vec::new();
+        for plugin in &self.plugins {
+            let path = host::find_library(&plugin.library);
+            let lib = match libraries.iter().position(|x| x.0 == path) {
+                Some(idx) => libraries[idx].1.clone(),
+                None => {
+                    let lib = Library::open(&path)?;
+                    libraries.push((path.clone(), lib.clone()));
+                    lib
+                }
+            };
+            descriptors.push(plugin.find(&lib)?);
+        }
+
+        let first_inputs = descriptors.first().map_or(0, |d| audio_ports(d, PortDescriptor::AudioInput).len());
+        let last_outputs = descriptors.last().map_or(0, |d| audio_ports(d, PortDescriptor::AudioOutput).len());
+        let mut graph = Graph::new(sample_rate,
+                                   max_block_size,
+                                   self.inputs.unwrap_or(first_inputs),
+                                   self.outputs.unwrap_or(last_outputs));
+
+        let mut nodes = Vec::new();
+        for (plugin, desc) in self.plugins.iter().zip(&descriptors) {
+            let node = graph.add_node(desc)?;
+            for (name, &value) in &plugin.controls {
+                match desc.port_index(name) {
+                    Some(port) if desc.port_descriptor(port) == PortDescriptor::ControlInput => {
+                        graph.set_control(node, port, value);
+                    }
+                    _ => return Err(Error::UnknownPort(format!("{}.{}", desc.label(), name))),
+                }
+            }
+            nodes.push(node);
+        }
+
+        if self.connections.is_empty() {
+            self.connect_serial(&mut graph, &nodes, &descriptors)?;
+        } else {
+            for conn in &self.connections {
+                match (self.endpoint(&graph, &nodes, &conn.from, "input")?,
+                       self.endpoint(&graph, &nodes, &conn.to, "output")?) {
+                    (Endpoint::Graph(input), Endpoint::Node(node, port)) => {
+                        graph.connect_input(input, node, port)?
+                    }
+                    (Endpoint::Node(node, port), Endpoint::Graph(output)) => {
+                        graph.connect_output(node, port, output)?
+                    }
+                    (Endpoint::Node(from, from_port), Endpoint::Node(to, to_port)) => {
+                        graph.connect(from, from_port, to, to_port)?
+                    }
+                    (Endpoint::Graph(_), Endpoint::Graph(_)) => {
+                        return Err(Error::Graph(graph::Error::PortMismatch))
+                    }
+                }
+            }
+        }
+        Ok(graph)
+    }
+
+    fn connect_serial(&self, graph: &mut Graph, nodes: &[NodeId], descriptors: &[Descriptor]) -> Result<(), Error> {
+        if let Some(&first) = nodes.first() {
+            let ports = audio_ports(&descriptors[0], PortDescriptor::AudioInput);
+            for (input, &port) in ports.iter().enumerate().take(graph.inputs()) {
+                graph.connect_input(input, first, port)?;
+            }
+        }
+        for i in 1..nodes.len() {
+            let outputs = audio_ports(&descriptors[i - 1], PortDescriptor::AudioOutput);
+            let inputs = audio_ports(&descriptors[i], PortDescriptor::AudioInput);
+            for (&from, &to) in outputs.iter().zip(&inputs) {
+                graph.connect(nodes[i - 1], from, nodes[i], to)?;
+            }
+        }
+        if let Some(&last) = nodes.last() {
+            let ports = audio_ports(descriptors.last().unwrap(), PortDescriptor::AudioOutput);
+            for (output, &port) in ports.iter().enumerate().take(graph.outputs()) {
+                graph.connect_output(last, port, output)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Resolves a "node.port" reference. The graph's own ports are called `graph_name.N`.
+    fn endpoint(&self, graph: &Graph, nodes: &[NodeId], name: &str, graph_name: &str) -> Result<Endpoint, Error> {
+        let unknown = || Error::UnknownPort(name.to_string());
+        let mut parts = name.splitn(2, '.');
+        let node = parts.next().unwrap_or("");
+        let port = parts.next().ok_or_else(&unknown)?;
+
+        if node == graph_name {
+            return port.parse().map(Endpoint::Graph).map_err(|_| unknown());
+        }
+        let idx = self.plugins.iter()
+            .position(|p| p.name.as_ref().map_or(false, |x| x == node))
+            .or_else(|| node.parse().ok())
+            .filter(|&idx| idx < nodes.len())
+            .ok_or_else(&unknown)?;
+        let desc = graph.node(nodes[idx]).descriptor();
+        let port = desc.port_index(port)
+            .or_else(|| port.parse().ok())
+            .filter(|&port| port < desc.port_count())
+            .ok_or_else(&unknown)?;
+        Ok(Endpoint::Node(nodes[idx], port))
+    }
+}
+
+impl ChainPlugin {
+    // Finds the descriptor in the library matching this plugin's label or unique ID.
+    fn find(&self, lib: &Arc<Library>) -> Result<Descriptor, Error> {
+        if self.label.is_none() && self.id.is_none() {
+            return Err(Error::Parse(format!("{}: plugin needs a label or id", self.library.display())));
+        }
+        Library::descriptors(lib)
+            .into_iter()
+            .find(|desc| {
+                self.label.as_ref().map_or(true, |label| desc.label() == *label) &&
+                self.id.map_or(true, |id| desc.unique_id() == id)
+            })
+            .ok_or_else(|| {
+                let name = self.label.clone().unwrap_or_else(|| self.id.unwrap().to_string());
+                Error::PluginNotFound(format!("{} in {}", name, self.library.display()))
+            })
+    }
+}
+
+fn audio_ports(desc: &Descriptor, kind: PortDescriptor) -> Vec<usize> {
+    (0..desc.port_count()).filter(|&i| desc.port_descriptor(i) == kind).collect()
+}
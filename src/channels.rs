@@ -0,0 +1,158 @@
+//! Building multichannel plugins from a single channel implementation.
+//!
+//! Write a ```PluginDescriptor``` for one channel, then describe the variants to export with
+//! ```Variant```. Each variant gets its own label and unique ID, and creates an instance of the
+//! single channel plugin for every channel. Audio ports and control outputs are repeated for
+//! every channel, named "Left ..." and "Right ..." for stereo and "Channel 1 ..." and so on
+//! for more channels. Control inputs are either shared by every channel or repeated like the
+//! other ports. A ```LATENCY_PORT``` is never repeated, and reports the largest latency of any
+//! channel.
+//!
+//! See ```examples/delay``` for a plugin exported in mono, stereo and linked stereo variants.
+
+use std::borrow::Cow;
+use std::mem;
+use std::sync::Arc;
+
+use diagnostics;
+use super::{Plugin, PluginDescriptor, PluginError, Port, PortConnection, PortDescriptor, LATENCY_PORT};
+
+/// One of the multichannel plugins generated by ```expand```.
+#[derive(Clone, Debug)]
+pub struct Variant {
+    pub unique_id: u64,
+    pub label: Cow<'static, str>,
+    pub name: Cow<'static, str>,
+    pub channels: usize,
+
+    /// Whether every channel shares the same control inputs. Otherwise each channel has its own.
+    pub linked: bool,
+}
+
+/// Returns a descriptor for each of the variants of a single channel plugin. Variants without any
+/// channels are reported through ```diagnostics``` and skipped.
+pub fn expand(desc: PluginDescriptor, variants: &[Variant]) -> Vec<PluginDescriptor> {
+    let inner = Arc::new(desc);
+    variants.iter().filter_map(|variant| {
+        if variant.channels == 0 {
+            diagnostics::report(&format!("{} has no channels, so it is skipped", variant.label));
+            return None;
+        }
+        Some(build(&inner, variant))
+    }).collect()
+}
+
+/// Returns a descriptor for a single variant of a single channel plugin. Panics if the variant
+/// has no channels, which ```expand``` checks for.
+pub fn build(inner: &Arc<PluginDescriptor>, variant: &Variant) -> PluginDescriptor {
+    assert!(variant.channels > 0, "a variant needs at least one channel");
+    let (ports, map) = layout(&inner.ports, variant);
+    let channels = variant.channels;
+    // Factory presets name single channel ports, which only still exist if they're shared.
+    let presets = if channels == 1 || variant.linked { inner.presets.clone() } else { vec![] };
+    let inner = inner.clone();
+    PluginDescriptor {
+        unique_id: variant.unique_id,
+        label: variant.label.clone(),
+        properties: inner.properties,
+        name: variant.name.clone(),
+        maker: inner.maker.clone(),
+        copyright: inner.copyright.clone(),
+        ports: ports,
+        new: Box::new(move |_: &PluginDescriptor, sample_rate: u64| {
+            Ok(Box::new(Multichannel {
+                plugins: (0..channels)
+                    .map(|_| (inner.new)(&inner, sample_rate))
+                    .collect::<Result<_, _>>()?,
+                map: map.clone(),
+                refs: Vec::with_capacity(inner.ports.len()),
+            }) as Box<Plugin + Send>)
+        }),
+        presets: presets,
+    }
+}
+
+/// An instance of a single channel plugin for each channel, created by the descriptors returned
+/// from ```expand```.
+pub struct Multichannel {
+    plugins: Vec<Box<Plugin + Send>>,
+    // For each channel, the outer port connected to each port of the inner plugin.
+    map: Vec<Vec<usize>>,
+    // Storage reused between calls to run so that it never allocates. Always empty between calls.
+    refs: Vec<&'static PortConnection<'static>>,
+}
+
+impl Plugin for Multichannel {
+    fn activate(&mut self) -> Result<(), PluginError> {
+        for i in 0..self.plugins.len() {
+            if let Err(err) = self.plugins[i].activate() {
+                // Only the channels which were activated are deactivated.
+                for plugin in &mut self.plugins[..i] {
+                    plugin.deactivate();
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    fn run<'a>(&mut self, sample_count: usize, ports: &[&'a PortConnection<'a>]) {
+        for (plugin, map) in self.plugins.iter_mut().zip(&self.map) {
+            // The stored vector is empty, so only its allocation is reused here.
+            let mut refs: Vec<&'a PortConnection<'a>> =
+                unsafe { mem::transmute(mem::replace(&mut self.refs, Vec::new())) };
+            refs.extend(map.iter().map(|&port| ports[port]));
+            plugin.run(sample_count, &refs);
+            refs.clear();
+            self.refs = unsafe {
+                mem::transmute::<Vec<&PortConnection>, Vec<&PortConnection<'static>>>(refs)
+            };
+        }
+    }
+
+    fn deactivate(&mut self) {
+        for plugin in &mut self.plugins {
+            plugin.deactivate();
+        }
+    }
+
+    fn latency(&self) -> usize {
+        self.plugins.iter().map(|plugin| plugin.latency()).max().unwrap_or(0)
+    }
+}
+
+// Returns the ports of a variant, and for each channel the outer port for each inner port.
+fn layout(inner: &[Port], variant: &Variant) -> (Vec<Port>, Vec<Vec<usize>>) {
+    let mut ports = Vec::new();
+    let mut map = vec![Vec::with_capacity(inner.len()); variant.channels];
+    for port in inner {
+        let shared = (port.desc == PortDescriptor::ControlInput && variant.linked) ||
+                     (port.desc == PortDescriptor::ControlOutput && port.name == LATENCY_PORT);
+        if shared || variant.channels == 1 {
+            for channel in &mut map {
+                channel.push(ports.len());
+            }
+            ports.push(port.clone());
+            continue;
+        }
+        for (channel, ports_map) in map.iter_mut().enumerate() {
+            ports_map.push(ports.len());
+            ports.push(Port {
+                name: channel_name(channel, variant.channels, &port.name).into(),
+                // Old names are only meaningful for the single channel port.
+                aliases: vec![],
+                ..port.clone()
+            });
+        }
+    }
+    (ports, map)
+}
+
+fn channel_name(channel: usize, channels: usize, name: &str) -> String {
+    let prefix = match (channels, channel) {
+        (2, 0) => "Left".to_string(),
+        (2, 1) => "Right".to_string(),
+        _ => format!("Channel {}", channel + 1),
+    };
+    format!("{} {}", prefix, name)
+}
@@ -0,0 +1,535 @@
+//! Checking that a plugin honours the LADSPA specification.
+//!
+//! ```check``` drives a plugin through the C interface, as a host would, and reports every way
+//! in which it breaks the contract a host relies on:
+//!
+//! * ```activate``` must reset all state, so the output after deactivating and reactivating an
+//!   instance must match that of a new instance.
+//! * The output must depend only on the input and control values, so two new instances given
+//!   the same input must agree.
+//! * ```run``` with a ```sample_count``` of 0 must not write to any audio output, or change the
+//!   output of later runs.
+//! * Unless the plugin has ```PROP_INPLACE_BROKEN```, connecting each audio output to the buffer
+//!   of an audio input must not change the output.
+//! * The descriptor must be well formed: labels without spaces, unique IDs between 1 and
+//!   0xFFFFFF, strings in UTF-8, distinct port names, valid port types, and bounds which contain
+//!   the default and exist wherever the default is defined by them.
+//! * ```HINT_TOGGLED``` ports must have no other hints or bounds and default to 0 or 1, and
+//!   ```HINT_INTEGER``` ports must have integer defaults. The output must stay finite at every
+//!   value such a port can take.
+//!
+//! Outputs are compared exactly. Panics suppressed at the C interface are reported as failures.
+//!
+//! The ```ladspa_conformance!``` macro turns these checks into a test. Given the
+//! ```get_ladspa_descriptor``` function of a plugin library, it checks every plugin the library
+//! provides, and that no two of them share a label or unique ID:
+//!
+//! ```rust,ignore
+//! #[cfg(test)]
+//! ladspa_conformance!(get_ladspa_descriptor);
+//! ```
+//!
+//! A single descriptor can be checked by a test with a given name:
+//!
+//! ```rust,ignore
+//! ladspa_conformance!(delay_conforms, delay_descriptor());
+//! ```
+//!
+//! This module and the macro need the ```testing``` feature, which a plugin library can enable
+//! for its tests alone by listing ```ladspa``` again under ```[dev-dependencies]``` with it.
+
+use std::collections::HashSet;
+use std::error;
+use std::ffi::CStr;
+use std::fmt;
+
+use libc::c_char;
+
+use ffi::{self, ladspa_h};
+use host::{Descriptor, Instance};
+use super::{Data, PluginDescriptor, PortDescriptor, HINT_INTEGER, HINT_LOGARITHMIC,
+            HINT_SAMPLE_RATE, HINT_TOGGLED, PROP_INPLACE_BROKEN};
+
+/// Generates a test checking plugins with ```conformance::check```.
+///
+/// ```ladspa_conformance!(get_ladspa_descriptor)``` generates a test named
+/// ```ladspa_conformance``` which checks every descriptor returned by the given function.
+/// ```ladspa_conformance!(name, descriptor)``` generates a test named ```name``` which checks a
+/// single ```PluginDescriptor```.
+#[macro_export]
+macro_rules! ladspa_conformance {
+    ($name:ident, $descriptor:expr) => {
+        #[test]
+        fn $name() {
+            $crate::conformance::assert_conforms($descriptor);
+        }
+    };
+    ($get_descriptor:path) => {
+        #[test]
+        fn ladspa_conformance() {
+            $crate::conformance::assert_library_conforms($get_descriptor);
+        }
+    };
+}
+
+/// The sample rate plugins are checked at.
+pub const SAMPLE_RATE: u64 = 48000;
+
+// The block sizes of each rendering, covering single samples, uneven sizes and the largest size.
+const BLOCKS: [usize; 5] = [1, 64, 256, 17, 200];
+const MAX_BLOCK_SIZE: usize = 256;
+
+// The largest number of values of an integer port tried.
+const MAX_INTEGER_VALUES: usize = 64;
+
+// Seeds for the input signal, the second being used to put instances into a different state.
+const SEED: u32 = 0x1234_5678;
+const OTHER_SEED: u32 = 0x9abc_def1;
+
+/// A way in which a plugin breaks the specification. Each variant except ```Instantiate```
+/// describes what went wrong.
+#[derive(Debug)]
+pub enum Error {
+    /// The plugin panicked.
+    Panic(String),
+
+    /// The plugin couldn't be instantiated.
+    Instantiate,
+
+    /// The output after reactivating an instance differs from that of a new instance.
+    NotReset(String),
+
+    /// The output differs between two new instances given the same input.
+    Nondeterministic(String),
+
+    /// Running 0 samples wrote to an output or changed the output of later runs.
+    ZeroSamples(String),
+
+    /// The output differs when processing in place.
+    InPlace(String),
+
+    /// The descriptor is malformed.
+    Descriptor(String),
+
+    /// A port's hints don't make sense together.
+    Hint(String),
+
+    /// The output isn't finite at a value of a toggled or integer port.
+    NotFinite(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Panic(ref msg) => write!(f, "{}", msg),
+            Error::Instantiate => write!(f, "failed to instantiate"),
+            Error::NotReset(ref msg) => {
+                write!(f, "output after reactivating differs from a new instance: {}", msg)
+            }
+            Error::Nondeterministic(ref msg) => {
+                write!(f, "output differs between identical instances: {}", msg)
+            }
+            Error::ZeroSamples(ref msg) => write!(f, "running 0 samples {}", msg),
+            Error::InPlace(ref msg) => write!(f, "output differs when processing in place: {}", msg),
+            Error::Descriptor(ref msg) => write!(f, "{}", msg),
+            Error::Hint(ref msg) => write!(f, "{}", msg),
+            Error::NotFinite(ref msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// Checks a plugin against the specification, returning every failure found, starting with those
+/// found by ```check_descriptor```.
+pub fn check(desc: &Descriptor) -> Vec<Error> {
+    let mut errors = check_descriptor(desc);
+    // Running a plugin whose descriptor can't be read would only crash.
+    if !readable(desc) {
+        return errors;
+    }
+    errors.extend([deterministic, resets, zero_samples, in_place]
+        .iter()
+        .filter_map(|check| check(desc).err()));
+    errors.extend(hints(desc));
+    errors
+}
+
+/// Checks a plugin's descriptor without running it.
+pub fn check_descriptor(desc: &Descriptor) -> Vec<Error> {
+    let mut errors = Vec::new();
+    let raw = desc.as_raw();
+    for &(field, ptr) in &[("label", raw.label), ("name", raw.name), ("maker", raw.maker), ("copyright", raw.copyright)] {
+        if let Err(msg) = string(ptr) {
+            errors.push(Error::Descriptor(format!("{} {}", field, msg)));
+        }
+    }
+    if !readable(desc) {
+        if desc.port_count() > 0 {
+            errors.push(Error::Descriptor("port arrays are null".to_string()));
+        }
+        return errors;
+    }
+    let label = desc.label();
+    if label.is_empty() || label.chars().any(char::is_whitespace) {
+        errors.push(Error::Descriptor(format!("label \"{}\" is empty or contains spaces", label)));
+    }
+    if desc.unique_id() == 0 || desc.unique_id() > 0xFF_FFFF {
+        errors.push(Error::Descriptor(format!("unique ID {} is not between 1 and 0xFFFFFF", desc.unique_id())));
+    }
+
+    let mut names = HashSet::new();
+    for port in 0..desc.port_count() {
+        let name = match string(unsafe { *raw.port_names.add(port) }) {
+            Ok(name) => name,
+            Err(msg) => {
+                errors.push(Error::Descriptor(format!("name of port {} {}", port, msg)));
+                continue;
+            }
+        };
+        if name.is_empty() {
+            errors.push(Error::Descriptor(format!("port {} has an empty name", port)));
+        } else if !names.insert(name.clone()) {
+            errors.push(Error::Descriptor(format!("more than one port is named {}", name)));
+        }
+        if desc.port_descriptor(port) == PortDescriptor::Invalid {
+            errors.push(Error::Descriptor(format!("{} is not exactly one of audio or control and input or output", name)));
+        }
+        if let Some(msg) = bounds(desc, port) {
+            errors.push(Error::Hint(format!("{} {}", name, msg)));
+        }
+    }
+    errors
+}
+
+/// Checks that the plugins in a library don't share labels or unique IDs.
+pub fn check_library(descs: &[Descriptor]) -> Vec<Error> {
+    let mut errors = Vec::new();
+    let (mut labels, mut ids) = (HashSet::new(), HashSet::new());
+    for desc in descs.iter().filter(|desc| readable(desc)) {
+        if !labels.insert(desc.label()) {
+            errors.push(Error::Descriptor(format!("label {} is used by more than one plugin", desc.label())));
+        }
+        if !ids.insert(desc.unique_id()) {
+            errors.push(Error::Descriptor(format!("unique ID {} is used by more than one plugin", desc.unique_id())));
+        }
+    }
+    errors
+}
+
+/// Checks a native plugin, panicking with a list of the failures if there are any.
+pub fn assert_conforms(desc: PluginDescriptor) {
+    let desc = Descriptor::from_native(desc);
+    fail_on(&desc.label(), check(&desc));
+}
+
+/// Checks every plugin returned by a library's ```get_ladspa_descriptor``` function.
+pub fn assert_library_conforms<F: Fn(u64) -> Option<PluginDescriptor>>(get_descriptor: F) {
+    let mut descs = Vec::new();
+    while let Some(desc) = get_descriptor(descs.len() as u64) {
+        descs.push(Descriptor::from_native(desc));
+    }
+    for desc in &descs {
+        fail_on(&desc.label(), check(desc));
+    }
+    fail_on("the library", check_library(&descs));
+}
+
+fn fail_on(what: &str, errors: Vec<Error>) {
+    if !errors.is_empty() {
+        let list: Vec<String> = errors.iter().map(|err| format!("\n  {}", err)).collect();
+        panic!("{} does not conform to the LADSPA specification:{}", what, list.concat());
+    }
+}
+
+fn deterministic(desc: &Descriptor) -> Result<(), Error> {
+    let first = fresh_output(desc)?;
+    let second = fresh_output(desc)?;
+    compare(desc, &first, &second).map_err(Error::Nondeterministic)
+}
+
+fn resets(desc: &Descriptor) -> Result<(), Error> {
+    let mut instance = instantiate(desc)?;
+    // Put the plugin into a different state, with different input and controls at their lower
+    // bounds, so that anything activate fails to reset shows up.
+    let defaults: Vec<Data> = (0..desc.port_count()).map(|i| instance.control(i)).collect();
+    for port in ports(desc, PortDescriptor::ControlInput) {
+        if let (Some(lower), _) = desc.port_bounds(port, SAMPLE_RATE) {
+            instance.set_control(port, lower);
+        }
+    }
+    activate(&mut instance)?;
+    render(&mut instance, OTHER_SEED)?;
+    deactivate(&mut instance)?;
+    for port in ports(desc, PortDescriptor::ControlInput) {
+        instance.set_control(port, defaults[port]);
+    }
+    activate(&mut instance)?;
+    let output = render(&mut instance, SEED)?;
+    close(instance)?;
+    compare(desc, &fresh_output(desc)?, &output).map_err(Error::NotReset)
+}
+
+fn zero_samples(desc: &Descriptor) -> Result<(), Error> {
+    const SENTINEL: Data = 12345.0;
+    let mut instance = instantiate(desc)?;
+    activate(&mut instance)?;
+    for port in ports(desc, PortDescriptor::AudioOutput) {
+        for x in instance.audio_mut(port) {
+            *x = SENTINEL;
+        }
+    }
+    instance.run(0);
+    suppressed()?;
+    for port in ports(desc, PortDescriptor::AudioOutput) {
+        if instance.audio(port).iter().any(|&x| x != SENTINEL) {
+            return Err(Error::ZeroSamples(format!("wrote to {}", desc.port_name(port))));
+        }
+    }
+    let output = render(&mut instance, SEED)?;
+    close(instance)?;
+    compare(desc, &fresh_output(desc)?, &output)
+        .map_err(|msg| Error::ZeroSamples(format!("changed later output: {}", msg)))
+}
+
+fn in_place(desc: &Descriptor) -> Result<(), Error> {
+    if desc.properties().contains(PROP_INPLACE_BROKEN) {
+        return Ok(());
+    }
+    let inputs = ports(desc, PortDescriptor::AudioInput);
+    let outputs = ports(desc, PortDescriptor::AudioOutput);
+    if inputs.is_empty() || outputs.is_empty() {
+        return Ok(());
+    }
+    let mut instance = instantiate(desc)?;
+    // Each output shares the buffer of the input at the same position among the inputs.
+    for (&output, &input) in outputs.iter().zip(&inputs) {
+        instance.connect_in_place(output, input);
+        suppressed()?;
+    }
+    activate(&mut instance)?;
+    let output = render(&mut instance, SEED)?;
+    close(instance)?;
+    compare(desc, &fresh_output(desc)?, &output).map_err(Error::InPlace)
+}
+
+fn hints(desc: &Descriptor) -> Vec<Error> {
+    let mut errors = Vec::new();
+    for port in ports(desc, PortDescriptor::ControlInput) {
+        let name = desc.port_name(port);
+        let hint = desc.port_hint(port);
+        let (lower, upper) = desc.port_bounds(port, SAMPLE_RATE);
+        let default = desc.port_default(port, SAMPLE_RATE);
+        let values: Vec<Data> = if hint.contains(HINT_TOGGLED) {
+            if hint.intersects(HINT_LOGARITHMIC | HINT_INTEGER | HINT_SAMPLE_RATE) {
+                errors.push(Error::Hint(format!("{} is toggled but has other hints", name)));
+            }
+            if lower.is_some() || upper.is_some() {
+                errors.push(Error::Hint(format!("{} is toggled but has bounds", name)));
+            }
+            if let Some(value) = default.filter(|&x| x != 0.0 && x != 1.0) {
+                errors.push(Error::Hint(format!("{} is toggled but defaults to {}", name, value)));
+            }
+            vec![0.0, 1.0]
+        } else if hint.contains(HINT_INTEGER) {
+            if let Some(value) = default.filter(|x| x.fract() != 0.0) {
+                errors.push(Error::Hint(format!("{} is an integer but defaults to {}", name, value)));
+            }
+            match (lower, upper) {
+                (Some(lower), Some(upper)) if lower.ceil() <= upper.floor() => {
+                    let (lower, upper) = (lower.ceil() as f64, upper.floor() as f64);
+                    let steps = ((upper - lower) as usize).clamp(1, MAX_INTEGER_VALUES - 1);
+                    let mut values: Vec<Data> = (0..steps + 1)
+                        .map(|k| (lower + ((upper - lower) * k as f64 / steps as f64).round()) as Data)
+                        .collect();
+                    values.dedup();
+                    values
+                }
+                _ => vec![],
+            }
+        } else {
+            vec![]
+        };
+        for value in values {
+            if let Err(err) = finite_at(desc, port, value) {
+                errors.push(err);
+                break;
+            }
+        }
+    }
+    errors
+}
+
+// Describes what's wrong with the bounds and default of a port, if anything.
+fn bounds(desc: &Descriptor, port: usize) -> Option<String> {
+    let hint = unsafe { *desc.as_raw().port_range_hints.add(port) };
+    let bits = hint.hint_descriptor;
+    let (lower, upper) = desc.port_bounds(port, SAMPLE_RATE);
+    if let (Some(lower), Some(upper)) = (lower, upper) {
+        if lower > upper {
+            return Some(format!("has a lower bound of {} above its upper bound of {}", lower, upper));
+        }
+    }
+    let default = bits & ladspa_h::HINT_DEFAULT_MASK;
+    let (needs_lower, needs_upper) = match default {
+        ladspa_h::HINT_DEFAULT_NONE | ladspa_h::HINT_DEFAULT_0 | ladspa_h::HINT_DEFAULT_1 |
+        ladspa_h::HINT_DEFAULT_100 | ladspa_h::HINT_DEFAULT_440 => (false, false),
+        ladspa_h::HINT_DEFAULT_MINIMUM => (true, false),
+        ladspa_h::HINT_DEFAULT_MAXIMUM => (false, true),
+        ladspa_h::HINT_DEFAULT_LOW | ladspa_h::HINT_DEFAULT_MIDDLE | ladspa_h::HINT_DEFAULT_HIGH => (true, true),
+        _ => return Some(format!("has an unknown default hint {:#x}", default)),
+    };
+    if (needs_lower && lower.is_none()) || (needs_upper && upper.is_none()) {
+        return Some("has a default defined by bounds it doesn't have".to_string());
+    }
+    if needs_lower && needs_upper && bits & ladspa_h::HINT_LOGARITHMIC != 0 &&
+       (lower.unwrap() <= 0.0 || upper.unwrap() <= 0.0) {
+        return Some("is logarithmic with a default between bounds which aren't positive".to_string());
+    }
+    match desc.port_default(port, SAMPLE_RATE) {
+        Some(value) if lower.is_some_and(|x| value < x) || upper.is_some_and(|x| value > x) => {
+            Some(format!("defaults to {}, outside its bounds", value))
+        }
+        _ => None,
+    }
+}
+
+// Reads a string from a descriptor, describing what's wrong with it if it can't.
+fn string(ptr: *const c_char) -> Result<String, String> {
+    if ptr.is_null() {
+        return Err("is null".to_string());
+    }
+    unsafe { CStr::from_ptr(ptr) }.to_str().map(|s| s.to_string()).map_err(|_| "is not valid UTF-8".to_string())
+}
+
+/// Whether the strings and port arrays of a descriptor can be read without crashing. Methods such
+/// as ```Descriptor::label``` must not be called on descriptors for which this is false.
+pub fn readable(desc: &Descriptor) -> bool {
+    let raw = desc.as_raw();
+    let strings = [raw.label, raw.name, raw.maker, raw.copyright].iter().all(|ptr| !ptr.is_null());
+    let ports = desc.port_count() == 0 ||
+        !(raw.port_descriptors.is_null() || raw.port_names.is_null() || raw.port_range_hints.is_null());
+    strings && ports
+}
+
+// Checks that the output is finite with a control input set to a value.
+fn finite_at(desc: &Descriptor, port: usize, value: Data) -> Result<(), Error> {
+    let mut instance = instantiate(desc)?;
+    instance.set_control(port, value);
+    activate(&mut instance)?;
+    let output = render(&mut instance, SEED)?;
+    close(instance)?;
+    for (output, values) in output {
+        if let Some(k) = values.iter().position(|x| !x.is_finite()) {
+            return Err(Error::NotFinite(format!("{} is {} at {} when {} is {}",
+                                                desc.port_name(output),
+                                                values[k],
+                                                position(desc, output, k),
+                                                desc.port_name(port),
+                                                value)));
+        }
+    }
+    Ok(())
+}
+
+// The values written to each output port during a rendering: every sample of audio outputs, and
+// the value after each run of control outputs.
+type Output = Vec<(usize, Vec<Data>)>;
+
+// Renders a new instance with its controls at their defaults.
+fn fresh_output(desc: &Descriptor) -> Result<Output, Error> {
+    let mut instance = instantiate(desc)?;
+    activate(&mut instance)?;
+    let output = render(&mut instance, SEED)?;
+    close(instance)?;
+    Ok(output)
+}
+
+// Runs an active instance on noise from the given seed, in blocks of varying size.
+fn render(instance: &mut Instance, seed: u32) -> Result<Output, Error> {
+    let desc = instance.descriptor().clone();
+    let inputs = ports(&desc, PortDescriptor::AudioInput);
+    let mut output: Output = (0..desc.port_count())
+        .filter(|&i| desc.port_descriptor(i).is_output())
+        .map(|i| (i, Vec::new()))
+        .collect();
+    let mut state = seed;
+    for &n in &BLOCKS {
+        for &port in &inputs {
+            for x in &mut instance.audio_mut(port)[..n] {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                *x = (state as Data / u32::MAX as Data - 0.5) * 1.5;
+            }
+        }
+        instance.run(n);
+        suppressed()?;
+        for &mut (port, ref mut values) in &mut output {
+            if desc.port_descriptor(port).is_audio() {
+                values.extend_from_slice(&instance.audio(port)[..n]);
+            } else {
+                values.push(instance.control(port));
+            }
+        }
+    }
+    Ok(output)
+}
+
+// Describes the first difference between two renderings, if there is one.
+fn compare(desc: &Descriptor, expected: &Output, actual: &Output) -> Result<(), String> {
+    for (&(port, ref expected), (_, actual)) in expected.iter().zip(actual) {
+        let same = |(x, y): (&Data, &Data)| x == y || (x.is_nan() && y.is_nan());
+        if let Some(k) = expected.iter().zip(actual.iter()).position(|pair| !same(pair)) {
+            return Err(format!("{} is {} instead of {} at {}",
+                               desc.port_name(port),
+                               actual[k],
+                               expected[k],
+                               position(desc, port, k)));
+        }
+    }
+    Ok(())
+}
+
+// Describes where in a rendering a value of an output port was written.
+fn position(desc: &Descriptor, port: usize, k: usize) -> String {
+    if desc.port_descriptor(port).is_audio() {
+        format!("sample {}", k)
+    } else {
+        format!("run {}", k + 1)
+    }
+}
+
+fn ports(desc: &Descriptor, kind: PortDescriptor) -> Vec<usize> {
+    (0..desc.port_count()).filter(|&i| desc.port_descriptor(i) == kind).collect()
+}
+
+fn instantiate(desc: &Descriptor) -> Result<Instance, Error> {
+    let instance = Instance::new(desc, SAMPLE_RATE, MAX_BLOCK_SIZE);
+    suppressed()?;
+    instance.map_err(|_| Error::Instantiate)
+}
+
+fn activate(instance: &mut Instance) -> Result<(), Error> {
+    instance.activate();
+    suppressed()
+}
+
+fn deactivate(instance: &mut Instance) -> Result<(), Error> {
+    instance.deactivate();
+    suppressed()
+}
+
+// Deactivates and cleans up an instance.
+fn close(instance: Instance) -> Result<(), Error> {
+    drop(instance);
+    suppressed()
+}
+
+// Fails if the plugin panicked during the last call.
+fn suppressed() -> Result<(), Error> {
+    match ffi::take_suppressed_panic() {
+        Some(msg) => Err(Error::Panic(msg)),
+        None => Ok(()),
+    }
+}
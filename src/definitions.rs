@@ -0,0 +1,383 @@
+//! Plugin variants defined in a TOML file installed next to the library.
+//!
+//! The processing code of a plugin is written in Rust as a ```Template```: a function returning a
+//! ```PluginDescriptor``` with sensible ranges and defaults. A TOML file then lists the plugins
+//! the library actually exports, each built from a template with its own label and unique ID, and
+//! optionally a different channel count, different port ranges and defaults, and extra presets:
+//!
+//! ```toml
+//! [[plugin]]
+//! template = "delay"
+//! unique_id = 410
+//! label = "short_stereo_delay"
+//! name = "Short Stereo Delay"
+//! channels = 2
+//! linked = true
+//!
+//! [plugin.ports."Delay (seconds)"]
+//! upper_bound = 0.5
+//! default = "middle"
+//!
+//! [[plugin.preset]]
+//! name = "Slapback"
+//! values = { "Delay (seconds)" = 0.12, "Dry/Wet" = 0.3 }
+//! ```
+//!
+//! Channels are expanded as in the ```channels``` module, so ports and presets are always named
+//! as in the template. A ```default``` is one of "minimum", "low", "middle", "high", "maximum",
+//! "0", "1", "100" or "440", as in ```DefaultValue```.
+//!
+//! ```descriptor``` reads the file with the same name as the library but a ```.toml``` extension,
+//! so ```libdelay.so``` is configured by ```libdelay.toml``` in the same directory. It can be
+//! called from ```get_ladspa_descriptor```, after any plugins the library always exports:
+//!
+//! ```rust,ignore
+//! const TEMPLATES: [Template; 1] = [Template { name: "delay", build: delay }];
+//!
+//! #[no_mangle]
+//! pub fn get_ladspa_descriptor(index: u64) -> Option<PluginDescriptor> {
+//!     definitions::descriptor(&TEMPLATES, index)
+//! }
+//! ```
+//!
+//! A mistake in the file must not take down the host that loaded the library, so definitions
+//! which fail to validate are reported through ```diagnostics``` and skipped, and the rest are
+//! still exported.
+
+use std::collections::{BTreeMap, HashSet};
+use std::error;
+use std::ffi::CStr;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use libc::{self, c_void};
+use toml;
+
+use channels::{self, Variant};
+use diagnostics;
+use super::{Data, DefaultValue, FactoryPreset, PluginDescriptor, PortDescriptor};
+
+/// The ways loading a definitions file can fail.
+#[derive(Debug)]
+pub enum Error {
+    /// The file could not be read or parsed.
+    Parse(String),
+    /// A definition names a template which the library doesn't provide.
+    UnknownTemplate(String),
+    /// A port override or preset names a port which the template doesn't have.
+    UnknownPort(String),
+    /// A definition is inconsistent, or clashes with another definition.
+    Invalid(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Parse(ref msg) => write!(f, "invalid definitions: {}", msg),
+            Error::UnknownTemplate(ref msg) => write!(f, "unknown template: {}", msg),
+            Error::UnknownPort(ref msg) => write!(f, "unknown port: {}", msg),
+            Error::Invalid(ref msg) => write!(f, "invalid plugin definition: {}", msg),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// A plugin implemented in Rust, which definitions can build variants of.
+pub struct Template {
+    /// The name definitions refer to the template by.
+    pub name: &'static str,
+    /// Returns the descriptor which definitions start from.
+    pub build: fn() -> PluginDescriptor,
+}
+
+/// A parsed definitions file.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Definitions {
+    #[serde(default, rename = "plugin")]
+    pub plugins: Vec<Definition>,
+}
+
+/// A plugin built from a template.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Definition {
+    pub template: String,
+    pub unique_id: u64,
+    pub label: String,
+
+    /// Defaults to the name of the template's plugin, as do ```maker``` and ```copyright```.
+    #[serde(default)]
+    pub name: Option<String>,
+
+    #[serde(default)]
+    pub maker: Option<String>,
+
+    #[serde(default)]
+    pub copyright: Option<String>,
+
+    /// The number of channels, as in ```channels::Variant```. Defaults to 1, which exports the
+    /// template's ports unchanged.
+    #[serde(default)]
+    pub channels: Option<usize>,
+
+    /// Whether every channel shares the same control inputs.
+    #[serde(default)]
+    pub linked: bool,
+
+    /// Changes to the template's control ports, by port name.
+    #[serde(default)]
+    pub ports: BTreeMap<String, PortDefinition>,
+
+    /// Presets added to the template's own.
+    #[serde(default, rename = "preset")]
+    pub presets: Vec<PresetDefinition>,
+}
+
+/// Changes to the range and default of a control port.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PortDefinition {
+    #[serde(default)]
+    pub lower_bound: Option<Data>,
+
+    #[serde(default)]
+    pub upper_bound: Option<Data>,
+
+    #[serde(default)]
+    pub default: Option<String>,
+}
+
+/// A preset added by a definition.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PresetDefinition {
+    pub name: String,
+
+    /// Control input values, by port name.
+    pub values: BTreeMap<String, Data>,
+}
+
+impl Definitions {
+    /// Parses definitions from TOML.
+    pub fn from_toml(text: &str) -> Result<Definitions, Error> {
+        toml::from_str(text).map_err(|e| Error::Parse(e.to_string()))
+    }
+
+    /// Reads a definitions file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Definitions, Error> {
+        let path = path.as_ref();
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .map_err(|e| Error::Parse(format!("{}: {}", path.display(), e)))?;
+        Definitions::from_toml(&text)
+    }
+
+    /// Builds every definition, in order. Definitions which reuse the label or unique ID of an
+    /// earlier one fail, as hosts identify plugins by them.
+    pub fn build(&self, templates: &[Template]) -> Vec<Result<PluginDescriptor, Error>> {
+        let mut labels = HashSet::new();
+        let mut ids = HashSet::new();
+        self.plugins.iter().map(|def| {
+            let desc = def.build(templates)?;
+            if !labels.insert(def.label.clone()) {
+                return Err(Error::Invalid(format!("{}: label is used by an earlier plugin", def.label)));
+            }
+            if !ids.insert(def.unique_id) {
+                return Err(Error::Invalid(format!("{}: unique_id {} is used by an earlier plugin",
+                                                  def.label,
+                                                  def.unique_id)));
+            }
+            Ok(desc)
+        }).collect()
+    }
+}
+
+impl Definition {
+    /// Builds the plugin from its template.
+    pub fn build(&self, templates: &[Template]) -> Result<PluginDescriptor, Error> {
+        let invalid = |msg: &str| Error::Invalid(format!("{}: {}", self.label, msg));
+        if self.label.is_empty() || self.label.contains(char::is_whitespace) {
+            return Err(invalid("labels must not be empty or contain spaces"));
+        }
+        let strings = [Some(&self.label), self.name.as_ref(), self.maker.as_ref(),
+                       self.copyright.as_ref()];
+        let presets = self.presets.iter().map(|preset| &preset.name);
+        if strings.iter().flatten().cloned().chain(presets).any(|s| s.contains('\0')) {
+            return Err(invalid("the label, name, maker, copyright and preset names must not \
+                                contain NUL"));
+        }
+        let template = templates.iter()
+            .find(|t| t.name == self.template)
+            .ok_or_else(|| Error::UnknownTemplate(format!("{} in {}", self.template, self.label)))?;
+        let mut desc = (template.build)();
+
+        for (name, def) in &self.ports {
+            let port = desc.ports.iter_mut()
+                .find(|p| p.name == **name && p.desc.is_control())
+                .ok_or_else(|| Error::UnknownPort(format!("{}.{}", self.label, name)))?;
+            if def.lower_bound.is_some() {
+                port.lower_bound = def.lower_bound;
+            }
+            if def.upper_bound.is_some() {
+                port.upper_bound = def.upper_bound;
+            }
+            if let Some(ref default) = def.default {
+                port.default = Some(parse_default(default)
+                    .ok_or_else(|| invalid(&format!("{} is not a default value for {}", default, name)))?);
+            }
+            if let (Some(lower), Some(upper)) = (port.lower_bound, port.upper_bound) {
+                if lower > upper {
+                    return Err(invalid(&format!("{} has a lower bound above its upper bound", name)));
+                }
+            }
+            let needs_bounds = match port.default {
+                Some(DefaultValue::Minimum) => (true, false),
+                Some(DefaultValue::Maximum) => (false, true),
+                Some(DefaultValue::Low) | Some(DefaultValue::Middle) | Some(DefaultValue::High) => (true, true),
+                _ => (false, false),
+            };
+            if (needs_bounds.0 && port.lower_bound.is_none()) || (needs_bounds.1 && port.upper_bound.is_none()) {
+                return Err(invalid(&format!("{} needs bounds for its default value", name)));
+            }
+        }
+
+        for preset in &self.presets {
+            for (name, &value) in &preset.values {
+                let port = desc.ports.iter()
+                    .find(|p| p.name == **name && p.desc == PortDescriptor::ControlInput)
+                    .ok_or_else(|| Error::UnknownPort(format!("{}.{}", self.label, name)))?;
+                if port.lower_bound.is_some_and(|lower| value < lower) ||
+                   port.upper_bound.is_some_and(|upper| value > upper) {
+                    return Err(invalid(&format!("preset \"{}\" sets {} out of range", preset.name, name)));
+                }
+            }
+            desc.presets.push(FactoryPreset {
+                name: preset.name.clone().into(),
+                values: preset.values.iter().map(|(name, &value)| (name.clone().into(), value)).collect(),
+            });
+        }
+
+        desc.unique_id = self.unique_id;
+        desc.label = self.label.clone().into();
+        if let Some(ref name) = self.name {
+            desc.name = name.clone().into();
+        }
+        if let Some(ref maker) = self.maker {
+            desc.maker = maker.clone().into();
+        }
+        if let Some(ref copyright) = self.copyright {
+            desc.copyright = copyright.clone().into();
+        }
+
+        let channels = self.channels.unwrap_or(1);
+        if channels == 0 {
+            return Err(invalid("a plugin needs at least one channel"));
+        }
+        if channels == 1 && !self.linked {
+            return Ok(desc);
+        }
+        if !self.linked && !self.presets.is_empty() {
+            return Err(invalid("presets need linked controls when there is more than one channel"));
+        }
+        let variant = Variant {
+            unique_id: desc.unique_id,
+            label: desc.label.clone(),
+            name: desc.name.clone(),
+            channels: channels,
+            linked: self.linked,
+        };
+        Ok(channels::build(&Arc::new(desc), &variant))
+    }
+}
+
+fn parse_default(name: &str) -> Option<DefaultValue> {
+    Some(match name {
+        "minimum" => DefaultValue::Minimum,
+        "low" => DefaultValue::Low,
+        "middle" => DefaultValue::Middle,
+        "high" => DefaultValue::High,
+        "maximum" => DefaultValue::Maximum,
+        "0" => DefaultValue::Value0,
+        "1" => DefaultValue::Value1,
+        "100" => DefaultValue::Value100,
+        "440" => DefaultValue::Value440,
+        _ => return None,
+    })
+}
+
+/// Returns the path of the shared library this crate is linked into, as found by ```dladdr```.
+pub fn library_path() -> Option<PathBuf> {
+    unsafe {
+        let mut info: libc::Dl_info = mem::zeroed();
+        if libc::dladdr(library_path as *const c_void, &mut info) == 0 || info.dli_fname.is_null() {
+            return None;
+        }
+        Some(PathBuf::from(CStr::from_ptr(info.dli_fname).to_string_lossy().into_owned()))
+    }
+}
+
+/// Returns the path of the definitions file for the shared library this crate is linked into.
+pub fn definitions_path() -> Option<PathBuf> {
+    library_path().map(|path| path.with_extension("toml"))
+}
+
+// The definitions which built successfully with a set of templates, identified by their names and
+// build functions.
+type Validated = (Vec<(&'static str, usize)>, Vec<Definition>);
+
+// Filled in the first time ```descriptor``` is called with each set of templates.
+static VALID: Mutex<Vec<Validated>> = Mutex::new(Vec::new());
+
+/// Returns the plugin built from the definition at ```index``` in the library's definitions
+/// file, or ```None``` once there are no more. The file is read once for each set of templates.
+/// If it can't be read, nothing is exported, and any definitions which fail to build are
+/// skipped. Either way the problem is reported through ```diagnostics```.
+pub fn descriptor(templates: &[Template], index: u64) -> Option<PluginDescriptor> {
+    let key: Vec<_> = templates.iter().map(|t| (t.name, t.build as usize)).collect();
+    let def = {
+        let mut valid = VALID.lock().unwrap_or_else(|err| err.into_inner());
+        let pos = match valid.iter().position(|entry| entry.0 == key) {
+            Some(pos) => pos,
+            None => {
+                valid.push((key, validate(templates)));
+                valid.len() - 1
+            }
+        };
+        valid[pos].1.get(index as usize).cloned()
+    };
+    def.and_then(|def| def.build(templates).ok())
+}
+
+// Loads the library's definitions file, returning the definitions which build.
+fn validate(templates: &[Template]) -> Vec<Definition> {
+    let path = match definitions_path() {
+        Some(path) => path,
+        None => {
+            diagnostics::report("could not find the path of the plugin library");
+            return vec![];
+        }
+    };
+    let defs = match Definitions::load(&path) {
+        Ok(defs) => defs,
+        Err(err) => {
+            diagnostics::report(&err.to_string());
+            return vec![];
+        }
+    };
+    defs.build(templates).into_iter().zip(defs.plugins.iter()).filter_map(|(result, def)| {
+        match result {
+            Ok(_) => Some(def.clone()),
+            Err(err) => {
+                diagnostics::report(&format!("{}: {}", path.display(), err));
+                None
+            }
+        }
+    }).collect()
+}
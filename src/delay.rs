@@ -0,0 +1,71 @@
+// A delay line aligning the dry signal of a wrapped plugin with its processed output, used by the
+// bypass and mix adapters.
+
+use std::cmp;
+
+use super::Data;
+
+// Its length follows the latency of the inner plugin, and it only allocates on activation.
+pub struct Delay {
+    buf: Vec<Data>,
+    len: usize,
+    pos: usize,
+}
+
+impl Delay {
+    pub fn new() -> Delay {
+        Delay {
+            buf: Vec::new(),
+            len: 0,
+            pos: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for x in &mut self.buf {
+            *x = 0.0;
+        }
+    }
+
+    // Sets the length, allocating if it's longer than any so far. Called on activation, when the
+    // plugin isn't expected to run in real time.
+    pub fn reserve(&mut self, len: usize) {
+        if len > self.buf.len() {
+            // Make room for the extra delay, keeping the samples already stored in order.
+            let mut buf = vec![0.0; len];
+            for i in 0..self.buf.len() {
+                buf[len - self.buf.len() + i] = self.buf[(self.pos + i) % self.buf.len()];
+            }
+            self.buf = buf;
+            self.pos = 0;
+        }
+        self.len = len;
+    }
+
+    // Follows the latency while running, without allocating. A latency which grows past the one
+    // reserved is only partly made up for until the next activation.
+    pub fn set_len(&mut self, len: usize) {
+        self.len = cmp::min(len, self.buf.len());
+    }
+
+    // Returns the sample len samples before position k of the current block.
+    pub fn get(&self, block: &[Data], k: usize) -> Data {
+        if k >= self.len {
+            return block[k - self.len];
+        }
+        let size = self.buf.len();
+        self.buf[(self.pos + size - self.len + k) % size]
+    }
+
+    // Stores the current block, once every output has read from it.
+    pub fn push(&mut self, block: &[Data]) {
+        let size = self.buf.len();
+        for &x in block {
+            if size == 0 {
+                return;
+            }
+            self.buf[self.pos] = x;
+            self.pos = (self.pos + 1) % size;
+        }
+    }
+}
@@ -0,0 +1,42 @@
+//! Where problems noticed at the C interface are reported.
+//!
+//! Plugins can't return errors to their host, so plugins which fail to instantiate or activate,
+//! panics suppressed at the C interface, hosts which break the LADSPA specification and traces
+//! which can't be written are reported here instead. By default each message is printed to
+//! standard error, prefixed with ```ladspa.rs: ```. A plugin library can send them elsewhere,
+//! such as a log file, by installing a hook when its descriptors are first requested:
+//!
+//! ```rust,ignore
+//! ladspa::diagnostics::set_hook(Box::new(|msg: &str| log::warn!("{}", msg)));
+//! ```
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::RwLock;
+
+/// A function which is given every message.
+pub type Hook = Box<Fn(&str) + Send + Sync>;
+
+static HOOK: RwLock<Option<Hook>> = RwLock::new(None);
+
+/// Sends every message to ```hook``` instead of standard error. The hook may be called from
+/// the host's audio thread, so it should return quickly.
+pub fn set_hook(hook: Hook) {
+    *HOOK.write().unwrap_or_else(|err| err.into_inner()) = Some(hook);
+}
+
+/// Goes back to printing messages to standard error.
+pub fn clear_hook() {
+    *HOOK.write().unwrap_or_else(|err| err.into_inner()) = None;
+}
+
+/// Reports a message through the hook, or to standard error if there is none. A hook which
+/// panics is ignored, as a panic can't be allowed to reach the host.
+pub fn report(msg: &str) {
+    let hook = HOOK.read().unwrap_or_else(|err| err.into_inner());
+    match *hook {
+        Some(ref hook) => {
+            let _ = catch_unwind(AssertUnwindSafe(|| hook(msg)));
+        }
+        None => eprintln!("ladspa.rs: {}", msg),
+    }
+}
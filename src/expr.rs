@@ -0,0 +1,847 @@
+//! Plugins whose processing is written as a short program of math expressions.
+//!
+//! A program declares its inputs and controls, then assigns expressions to its outputs, one
+//! statement per line or separated by ```;```:
+//!
+//! ```text
+//! # A ring modulator.
+//! input in
+//! control f = 440 [20, 20000] log
+//! out = in * sin(2 * pi * f * t)
+//! ```
+//!
+//! Every ```input``` becomes an audio input and every ```control``` a control input, with an
+//! optional default value, range and any of the hints ```log```, ```integer``` and
+//! ```toggled```. LADSPA can only describe a few default values, so others are rounded to the
+//! nearest one it can. Any other name assigned to becomes an audio output, unless it is declared
+//! with ```let``` as a temporary. Ports appear in the order their names are first declared or
+//! assigned.
+//!
+//! Expressions are evaluated once per sample in double precision, with the usual arithmetic
+//! operators, ```%```, ```^``` for powers, and comparisons which give 1 or 0. ```t``` is the
+//! time in seconds since the plugin was activated, ```sr``` is the sample rate, and ```pi``` and
+//! ```e``` are the constants. The functions are ```sin```, ```cos```, ```tan```, ```asin```,
+//! ```acos```, ```atan```, ```atan2```, ```sinh```, ```cosh```, ```tanh```, ```exp```, ```log```
+//! (natural), ```log2```, ```log10```, ```sqrt```, ```abs```, ```floor```, ```ceil```,
+//! ```round```, ```min```, ```max```, ```pow```, ```clamp(x, low, high)``` and
+//! ```if(condition, then, else)```.
+//!
+//! Reading an output or temporary before it is assigned gives its value from the previous
+//! sample, or 0 just after activation, so ```y = y + 0.01 * (in - y)``` is a simple lowpass
+//! filter.
+//!
+//! Programs are compiled to bytecode for a small stack machine, which runs without allocating.
+//! Expressions nested more than 256 deep, counting both parentheses and operators, are rejected.
+
+use std::borrow::Cow;
+use std::error;
+use std::f64::consts;
+use std::fmt;
+use std::sync::Arc;
+
+use super::{Data, DefaultValue, Plugin, PluginDescriptor, PluginError, Port, PortConnection,
+            PortDescriptor, HINT_INTEGER, HINT_LOGARITHMIC, HINT_TOGGLED, PROP_HARD_REALTIME_CAPABLE};
+
+/// The ways compiling a program can fail. Each holds the line number and a description.
+#[derive(Debug)]
+pub enum Error {
+    /// The program is not well formed.
+    Syntax(usize, String),
+    /// A name is used without being declared, or declared more than once.
+    Name(usize, String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Syntax(line, ref msg) => write!(f, "syntax error on line {}: {}", line, msg),
+            Error::Name(line, ref msg) => write!(f, "error on line {}: {}", line, msg),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// Compiles ```source``` into a plugin with ports derived from its declarations.
+pub fn descriptor(unique_id: u64,
+                  label: Cow<'static, str>,
+                  name: Cow<'static, str>,
+                  maker: Cow<'static, str>,
+                  copyright: Cow<'static, str>,
+                  source: &str)
+                  -> Result<PluginDescriptor, Error> {
+    let program = Arc::new(Program::compile(source)?);
+    Ok(PluginDescriptor {
+        unique_id: unique_id,
+        label: label,
+        properties: PROP_HARD_REALTIME_CAPABLE,
+        name: name,
+        maker: maker,
+        copyright: copyright,
+        ports: program.ports.clone(),
+        new: Box::new(move |_: &PluginDescriptor, sample_rate: u64| {
+            Ok(Box::new(Expression::new(program.clone(), sample_rate)) as Box<Plugin + Send>)
+        }),
+        presets: vec![],
+    })
+}
+
+/// A compiled program.
+#[derive(Clone)]
+pub struct Program {
+    ports: Vec<Port>,
+    // The register each port is read from or written to.
+    bindings: Vec<usize>,
+    registers: usize,
+    code: Vec<Op>,
+    stack_size: usize,
+}
+
+// The registers holding the time and sample rate. Declared names follow.
+const TIME: usize = 0;
+const SAMPLE_RATE: usize = 1;
+const FIRST_NAME: usize = 2;
+
+impl Program {
+    /// Parses and compiles a program.
+    pub fn compile(source: &str) -> Result<Program, Error> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens: tokens,
+            pos: 0,
+            names: Vec::new(),
+            depth: 0,
+        };
+        let mut assignments = Vec::new();
+        while parser.pos < parser.tokens.len() {
+            if let Some(assignment) = parser.statement()? {
+                assignments.push(assignment);
+            }
+        }
+
+        let mut code = Vec::new();
+        for (register, expr) in &assignments {
+            parser.emit(expr, &mut code)?;
+            code.push(Op::Store(*register));
+        }
+        if !parser.names.iter().any(|name| name.kind == Kind::Output) {
+            let line = parser.tokens.last().map_or(1, |token| token.1);
+            return Err(Error::Name(line, "the program has no outputs".to_string()));
+        }
+
+        let mut ports = Vec::new();
+        let mut bindings = Vec::new();
+        for (idx, name) in parser.names.iter().enumerate() {
+            let desc = match name.kind {
+                Kind::Input => PortDescriptor::AudioInput,
+                Kind::Output => PortDescriptor::AudioOutput,
+                Kind::Control => PortDescriptor::ControlInput,
+                Kind::Local => continue,
+            };
+            ports.push(Port {
+                name: name.name.clone().into(),
+                desc: desc,
+                ..name.port.clone()
+            });
+            bindings.push(FIRST_NAME + idx);
+        }
+        Ok(Program {
+            ports: ports,
+            bindings: bindings,
+            registers: FIRST_NAME + parser.names.len(),
+            stack_size: stack_size(&code),
+            code: code,
+        })
+    }
+
+    /// The ports of the plugin, in order.
+    pub fn ports(&self) -> &[Port] {
+        &self.ports
+    }
+}
+
+/// The plugin created by the descriptors returned from ```descriptor```.
+pub struct Expression {
+    program: Arc<Program>,
+    registers: Vec<f64>,
+    stack: Vec<f64>,
+    // The number of samples processed since activation.
+    time: u64,
+}
+
+impl Expression {
+    /// Creates an instance of a compiled program.
+    pub fn new(program: Arc<Program>, sample_rate: u64) -> Expression {
+        let mut registers = vec![0.0; program.registers];
+        registers[SAMPLE_RATE] = sample_rate as f64;
+        Expression {
+            stack: vec![0.0; program.stack_size],
+            registers: registers,
+            program: program,
+            time: 0,
+        }
+    }
+}
+
+impl Plugin for Expression {
+    fn activate(&mut self) -> Result<(), PluginError> {
+        for x in &mut self.registers[FIRST_NAME..] {
+            *x = 0.0;
+        }
+        self.time = 0;
+        Ok(())
+    }
+
+    fn run<'a>(&mut self, sample_count: usize, ports: &[&'a PortConnection<'a>]) {
+        let program = &*self.program;
+        for (port, &register) in ports.iter().zip(&program.bindings) {
+            if port.port.desc == PortDescriptor::ControlInput {
+                self.registers[register] = *port.unwrap_control() as f64;
+            }
+        }
+        for k in 0..sample_count {
+            self.registers[TIME] = (self.time + k as u64) as f64 / self.registers[SAMPLE_RATE];
+            for (port, &register) in ports.iter().zip(&program.bindings) {
+                if port.port.desc == PortDescriptor::AudioInput {
+                    self.registers[register] = port.unwrap_audio()[k] as f64;
+                }
+            }
+            execute(&program.code, &mut self.registers, &mut self.stack);
+            for (port, &register) in ports.iter().zip(&program.bindings) {
+                if port.port.desc == PortDescriptor::AudioOutput {
+                    port.unwrap_audio_mut()[k] = self.registers[register] as Data;
+                }
+            }
+        }
+        self.time += sample_count as u64;
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Op {
+    Const(f64),
+    Load(usize),
+    // Pops the top of the stack into a register.
+    Store(usize),
+    Negate,
+    Binary(BinOp),
+    Call1(fn(f64) -> f64),
+    Call2(fn(f64, f64) -> f64),
+    Clamp,
+    Select,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+}
+
+impl BinOp {
+    fn apply(self, a: f64, b: f64) -> f64 {
+        let truth = |x: bool| if x { 1.0 } else { 0.0 };
+        match self {
+            BinOp::Add => a + b,
+            BinOp::Sub => a - b,
+            BinOp::Mul => a * b,
+            BinOp::Div => a / b,
+            BinOp::Rem => a % b,
+            BinOp::Pow => a.powf(b),
+            BinOp::Less => truth(a < b),
+            BinOp::Greater => truth(a > b),
+            BinOp::LessEqual => truth(a <= b),
+            BinOp::GreaterEqual => truth(a >= b),
+            BinOp::Equal => truth(a == b),
+            BinOp::NotEqual => truth(a != b),
+        }
+    }
+}
+
+fn execute(code: &[Op], registers: &mut [f64], stack: &mut [f64]) {
+    let mut sp = 0;
+    for op in code {
+        match *op {
+            Op::Const(x) => {
+                stack[sp] = x;
+                sp += 1;
+            }
+            Op::Load(register) => {
+                stack[sp] = registers[register];
+                sp += 1;
+            }
+            Op::Store(register) => {
+                sp -= 1;
+                registers[register] = stack[sp];
+            }
+            Op::Negate => stack[sp - 1] = -stack[sp - 1],
+            Op::Binary(op) => {
+                sp -= 1;
+                stack[sp - 1] = op.apply(stack[sp - 1], stack[sp]);
+            }
+            Op::Call1(f) => stack[sp - 1] = f(stack[sp - 1]),
+            Op::Call2(f) => {
+                sp -= 1;
+                stack[sp - 1] = f(stack[sp - 1], stack[sp]);
+            }
+            Op::Clamp => {
+                sp -= 2;
+                stack[sp - 1] = stack[sp - 1].max(stack[sp]).min(stack[sp + 1]);
+            }
+            Op::Select => {
+                sp -= 2;
+                stack[sp - 1] = if stack[sp - 1] != 0.0 { stack[sp] } else { stack[sp + 1] };
+            }
+        }
+    }
+}
+
+// Returns the deepest the stack gets while running the code.
+fn stack_size(code: &[Op]) -> usize {
+    let mut depth: isize = 0;
+    let mut max = 0;
+    for op in code {
+        depth += match *op {
+            Op::Const(_) | Op::Load(_) => 1,
+            Op::Negate | Op::Call1(_) => 0,
+            Op::Store(_) | Op::Binary(_) | Op::Call2(_) => -1,
+            Op::Clamp | Op::Select => -2,
+        };
+        max = max.max(depth);
+    }
+    max as usize
+}
+
+// Returns the operation for a function, and how many arguments it takes.
+fn function(name: &str) -> Option<(Op, usize)> {
+    Some(match name {
+        "sin" => (Op::Call1(f64::sin), 1),
+        "cos" => (Op::Call1(f64::cos), 1),
+        "tan" => (Op::Call1(f64::tan), 1),
+        "asin" => (Op::Call1(f64::asin), 1),
+        "acos" => (Op::Call1(f64::acos), 1),
+        "atan" => (Op::Call1(f64::atan), 1),
+        "sinh" => (Op::Call1(f64::sinh), 1),
+        "cosh" => (Op::Call1(f64::cosh), 1),
+        "tanh" => (Op::Call1(f64::tanh), 1),
+        "exp" => (Op::Call1(f64::exp), 1),
+        "log" => (Op::Call1(f64::ln), 1),
+        "log2" => (Op::Call1(f64::log2), 1),
+        "log10" => (Op::Call1(f64::log10), 1),
+        "sqrt" => (Op::Call1(f64::sqrt), 1),
+        "abs" => (Op::Call1(f64::abs), 1),
+        "floor" => (Op::Call1(f64::floor), 1),
+        "ceil" => (Op::Call1(f64::ceil), 1),
+        "round" => (Op::Call1(f64::round), 1),
+        "atan2" => (Op::Call2(f64::atan2), 2),
+        "min" => (Op::Call2(f64::min), 2),
+        "max" => (Op::Call2(f64::max), 2),
+        "pow" => (Op::Binary(BinOp::Pow), 2),
+        "clamp" => (Op::Clamp, 3),
+        "if" => (Op::Select, 3),
+        _ => return None,
+    })
+}
+
+// Names which can't be declared.
+fn is_reserved(name: &str) -> bool {
+    matches!(name, "input" | "control" | "let" | "t" | "sr" | "pi" | "e") || function(name).is_some()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Name(String),
+    Symbol(&'static str),
+    // The end of a statement.
+    End,
+}
+
+// Two character symbols come first so that they aren't split.
+const SYMBOLS: [&str; 19] = ["<=", ">=", "==", "!=", "+", "-", "*", "/", "%", "^", "(", ")", ",", "=",
+                             "[", "]", "<", ">", ";"];
+
+// Splits the source into tokens, each with its line number.
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, Error> {
+    let mut tokens = Vec::new();
+    for (idx, line) in source.lines().enumerate() {
+        let line_no = idx + 1;
+        let mut rest = line.split('#').next().unwrap_or("");
+        loop {
+            rest = rest.trim_start();
+            let c = match rest.chars().next() {
+                Some(c) => c,
+                None => break,
+            };
+            let len;
+            if c.is_ascii_digit() || c == '.' {
+                len = number_len(rest);
+                let value = rest[..len].parse()
+                    .map_err(|_| Error::Syntax(line_no, format!("invalid number {}", &rest[..len])))?;
+                tokens.push((Token::Number(value), line_no));
+            } else if c.is_alphabetic() || c == '_' {
+                len = rest.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(rest.len());
+                tokens.push((Token::Name(rest[..len].to_string()), line_no));
+            } else if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+                len = symbol.len();
+                tokens.push((if *symbol == ";" { Token::End } else { Token::Symbol(symbol) }, line_no));
+            } else {
+                return Err(Error::Syntax(line_no, format!("unexpected character {}", c)));
+            }
+            rest = &rest[len..];
+        }
+        tokens.push((Token::End, line_no));
+    }
+    Ok(tokens)
+}
+
+// Returns the length of the number at the start of s, including any exponent.
+fn number_len(s: &str) -> usize {
+    let bytes = s.as_bytes();
+    let digits = |mut i: usize, dot: bool| {
+        while i < bytes.len() && (bytes[i].is_ascii_digit() || (dot && bytes[i] == b'.')) {
+            i += 1;
+        }
+        i
+    };
+    let mut len = digits(0, true);
+    if len < bytes.len() && (bytes[len] == b'e' || bytes[len] == b'E') {
+        let mut exp = len + 1;
+        if exp < bytes.len() && (bytes[exp] == b'+' || bytes[exp] == b'-') {
+            exp += 1;
+        }
+        if exp < bytes.len() && bytes[exp].is_ascii_digit() {
+            len = digits(exp, false);
+        }
+    }
+    len
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Kind {
+    Input,
+    Control,
+    Output,
+    Local,
+}
+
+struct Name {
+    name: String,
+    kind: Kind,
+    // The range, default and hints of a control.
+    port: Port,
+}
+
+// Calls, negations and binary operations end with the height of their tree, which is kept to
+// MAX_DEPTH so that compiling or dropping it can't overflow the stack.
+enum Expr {
+    Number(f64),
+    Name(String, usize),
+    Call(String, Vec<Expr>, usize, usize),
+    Negate(Box<Expr>, usize),
+    Binary(BinOp, Box<Expr>, Box<Expr>, usize),
+}
+
+impl Expr {
+    fn height(&self) -> usize {
+        match *self {
+            Expr::Number(_) | Expr::Name(..) => 1,
+            Expr::Call(_, _, _, height) |
+            Expr::Negate(_, height) |
+            Expr::Binary(_, _, _, height) => height,
+        }
+    }
+}
+
+// How deeply expressions may nest, counting both parentheses and the operations in their tree.
+const MAX_DEPTH: usize = 256;
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    // Every declared or assigned name, in order. Each is stored in the register at its index
+    // plus FIRST_NAME.
+    names: Vec<Name>,
+    // How many expressions the one being parsed is nested in.
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        self.tokens.get(self.pos).map_or(&Token::End, |token| &token.0)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens.get(self.pos).or_else(|| self.tokens.last()).map_or(1, |token| token.1)
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.peek().clone();
+        self.pos += 1;
+        token
+    }
+
+    fn error<T>(&self, msg: &str) -> Result<T, Error> {
+        let found = match *self.peek() {
+            Token::Number(x) => x.to_string(),
+            Token::Name(ref name) => name.clone(),
+            Token::Symbol(symbol) => symbol.to_string(),
+            Token::End => "end of statement".to_string(),
+        };
+        Err(Error::Syntax(self.line(), format!("expected {}, found {}", msg, found)))
+    }
+
+    fn symbol(&mut self, symbol: &'static str) -> bool {
+        if *self.peek() == Token::Symbol(symbol) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: &'static str) -> Result<(), Error> {
+        if self.symbol(symbol) { Ok(()) } else { self.error(&format!("\"{}\"", symbol)) }
+    }
+
+    fn name(&mut self) -> Result<String, Error> {
+        match self.next() {
+            Token::Name(name) => Ok(name),
+            _ => {
+                self.pos -= 1;
+                self.error("a name")
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<f64, Error> {
+        let negative = self.symbol("-");
+        match self.next() {
+            // Ranges and defaults are stored as Data, where this would be infinite.
+            Token::Number(x) if !(x as Data).is_finite() => {
+                self.pos -= 1;
+                Err(Error::Syntax(self.line(), format!("{} is out of range", x)))
+            }
+            Token::Number(x) => Ok(if negative { -x } else { x }),
+            _ => {
+                self.pos -= 1;
+                self.error("a number")
+            }
+        }
+    }
+
+    fn find(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|x| x.name == name)
+    }
+
+    fn declare(&mut self, name: String, kind: Kind, port: Port) -> Result<usize, Error> {
+        if is_reserved(&name) {
+            return Err(Error::Name(self.line(), format!("{} is a reserved name", name)));
+        }
+        if self.find(&name).is_some() {
+            return Err(Error::Name(self.line(), format!("{} is already declared", name)));
+        }
+        self.names.push(Name {
+            name: name,
+            kind: kind,
+            port: port,
+        });
+        Ok(FIRST_NAME + self.names.len() - 1)
+    }
+
+    // Parses a statement, returning the register and expression of an assignment.
+    fn statement(&mut self) -> Result<Option<(usize, Expr)>, Error> {
+        let result = match self.next() {
+            Token::End => return Ok(None),
+            Token::Name(ref keyword) if keyword == "input" => {
+                loop {
+                    let name = self.name()?;
+                    self.declare(name, Kind::Input, Port::default())?;
+                    if !self.symbol(",") {
+                        break;
+                    }
+                }
+                None
+            }
+            Token::Name(ref keyword) if keyword == "control" => {
+                let name = self.name()?;
+                let port = self.control(&name)?;
+                self.declare(name, Kind::Control, port)?;
+                None
+            }
+            Token::Name(ref keyword) if keyword == "let" => {
+                let name = self.name()?;
+                let register = self.declare(name, Kind::Local, Port::default())?;
+                self.expect("=")?;
+                Some((register, self.expr()?))
+            }
+            Token::Name(name) => {
+                let register = match self.find(&name) {
+                    Some(idx) if matches!(self.names[idx].kind, Kind::Input | Kind::Control) => {
+                        return Err(Error::Name(self.line(), format!("{} is an input", name)));
+                    }
+                    Some(idx) => FIRST_NAME + idx,
+                    None => self.declare(name, Kind::Output, Port::default())?,
+                };
+                self.expect("=")?;
+                Some((register, self.expr()?))
+            }
+            _ => {
+                self.pos -= 1;
+                return self.error("a statement");
+            }
+        };
+        if self.next() != Token::End {
+            self.pos -= 1;
+            return self.error("end of statement");
+        }
+        Ok(result)
+    }
+
+    // Parses the default value, range and hints of a control.
+    fn control(&mut self, name: &str) -> Result<Port, Error> {
+        let mut port = Port::default();
+        let default = if self.symbol("=") { Some(self.number()? as Data) } else { None };
+        if self.symbol("[") {
+            port.lower_bound = Some(self.number()? as Data);
+            self.expect(",")?;
+            port.upper_bound = Some(self.number()? as Data);
+            self.expect("]")?;
+        }
+        let mut hint = None;
+        while let Token::Name(flag) = self.peek().clone() {
+            let flag = match &*flag {
+                "log" => HINT_LOGARITHMIC,
+                "integer" => HINT_INTEGER,
+                "toggled" => HINT_TOGGLED,
+                _ => return self.error("log, integer or toggled"),
+            };
+            hint = Some(hint.map_or(flag, |hint| hint | flag));
+            self.pos += 1;
+        }
+        port.hint = hint;
+
+        let line = self.line();
+        if let (Some(lower), Some(upper)) = (port.lower_bound, port.upper_bound) {
+            if lower > upper {
+                return Err(Error::Syntax(line, format!("{} has a lower bound above its upper bound", name)));
+            }
+            if default.is_some_and(|x| x < lower || x > upper) {
+                return Err(Error::Syntax(line, format!("the default of {} is out of range", name)));
+            }
+        }
+        if let Some(value) = default {
+            port.default = Some(default_value(value, &port).ok_or_else(|| {
+                Error::Syntax(line, format!("{} needs a range for its default to be {}", name, value))
+            })?);
+        }
+        Ok(port)
+    }
+
+    fn too_deep<T>(&self) -> Result<T, Error> {
+        Err(Error::Syntax(self.line(), "the expression is nested too deeply".to_string()))
+    }
+
+    // Parses with the given function one level deeper.
+    fn nested(&mut self, parse: fn(&mut Parser) -> Result<Expr, Error>) -> Result<Expr, Error> {
+        if self.depth == MAX_DEPTH {
+            return self.too_deep();
+        }
+        self.depth += 1;
+        let expr = parse(self);
+        self.depth -= 1;
+        expr
+    }
+
+    // Builds an expression from its operands, unless its tree would be too tall.
+    fn node<F: FnOnce(usize) -> Expr>(&self, height: usize, build: F) -> Result<Expr, Error> {
+        if height >= MAX_DEPTH {
+            return self.too_deep();
+        }
+        Ok(build(height + 1))
+    }
+
+    fn binary(&self, op: BinOp, lhs: Expr, rhs: Expr) -> Result<Expr, Error> {
+        let height = lhs.height().max(rhs.height());
+        self.node(height, |height| Expr::Binary(op, Box::new(lhs), Box::new(rhs), height))
+    }
+
+    fn expr(&mut self) -> Result<Expr, Error> {
+        self.nested(Parser::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, Error> {
+        let lhs = self.sum()?;
+        let op = match *self.peek() {
+            Token::Symbol("<") => BinOp::Less,
+            Token::Symbol(">") => BinOp::Greater,
+            Token::Symbol("<=") => BinOp::LessEqual,
+            Token::Symbol(">=") => BinOp::GreaterEqual,
+            Token::Symbol("==") => BinOp::Equal,
+            Token::Symbol("!=") => BinOp::NotEqual,
+            _ => return Ok(lhs),
+        };
+        self.pos += 1;
+        let rhs = self.sum()?;
+        self.binary(op, lhs, rhs)
+    }
+
+    fn sum(&mut self) -> Result<Expr, Error> {
+        let mut lhs = self.product()?;
+        loop {
+            let op = match *self.peek() {
+                Token::Symbol("+") => BinOp::Add,
+                Token::Symbol("-") => BinOp::Sub,
+                _ => return Ok(lhs),
+            };
+            self.pos += 1;
+            let rhs = self.product()?;
+            lhs = self.binary(op, lhs, rhs)?;
+        }
+    }
+
+    fn product(&mut self) -> Result<Expr, Error> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = match *self.peek() {
+                Token::Symbol("*") => BinOp::Mul,
+                Token::Symbol("/") => BinOp::Div,
+                Token::Symbol("%") => BinOp::Rem,
+                _ => return Ok(lhs),
+            };
+            self.pos += 1;
+            let rhs = self.unary()?;
+            lhs = self.binary(op, lhs, rhs)?;
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, Error> {
+        if self.symbol("-") {
+            let expr = self.nested(Parser::unary)?;
+            return self.node(expr.height(), |height| Expr::Negate(Box::new(expr), height));
+        }
+        let base = self.atom()?;
+        if self.symbol("^") {
+            // Powers are right associative, and bind more tightly than negation on their left.
+            let exponent = self.nested(Parser::unary)?;
+            return self.binary(BinOp::Pow, base, exponent);
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<Expr, Error> {
+        let line = self.line();
+        match self.next() {
+            Token::Number(x) => Ok(Expr::Number(x)),
+            Token::Name(name) => {
+                if !self.symbol("(") {
+                    return Ok(Expr::Name(name, line));
+                }
+                let mut args = Vec::new();
+                if !self.symbol(")") {
+                    loop {
+                        args.push(self.expr()?);
+                        if self.symbol(")") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                let height = args.iter().map(Expr::height).max().unwrap_or(0);
+                self.node(height, |height| Expr::Call(name, args, line, height))
+            }
+            Token::Symbol("(") => {
+                let expr = self.expr()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            _ => {
+                self.pos -= 1;
+                self.error("an expression")
+            }
+        }
+    }
+
+    // Compiles an expression, leaving its value on the stack.
+    fn emit(&self, expr: &Expr, code: &mut Vec<Op>) -> Result<(), Error> {
+        match *expr {
+            Expr::Number(x) => code.push(Op::Const(x)),
+            Expr::Name(ref name, line) => {
+                code.push(match &**name {
+                    "t" => Op::Load(TIME),
+                    "sr" => Op::Load(SAMPLE_RATE),
+                    "pi" => Op::Const(consts::PI),
+                    "e" => Op::Const(consts::E),
+                    _ => match self.find(name) {
+                        Some(idx) => Op::Load(FIRST_NAME + idx),
+                        None => return Err(Error::Name(line, format!("unknown name {}", name))),
+                    },
+                });
+            }
+            Expr::Call(ref name, ref args, line, _) => {
+                let (op, arity) = function(name)
+                    .ok_or_else(|| Error::Name(line, format!("unknown function {}", name)))?;
+                if args.len() != arity {
+                    let plural = if arity == 1 { "" } else { "s" };
+                    return Err(Error::Syntax(line, format!("{} takes {} argument{}", name, arity, plural)));
+                }
+                for arg in args {
+                    self.emit(arg, code)?;
+                }
+                code.push(op);
+            }
+            Expr::Negate(ref expr, _) => {
+                self.emit(expr, code)?;
+                code.push(Op::Negate);
+            }
+            Expr::Binary(op, ref lhs, ref rhs, _) => {
+                self.emit(lhs, code)?;
+                self.emit(rhs, code)?;
+                code.push(Op::Binary(op));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Returns the LADSPA default closest to value, given the port's range and hints.
+fn default_value(value: Data, port: &Port) -> Option<DefaultValue> {
+    if port.lower_bound == Some(value) {
+        return Some(DefaultValue::Minimum);
+    }
+    if port.upper_bound == Some(value) {
+        return Some(DefaultValue::Maximum);
+    }
+    match value {
+        0.0 => return Some(DefaultValue::Value0),
+        1.0 => return Some(DefaultValue::Value1),
+        100.0 => return Some(DefaultValue::Value100),
+        440.0 => return Some(DefaultValue::Value440),
+        _ => {}
+    }
+    let (lower, upper) = match (port.lower_bound, port.upper_bound) {
+        (Some(lower), Some(upper)) => (lower, upper),
+        _ => return None,
+    };
+    let log = port.hint.is_some_and(|hint| hint.contains(HINT_LOGARITHMIC)) && lower > 0.0;
+    let at = |amount: Data| {
+        if log {
+            (lower.ln() * (1.0 - amount) + upper.ln() * amount).exp()
+        } else {
+            lower * (1.0 - amount) + upper * amount
+        }
+    };
+    let candidates = [(DefaultValue::Low, at(0.25)), (DefaultValue::Middle, at(0.5)), (DefaultValue::High, at(0.75))];
+    candidates.iter()
+        .min_by(|a, b| (a.1 - value).abs().total_cmp(&(b.1 - value).abs()))
+        .map(|candidate| candidate.0)
+}
@@ -1,26 +1,50 @@
-use std::{mem, ptr};
-use libc::{self, c_char, c_ulong};
+use std::any::Any;
+use std::ptr;
+use libc::{c_char, c_ulong, c_void};
 use std::slice;
 use std::cell::RefCell;
 use vec_map::VecMap;
 use std::ffi::CString;
 use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::Once;
 
 use super::PluginDescriptor;
 use super::get_ladspa_descriptor;
+use diagnostics;
+use lifecycle::{self, State};
+use trace::Recorder;
 
 macro_rules! call_user_code {
     ($code:expr, $name:expr) => {
         match catch_unwind(move || $code) {
             Ok(x) => x,
-            Err(_) => {
-                println!("ladspa.rs: panic in {} suppressed.", $name);
+            Err(payload) => {
+                record_panic($name, &*payload);
                 None
             }
         }
     }
 }
 
+thread_local! {
+    // The last panic suppressed on this thread, kept for test and fuzzing harnesses.
+    static SUPPRESSED: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+fn record_panic(name: &str, payload: &(Any + Send)) {
+    let msg = payload.downcast_ref::<&str>().map(|msg| msg.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string());
+    diagnostics::report(&format!("panic in {} suppressed: {}", name, msg));
+    SUPPRESSED.with(|last| *last.borrow_mut() = Some(format!("panic in {}: {}", name, msg)));
+}
+
+// Returns and forgets the last panic suppressed on this thread, so that harnesses driving plugins
+// through the C interface can treat it as a failure.
+pub fn take_suppressed_panic() -> Option<String> {
+    SUPPRESSED.with(|last| last.borrow_mut().take())
+}
+
 // essentially ladspa.h API translated to rust.
 pub mod ladspa_h {
     use libc::{c_void, c_char, c_int, c_ulong, c_float};
@@ -79,6 +103,8 @@ pub mod ladspa_h {
     pub const HINT_SAMPLE_RATE: PortRangeHintDescriptor = 0x8;
     pub const HINT_LOGARITHMIC: PortRangeHintDescriptor = 0x10;
     pub const HINT_INTEGER: PortRangeHintDescriptor = 0x20;
+    pub const HINT_DEFAULT_MASK: PortRangeHintDescriptor = 0x3C0;
+    pub const HINT_DEFAULT_NONE: PortRangeHintDescriptor = 0x0;
     pub const HINT_DEFAULT_MINIMUM: PortRangeHintDescriptor = 0x40;
     pub const HINT_DEFAULT_LOW: PortRangeHintDescriptor = 0x80;
     pub const HINT_DEFAULT_MIDDLE: PortRangeHintDescriptor = 0xC0;
@@ -90,8 +116,11 @@ pub mod ladspa_h {
     pub const HINT_DEFAULT_440: PortRangeHintDescriptor = 0x2C0;
 }
 
-static mut DESCRIPTORS: *mut Vec<*mut ladspa_h::Descriptor> =
-    0 as *mut Vec<*mut ladspa_h::Descriptor>;
+// The descriptors of every plugin in the library, built the first time any of them is requested
+// so that each index always refers to the same plugin, whichever order hosts ask in. Only written
+// inside DESCRIPTORS_INIT, which also makes the table visible to every thread that waits on it.
+static DESCRIPTORS_INIT: Once = Once::new();
+static mut DESCRIPTORS: *mut Vec<*mut ladspa_h::Descriptor> = ptr::null_mut();
 
 // It seems that ladspa_descriptor is deleted during link time optimization unless we
 // call it from somewhere.
@@ -103,96 +132,129 @@ unsafe fn _lto_workaround() {
 #[no_mangle]
 // Exported so the plugin is recognised by ladspa hosts.
 pub unsafe extern "C" fn ladspa_descriptor(index: c_ulong) -> *mut ladspa_h::Descriptor {
-    if DESCRIPTORS == ptr::null_mut() {
-        libc::atexit(global_destruct);
-        DESCRIPTORS = mem::transmute(Box::new(Vec::<*mut ladspa_h::Descriptor>::new()));
-    }
+    DESCRIPTORS_INIT.call_once(|| {
+        let mut descriptors = Vec::new();
+        // Plugins are numbered from zero, and the first index without one ends the list.
+        loop {
+            let index = descriptors.len() as u64;
+            match call_user_code!(get_ladspa_descriptor(index), "get_ladspa_descriptor") {
+                Some(plugin) => descriptors.push(build_descriptor(plugin)),
+                None => break,
+            }
+        }
+        DESCRIPTORS = Box::into_raw(Box::new(descriptors));
+    });
 
-    // If it's already been generated, return the cached copy.
-    if (index as usize) < (*DESCRIPTORS).len() {
-        return mem::transmute(&*(*DESCRIPTORS)[index as usize]);
+    // The table is gone once global_destruct has run, but a host may still ask for it while
+    // the process exits.
+    if DESCRIPTORS.is_null() {
+        return ptr::null_mut();
     }
-
-    let descriptor = call_user_code!(get_ladspa_descriptor(index as u64), "get_ladspa_descriptor");
-
-    match descriptor {
-        Some(plugin) => {
-            let desc = mem::transmute(Box::new(ladspa_h::Descriptor {
-                unique_id: plugin.unique_id as c_ulong,
-                label: CString::new(plugin.label).unwrap().into_raw(),
-                properties: plugin.properties.bits(),
-                name: CString::new(plugin.name).unwrap().into_raw(),
-                maker: CString::new(plugin.maker).unwrap().into_raw(),
-                copyright: CString::new(plugin.copyright).unwrap().into_raw(),
-
-                port_count: plugin.ports.len() as c_ulong,
-                port_descriptors: mem::transmute::<_, &mut [i32]>(
-                    plugin.ports.iter().map(|port|
-                                            port.desc as i32
-                                           ).collect::<Vec<_>>().into_boxed_slice()).as_mut_ptr(),
-                port_names: mem::transmute::<_, &mut [*mut c_char]>(
-                    plugin.ports.iter().map(|port|
-                                            CString::new(port.name).unwrap().into_raw()
-                                           ).collect::<Vec<_>>().into_boxed_slice()).as_mut_ptr(),
-                port_range_hints: mem::transmute::<_, &mut [ladspa_h::PortRangeHint]>(
-                    plugin.ports.iter().map(|port|
-                                            ladspa_h::PortRangeHint {
-                                                hint_descriptor: port.hint.map(|x| x.bits()).unwrap_or(0) |
-                                                    port.default.map(|x| x as i32).unwrap_or(0) |
-                                                    port.lower_bound.map(|_| ladspa_h::HINT_BOUNDED_BELOW)
-                                                    .unwrap_or(0) |
-                                                    port.upper_bound.map(|_| ladspa_h::HINT_BOUNDED_ABOVE)
-                                                    .unwrap_or(0),
-                                                 lower_bound: port.lower_bound.unwrap_or(0_f32),
-                                                 upper_bound: port.upper_bound.unwrap_or(0_f32),
-                                            }
-                                         ).collect::<Vec<_>>().into_boxed_slice()).as_mut_ptr(),
-                implementation_data: mem::transmute(Box::new(plugin)),
-                instantiate: instantiate,
-                connect_port: connect_port,
-                run: run,
-                cleanup: cleanup,
-                run_adding: None,
-                set_run_adding_gain: None,
-                activate: Some(activate),
-                deactivate: Some(deactivate),
-            }));
-
-            // store in global descriptor table
-            (*DESCRIPTORS).push(desc);
-            desc
-        }
+    match (&*DESCRIPTORS).get(index as usize) {
+        Some(&desc) => desc,
         None => ptr::null_mut(),
     }
 }
 
+// Converts a PluginDescriptor into the C representation expected by hosts. The result must
+// be released with drop_descriptor.
+pub unsafe fn build_descriptor(plugin: PluginDescriptor) -> *mut ladspa_h::Descriptor {
+    Box::into_raw(Box::new(ladspa_h::Descriptor {
+        unique_id: plugin.unique_id as c_ulong,
+        label: c_string(&plugin.label).into_raw(),
+        properties: plugin.properties.bits(),
+        name: c_string(&plugin.name).into_raw(),
+        maker: c_string(&plugin.maker).into_raw(),
+        copyright: c_string(&plugin.copyright).into_raw(),
+
+        port_count: plugin.ports.len() as c_ulong,
+        port_descriptors: Box::into_raw(
+            plugin.ports.iter().map(|port|
+                                    port.desc as i32
+                                   ).collect::<Vec<_>>().into_boxed_slice()) as *mut i32,
+        port_names: Box::into_raw(
+            plugin.ports.iter().map(|port|
+                                    c_string(&port.name).into_raw()
+                                   ).collect::<Vec<_>>().into_boxed_slice()) as *mut *mut c_char,
+        port_range_hints: Box::into_raw(
+            plugin.ports.iter().map(|port|
+                                    ladspa_h::PortRangeHint {
+                                        hint_descriptor: port.hint.map(|x| x.bits()).unwrap_or(0) |
+                                            port.default.map(|x| x as i32).unwrap_or(0) |
+                                            port.lower_bound.map(|_| ladspa_h::HINT_BOUNDED_BELOW)
+                                            .unwrap_or(0) |
+                                            port.upper_bound.map(|_| ladspa_h::HINT_BOUNDED_ABOVE)
+                                            .unwrap_or(0),
+                                         lower_bound: port.lower_bound.unwrap_or(0_f32),
+                                         upper_bound: port.upper_bound.unwrap_or(0_f32),
+                                    }
+                                 ).collect::<Vec<_>>().into_boxed_slice())
+            as *mut ladspa_h::PortRangeHint,
+        implementation_data: Box::into_raw(Box::new(plugin)) as *mut c_void,
+        instantiate: instantiate,
+        connect_port: connect_port,
+        run: run,
+        cleanup: cleanup,
+        run_adding: None,
+        set_run_adding_gain: None,
+        activate: Some(activate),
+        deactivate: Some(deactivate),
+    }))
+}
+
+// Frees the descriptor table when the library is unloaded with dlclose, or at exit if it never
+// is. Registering this with atexit instead would leave a pointer into the unloaded library.
+#[used]
+#[cfg_attr(any(target_os = "linux", target_os = "android", target_os = "freebsd",
+               target_os = "netbsd", target_os = "openbsd", target_os = "dragonfly"),
+           link_section = ".fini_array")]
+#[cfg_attr(target_os = "macos", link_section = "__DATA,__mod_term_func")]
+static GLOBAL_DESTRUCT: extern "C" fn() = global_destruct;
+
 extern "C" fn global_destruct() {
     unsafe {
-        let descs: Box<Vec<*mut ladspa_h::Descriptor>> = mem::transmute(DESCRIPTORS);
-        for desc in descs.iter() {
-            drop_descriptor(mem::transmute(*desc));
+        if DESCRIPTORS.is_null() {
+            return;
+        }
+        let descs = Box::from_raw(DESCRIPTORS);
+        DESCRIPTORS = ptr::null_mut();
+        for &desc in descs.iter() {
+            drop_descriptor(desc);
         }
     }
 }
 
-unsafe fn drop_descriptor(desc: &mut ladspa_h::Descriptor) {
-    CString::from_raw(desc.label);
-    CString::from_raw(desc.name);
-    CString::from_raw(desc.maker);
-    CString::from_raw(desc.copyright);
-    Vec::from_raw_parts(desc.port_descriptors,
-                        desc.port_count as usize,
-                        desc.port_count as usize);
-    Vec::from_raw_parts(desc.port_names,
-                        desc.port_count as usize,
-                        desc.port_count as usize)
-        .iter()
-        .map(|&x| CString::from_raw(x))
-        .collect::<Vec<_>>();
-    Vec::from_raw_parts(desc.port_range_hints,
-                        desc.port_count as usize,
-                        desc.port_count as usize);
-    mem::transmute::<_, Box<PluginDescriptor>>(desc.implementation_data);
+// Converts a string from a descriptor for the host. C strings end at the first NUL, so one
+// which contains a NUL is cut short there rather than failing while the host loads the library.
+fn c_string(s: &str) -> CString {
+    CString::new(s).unwrap_or_else(|err| {
+        diagnostics::report(&format!("{:?} contains a NUL, so it is cut short", s));
+        let nul = err.nul_position();
+        let mut bytes = err.into_vec();
+        bytes.truncate(nul);
+        CString::new(bytes).unwrap_or_default()
+    })
+}
+
+// Frees a descriptor created by build_descriptor, including the PluginDescriptor it owns.
+pub unsafe fn drop_descriptor(desc: *mut ladspa_h::Descriptor) {
+    let desc = Box::from_raw(desc);
+    drop(CString::from_raw(desc.label));
+    drop(CString::from_raw(desc.name));
+    drop(CString::from_raw(desc.maker));
+    drop(CString::from_raw(desc.copyright));
+    drop(Vec::from_raw_parts(desc.port_descriptors,
+                             desc.port_count as usize,
+                             desc.port_count as usize));
+    for &name in &Vec::from_raw_parts(desc.port_names,
+                                      desc.port_count as usize,
+                                      desc.port_count as usize) {
+        drop(CString::from_raw(name));
+    }
+    drop(Vec::from_raw_parts(desc.port_range_hints,
+                             desc.port_count as usize,
+                             desc.port_count as usize));
+    drop(Box::from_raw(desc.implementation_data as *mut PluginDescriptor));
 }
 
 // The handle that is given to ladspa.
@@ -201,29 +263,108 @@ struct Handle<'a> {
     plugin: Box<super::Plugin + Send + 'static>,
     port_map: VecMap<super::PortConnection<'a>>,
     ports: Vec<&'a super::PortConnection<'a>>,
+    // The port to write Plugin::latency to after each run, if any.
+    latency_port: Option<usize>,
+    // Records the calls made by the host, if tracing is enabled.
+    trace: Option<Recorder>,
+    state: State,
+}
+
+// Reports a call which breaks the LADSPA specification, in strict mode.
+fn violation(label: &str, msg: &str) {
+    if lifecycle::STRICT {
+        diagnostics::report(&format!("host error in {}: {}", label, msg));
+    }
+}
+
+// Turns the pointer given by the host back into a handle, unless it is null. Any other pointer
+// is trusted to come from instantiate and not to have been cleaned up, as nothing can be read
+// from it safely to tell otherwise.
+unsafe fn handle<'a>(instance: ladspa_h::Handle, call: &str) -> Option<&'a mut Handle<'a>> {
+    if instance.is_null() {
+        violation("an unknown plugin", &format!("{} called with a null instance", call));
+        return None;
+    }
+    Some(&mut *(instance as *mut Handle))
+}
+
+// Activates the plugin, leaving it in the failed state if it returns an error or panics.
+fn activate_plugin(handle: &mut Handle) {
+    lifecycle::enter(handle.state);
+    let mut plugin = AssertUnwindSafe(&mut handle.plugin);
+    let result = call_user_code!(Some(plugin.activate()), "Plugin::activate");
+    lifecycle::leave();
+    handle.state = match result {
+        Some(Ok(())) => State::Active,
+        Some(Err(err)) => {
+            diagnostics::report(&format!("{} failed to activate, so it will output silence: {}",
+                                         handle.descriptor.label,
+                                         err));
+            State::Failed
+        }
+        None => State::Failed,
+    };
+}
+
+fn deactivate_plugin(handle: &mut Handle) {
+    lifecycle::enter(handle.state);
+    let mut plugin = AssertUnwindSafe(&mut handle.plugin);
+    call_user_code!({ plugin.deactivate(); Some(()) }, "Plugin::deactivate");
+    lifecycle::leave();
+    handle.state = State::Deactivated;
+}
+
+// Writes silence to every connected audio output, in place of running the plugin.
+fn silence(handle: &mut Handle) {
+    for (_, port) in handle.port_map.iter_mut() {
+        if let super::PortData::AudioOutput(ref mut data) = port.data {
+            for x in data.borrow_mut().iter_mut() {
+                *x = 0.0;
+            }
+        }
+    }
 }
 
 extern "C" fn instantiate(descriptor: *const ladspa_h::Descriptor,
                           sample_rate: c_ulong)
                           -> ladspa_h::Handle {
     unsafe {
-        let desc: &mut ladspa_h::Descriptor = mem::transmute(descriptor);
-
-        let rust_desc: &super::PluginDescriptor = mem::transmute(desc.implementation_data);
-        let rust_plugin = match call_user_code!(Some((rust_desc.new)(rust_desc, sample_rate as u64)),
-                                                "PluginDescriptor::run") {
-            Some(plug) => plug,
+        let desc = &*descriptor;
+
+        let rust_desc = &*(desc.implementation_data as *const super::PluginDescriptor);
+        let unwind_desc = AssertUnwindSafe(rust_desc);
+        // c_ulong is only 32 bits on some platforms.
+        #[allow(clippy::unnecessary_cast)]
+        let sample_rate = sample_rate as u64;
+        // Started first, so that a plugin which fails to instantiate still leaves a trace.
+        let trace = Recorder::start(rust_desc, sample_rate);
+        let rust_plugin = match call_user_code!(Some((unwind_desc.new)(*unwind_desc, sample_rate)),
+                                                "PluginDescriptor::new") {
+            Some(Ok(plug)) => plug,
+            Some(Err(err)) => {
+                diagnostics::report(&format!("{} failed to instantiate at {}Hz: {}",
+                                             rust_desc.label,
+                                             sample_rate,
+                                             err));
+                return ptr::null_mut();
+            }
             None => return ptr::null_mut(),
         };
         let port_map: VecMap<super::PortConnection> = VecMap::new();
         let ports: Vec<&super::PortConnection> = Vec::new();
+        let latency_port = rust_desc.ports.iter().position(|port| {
+            port.desc == super::PortDescriptor::ControlOutput && port.name == super::LATENCY_PORT
+        });
 
-        mem::transmute(Box::new(Handle {
+        Box::into_raw(Box::new(Handle {
             descriptor: rust_desc,
             plugin: rust_plugin,
             port_map: port_map,
             ports: ports,
-        }))
+            latency_port: latency_port,
+            trace: trace,
+            state: State::Instantiated,
+        })) as ladspa_h::Handle
     }
 }
 
@@ -231,9 +372,26 @@ extern "C" fn connect_port(instance: ladspa_h::Handle,
                            port_num: c_ulong,
                            data_location: *mut ladspa_h::Data) {
     unsafe {
-        let handle: &mut Handle = mem::transmute(instance);
+        let handle = match handle(instance, "connect_port") {
+            Some(handle) => handle,
+            None => return,
+        };
 
-        let port = handle.descriptor.ports[port_num as usize];
+        let port = match handle.descriptor.ports.get(port_num as usize) {
+            Some(port) => port,
+            None => {
+                violation(&handle.descriptor.label,
+                          &format!("connect_port called for port {} of {}",
+                                   port_num,
+                                   handle.descriptor.ports.len()));
+                return;
+            }
+        };
+        if data_location.is_null() {
+            violation(&handle.descriptor.label,
+                      &format!("connect_port called with a null location for port {}", port_num));
+            return;
+        }
 
         // Create appropriate pointers to port data. Mutable locations are wrapped in refcells.
         let data = match port.desc {
@@ -247,10 +405,10 @@ extern "C" fn connect_port(instance: ladspa_h::Handle,
                                                                                     0)))
             }
             super::PortDescriptor::ControlInput => {
-                super::PortData::ControlInput(mem::transmute(data_location))
+                super::PortData::ControlInput(&*data_location)
             }
             super::PortDescriptor::ControlOutput => {
-                super::PortData::ControlOutput(RefCell::new(mem::transmute(data_location)))
+                super::PortData::ControlOutput(RefCell::new(&mut *data_location))
             }
             super::PortDescriptor::Invalid => panic!("Invalid port descriptor!"),
         };
@@ -260,9 +418,12 @@ extern "C" fn connect_port(instance: ladspa_h::Handle,
             data: data,
         };
         handle.port_map.insert(port_num as usize, conn);
+        if let Some(ref mut trace) = handle.trace {
+            trace.connect_port(port_num as usize, data_location);
+        }
 
         // Depends on the assumption that ports will be recreated whenever port_map changes
-        let handle_ptr: &mut Handle = mem::transmute(instance);
+        let handle_ptr = &mut *(instance as *mut Handle);
         if handle.port_map.len() == handle.descriptor.ports.len() {
             handle_ptr.ports = handle.port_map.values().collect();
         }
@@ -271,7 +432,10 @@ extern "C" fn connect_port(instance: ladspa_h::Handle,
 
 extern "C" fn run(instance: ladspa_h::Handle, sample_count: c_ulong) {
     unsafe {
-        let handle: &mut Handle = mem::transmute(instance);
+        let handle = match handle(instance, "run") {
+            Some(handle) => handle,
+            None => return,
+        };
         for (_, port) in handle.port_map.iter_mut() {
             match port.data {
                 super::PortData::AudioOutput(ref mut data) => {
@@ -285,27 +449,97 @@ extern "C" fn run(instance: ladspa_h::Handle, sample_count: c_ulong) {
                 _ => {}
             }
         }
-        let mut handle = AssertUnwindSafe(handle);
-        call_user_code!(Some({
-                            let ref mut handle = *handle;
-                            handle.plugin.run(sample_count as usize, &handle.ports)
-                        }),
-                        "Plugin::run");
+        if let Some(ref mut trace) = handle.trace {
+            trace.run(sample_count as usize, &handle.port_map);
+        }
+        if handle.port_map.len() < handle.descriptor.ports.len() {
+            violation(&handle.descriptor.label, "run called before every port was connected");
+            silence(handle);
+            return;
+        }
+        match handle.state {
+            State::Active => {}
+            State::Failed => {
+                silence(handle);
+                return;
+            }
+            State::Instantiated | State::Deactivated => {
+                violation(&handle.descriptor.label,
+                          &format!("run called on an instance which is {}", handle.state));
+                if lifecycle::STRICT {
+                    silence(handle);
+                    return;
+                }
+                activate_plugin(handle);
+                if handle.state == State::Failed {
+                    silence(handle);
+                    return;
+                }
+            }
+        }
+        lifecycle::enter(State::Active);
+        {
+            let mut handle = AssertUnwindSafe(&mut *handle);
+            call_user_code!({
+                                let handle: &mut Handle = &mut handle;
+                                handle.plugin.run(sample_count as usize, &handle.ports);
+                                Some(())
+                            },
+                            "Plugin::run");
+        }
+        lifecycle::leave();
+
+        if let Some(port) = handle.latency_port {
+            let plugin = AssertUnwindSafe(&handle.plugin);
+            let latency = call_user_code!(Some(plugin.latency()), "Plugin::latency");
+            if let (Some(latency), Some(conn)) = (latency, handle.port_map.get(port)) {
+                if let super::PortData::ControlOutput(ref data) = conn.data {
+                    **data.borrow_mut() = latency as ladspa_h::Data;
+                }
+            }
+        }
     }
 }
 
 extern "C" fn activate(instance: ladspa_h::Handle) {
     unsafe {
-        let handle: &mut Handle = mem::transmute(instance);
-        let mut handle = AssertUnwindSafe(handle);
-        call_user_code!(Some(handle.plugin.activate()), "Plugin::activate");
+        let handle = match handle(instance, "activate") {
+            Some(handle) => handle,
+            None => return,
+        };
+        if let Some(ref mut trace) = handle.trace {
+            trace.activate();
+        }
+        if handle.state == State::Active || handle.state == State::Failed {
+            violation(&handle.descriptor.label, "activate called twice without deactivate");
+            if lifecycle::STRICT {
+                return;
+            }
+            if handle.state == State::Active {
+                deactivate_plugin(handle);
+            }
+        }
+        activate_plugin(handle);
     }
 }
 extern "C" fn deactivate(instance: ladspa_h::Handle) {
     unsafe {
-        let handle: &mut Handle = mem::transmute(instance);
-        let mut handle = AssertUnwindSafe(handle);
-        call_user_code!(Some(handle.plugin.deactivate()), "Plugin::deactivate");
+        let handle = match handle(instance, "deactivate") {
+            Some(handle) => handle,
+            None => return,
+        };
+        if let Some(ref mut trace) = handle.trace {
+            trace.deactivate();
+        }
+        match handle.state {
+            State::Active => deactivate_plugin(handle),
+            // The plugin never became active, so it has nothing to undo.
+            State::Failed => handle.state = State::Deactivated,
+            State::Instantiated | State::Deactivated => {
+                violation(&handle.descriptor.label,
+                          &format!("deactivate called on an instance which is {}", handle.state));
+            }
+        }
     }
 }
 
@@ -316,6 +550,19 @@ extern "C" fn deactivate(instance: ladspa_h::Handle) {
 
 extern "C" fn cleanup(instance: ladspa_h::Handle) {
     unsafe {
-        mem::transmute::<_, Box<Handle>>(instance);
+        let handle = match handle(instance, "cleanup") {
+            Some(handle) => handle,
+            None => return,
+        };
+        if let Some(ref mut trace) = handle.trace {
+            trace.cleanup();
+        }
+        if handle.state == State::Active || handle.state == State::Failed {
+            violation(&handle.descriptor.label, "cleanup called without deactivate");
+            if handle.state == State::Active {
+                deactivate_plugin(handle);
+            }
+        }
+        drop(Box::from_raw(instance as *mut Handle));
     }
 }
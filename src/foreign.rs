@@ -0,0 +1,130 @@
+//! Using foreign LADSPA plugins as native ones.
+//!
+//! ```wrap``` describes a plugin loaded with ```host::Library``` as a ```PluginDescriptor```,
+//! whose instances are ```Foreign``` plugins forwarding every call to the original. The result
+//! can be used anywhere a plugin written in Rust can: returned from ```get_ladspa_descriptor```
+//! with a new label and unique ID, given extra ports, passed to wrappers such as
+//! ```bypass::wrap```, or tested with the same tools as native plugins.
+//!
+//! A ```Foreign``` plugin connects the original's ports directly to the buffers it is given, so
+//! no samples are copied.
+
+use std::ptr;
+
+use libc::c_ulong;
+
+use ffi::ladspa_h;
+use host::{self, Descriptor};
+use super::{Data, Plugin, PluginDescriptor, PluginError, PortConnection, PortData};
+
+/// Describes a foreign plugin as a native one, keeping its label, unique ID, ports and
+/// properties. Creating an instance fails with ```host::Error::Instantiate``` if the foreign
+/// plugin fails to instantiate.
+pub fn wrap(desc: &Descriptor) -> PluginDescriptor {
+    let inner = desc.clone();
+    PluginDescriptor {
+        unique_id: desc.unique_id(),
+        label: desc.label().into(),
+        properties: desc.properties(),
+        name: desc.name().into(),
+        maker: desc.maker().into(),
+        copyright: desc.copyright().into(),
+        ports: (0..desc.port_count()).map(|i| desc.port(i)).collect(),
+        new: Box::new(move |_: &PluginDescriptor, sample_rate: u64| {
+            Ok(Box::new(Foreign::new(&inner, sample_rate)?) as Box<Plugin + Send>)
+        }),
+        presets: vec![],
+    }
+}
+
+/// An instance of a foreign plugin. Its ports are those of the original.
+pub struct Foreign {
+    descriptor: Descriptor,
+    handle: ladspa_h::Handle,
+    // The location each port is currently connected to, so that unchanged ports aren't
+    // reconnected on every run.
+    connected: Vec<*mut Data>,
+    latency_port: Option<usize>,
+    latency: usize,
+    active: bool,
+}
+
+unsafe impl Send for Foreign { }
+
+impl Foreign {
+    /// Instantiates the foreign plugin. Its ports are connected during each ```run```.
+    pub fn new(descriptor: &Descriptor, sample_rate: u64) -> Result<Foreign, host::Error> {
+        let raw = descriptor.as_raw();
+        let handle = (raw.instantiate)(raw, sample_rate as c_ulong);
+        if handle.is_null() {
+            return Err(host::Error::Instantiate);
+        }
+        Ok(Foreign {
+            descriptor: descriptor.clone(),
+            handle: handle,
+            connected: vec![ptr::null_mut(); descriptor.port_count()],
+            latency_port: descriptor.latency_port(),
+            latency: 0,
+            active: false,
+        })
+    }
+
+    /// The descriptor of the foreign plugin.
+    pub fn descriptor(&self) -> &Descriptor {
+        &self.descriptor
+    }
+}
+
+impl Plugin for Foreign {
+    fn activate(&mut self) -> Result<(), PluginError> {
+        if let Some(activate) = self.descriptor.as_raw().activate {
+            activate(self.handle);
+        }
+        self.active = true;
+        Ok(())
+    }
+
+    fn run<'a>(&mut self, sample_count: usize, ports: &[&'a PortConnection<'a>]) {
+        let raw = self.descriptor.as_raw();
+        for (i, port) in ports.iter().enumerate() {
+            // The host owns every buffer, so the locations stay valid after the borrows end.
+            let location = match port.data {
+                PortData::AudioInput(data) => data.as_ptr() as *mut Data,
+                PortData::AudioOutput(ref data) => data.borrow_mut().as_mut_ptr(),
+                PortData::ControlInput(data) => data as *const Data as *mut Data,
+                PortData::ControlOutput(ref data) => &mut **data.borrow_mut() as *mut Data,
+            };
+            if self.connected[i] != location {
+                (raw.connect_port)(self.handle, i as c_ulong, location);
+                self.connected[i] = location;
+            }
+        }
+        (raw.run)(self.handle, sample_count as c_ulong);
+
+        if let Some(port) = self.latency_port {
+            let value = **ports[port].unwrap_control_mut();
+            self.latency = if value > 0.0 { value.round() as usize } else { 0 };
+        }
+    }
+
+    fn deactivate(&mut self) {
+        if let Some(deactivate) = self.descriptor.as_raw().deactivate {
+            deactivate(self.handle);
+        }
+        self.active = false;
+    }
+
+    /// The latency last reported through the foreign plugin's ```LATENCY_PORT```, if it has one.
+    fn latency(&self) -> usize {
+        self.latency
+    }
+}
+
+impl Drop for Foreign {
+    fn drop(&mut self) {
+        if self.active {
+            self.deactivate();
+        }
+        (self.descriptor.as_raw().cleanup)(self.handle);
+    }
+}
@@ -0,0 +1,289 @@
+//! Driving a plugin through the C interface with arbitrary input, for fuzzing.
+//!
+//! ```run``` interprets a string of bytes as a host session: a sample rate, then a sequence of
+//! calls to ```activate```, ```deactivate``` and ```run``` with any block size up to
+//! ```MAX_BLOCK_SIZE``` including 0, control values inside and outside the declared bounds, audio
+//! containing NaN, infinities and denormals, ports reconnected to other buffers or connected in
+//! place, and the plugin being cleaned up and instantiated again. Only sequences of calls which
+//! the LADSPA specification allows are made.
+//!
+//! Native plugins normally have their panics suppressed at the C interface so that they can't
+//! take down the host. ```run``` turns any suppressed panic into a real one, which fuzzers report
+//! as a failure along with the input that caused it.
+//!
+//! A [cargo-fuzz](https://github.com/rust-fuzz/cargo-fuzz) target for a plugin is then just:
+//!
+//! ```rust,ignore
+//! #![no_main]
+//! #[macro_use] extern crate libfuzzer_sys;
+//! extern crate ladspa;
+//!
+//! fuzz_target!(|data: &[u8]| {
+//!     ladspa::fuzz::run(&ladspa::host::Descriptor::from_native(my_plugin()), data);
+//! });
+//! ```
+//!
+//! See the ```fuzz``` directory of this repository for targets covering the plugins and adapters
+//! provided by this crate. This module needs the ```testing``` feature.
+
+use std::f32;
+
+use libc::c_ulong;
+
+use ffi::{self, ladspa_h};
+use host::Descriptor;
+use super::{Data, PortDescriptor, PROP_INPLACE_BROKEN};
+
+/// The largest block size passed to ```run```.
+pub const MAX_BLOCK_SIZE: usize = 2048;
+
+/// Runs the plugin through the host session described by ```data```. Panics if the plugin
+/// panics, or if it fails to instantiate.
+pub fn run(desc: &Descriptor, data: &[u8]) {
+    let mut input = Input { data: data };
+    let sample_rate = match input.byte() % 6 {
+        0 => 44100,
+        1 => 48000,
+        2 => 96000,
+        3 => 192000,
+        4 => 1,
+        // Large enough to be unusual without making plugins that size buffers by the sample rate
+        // run out of memory.
+        _ => 1 + input.u32() as u64 % 768000,
+    };
+    let mut session = Session::new(desc, sample_rate);
+
+    while !input.is_empty() {
+        match input.byte() % 7 {
+            0 => session.activate(),
+            1 => session.deactivate(),
+            2 => {
+                let sample_count = input.u16() as usize % (MAX_BLOCK_SIZE + 1);
+                session.run(sample_count);
+            }
+            3 => {
+                let port = input.byte() as usize;
+                session.set_control(port, &mut input);
+            }
+            4 => {
+                let port = input.byte() as usize;
+                session.fill_audio(port, &mut input);
+            }
+            5 => {
+                let (port, target) = (input.byte() as usize, input.byte() as usize);
+                session.reconnect(port, target);
+            }
+            _ => session.reinstantiate(),
+        }
+    }
+}
+
+// Reads values from the fuzzer's input, giving zeros once it runs out.
+struct Input<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Input<'a> {
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn byte(&mut self) -> u8 {
+        match self.data.split_first() {
+            Some((&x, rest)) => {
+                self.data = rest;
+                x
+            }
+            None => 0,
+        }
+    }
+
+    fn u16(&mut self) -> u16 {
+        u16::from(self.byte()) | u16::from(self.byte()) << 8
+    }
+
+    fn u32(&mut self) -> u32 {
+        u32::from(self.u16()) | u32::from(self.u16()) << 16
+    }
+}
+
+// An instance of the plugin with buffers for every port. Each port has two buffers, so that it
+// can be reconnected.
+struct Session<'a> {
+    desc: &'a Descriptor,
+    sample_rate: u64,
+    handle: ladspa_h::Handle,
+    active: bool,
+    audio: Vec<[Vec<Data>; 2]>,
+    control: Vec<[Data; 2]>,
+}
+
+impl<'a> Session<'a> {
+    fn new(desc: &'a Descriptor, sample_rate: u64) -> Session<'a> {
+        let ports = desc.port_count();
+        let mut session = Session {
+            desc: desc,
+            sample_rate: sample_rate,
+            handle: 0 as ladspa_h::Handle,
+            active: false,
+            audio: (0..ports).map(|_| [vec![0.0; MAX_BLOCK_SIZE], vec![0.0; MAX_BLOCK_SIZE]]).collect(),
+            control: (0..ports).map(|i| {
+                let value = desc.port_default(i, sample_rate)
+                    .or(desc.port_bounds(i, sample_rate).0)
+                    .unwrap_or(0.0);
+                [value, value]
+            }).collect(),
+        };
+        session.instantiate();
+        session
+    }
+
+    fn instantiate(&mut self) {
+        let raw = self.desc.as_raw();
+        self.handle = (raw.instantiate)(raw, self.sample_rate as c_ulong);
+        check(self.desc);
+        assert!(!self.handle.is_null(), "{} failed to instantiate", self.desc.label());
+        for port in 0..self.audio.len() {
+            self.connect(port, port, 0);
+        }
+    }
+
+    // Connects a port to one of the buffers of another port of the same kind.
+    fn connect(&mut self, port: usize, buffer_port: usize, buffer: usize) {
+        let location = if self.desc.port_descriptor(buffer_port).is_audio() {
+            self.audio[buffer_port][buffer].as_mut_ptr()
+        } else {
+            &mut self.control[buffer_port][buffer] as *mut Data
+        };
+        (self.desc.as_raw().connect_port)(self.handle, port as c_ulong, location);
+        check(self.desc);
+    }
+
+    fn activate(&mut self) {
+        if self.active {
+            return;
+        }
+        if let Some(activate) = self.desc.as_raw().activate {
+            activate(self.handle);
+            check(self.desc);
+        }
+        self.active = true;
+    }
+
+    fn deactivate(&mut self) {
+        if !self.active {
+            return;
+        }
+        if let Some(deactivate) = self.desc.as_raw().deactivate {
+            deactivate(self.handle);
+            check(self.desc);
+        }
+        self.active = false;
+    }
+
+    fn run(&mut self, sample_count: usize) {
+        // Hosts must activate a plugin before running it.
+        self.activate();
+        (self.desc.as_raw().run)(self.handle, sample_count as c_ulong);
+        check(self.desc);
+    }
+
+    fn set_control(&mut self, port: usize, input: &mut Input) {
+        let port = match self.ports_of(PortDescriptor::ControlInput, port) {
+            Some(port) => port,
+            None => return,
+        };
+        let (lower, upper) = self.desc.port_bounds(port, self.sample_rate);
+        let (lower, upper) = (lower.unwrap_or(-1.0), upper.unwrap_or(1.0));
+        let amount = input.byte() as Data / 255.0;
+        let value = match input.byte() % 8 {
+            0 => f32::NAN,
+            1 => f32::INFINITY,
+            2 => f32::NEG_INFINITY,
+            3 => lower - amount * 1e6,
+            4 => upper + amount * 1e6,
+            5 => f32::from_bits(input.u32()),
+            6 => if amount < 0.5 { lower } else { upper },
+            _ => lower + (upper - lower) * amount,
+        };
+        for buffer in &mut self.control[port] {
+            *buffer = value;
+        }
+    }
+
+    fn fill_audio(&mut self, port: usize, input: &mut Input) {
+        let port = match self.ports_of(PortDescriptor::AudioInput, port) {
+            Some(port) => port,
+            None => return,
+        };
+        let kind = input.byte() % 6;
+        let mut seed = input.u32() | 1;
+        let scale = [1.0, 1e-40, 1e6, 1e38][input.byte() as usize % 4];
+        for buffer in &mut self.audio[port] {
+            for (k, x) in buffer.iter_mut().enumerate() {
+                *x = match kind {
+                    0 => 0.0,
+                    1 => f32::NAN,
+                    2 => if k % 2 == 0 { f32::INFINITY } else { f32::NEG_INFINITY },
+                    3 => if k == 0 { scale } else { 0.0 },
+                    _ => {
+                        // A simple pseudorandom sequence, so that inputs stay short.
+                        seed ^= seed << 13;
+                        seed ^= seed >> 17;
+                        seed ^= seed << 5;
+                        (seed as Data / u32::MAX as Data * 2.0 - 1.0) * scale
+                    }
+                };
+            }
+        }
+    }
+
+    // Connects a port to its other buffer or, for audio outputs of plugins which can run in
+    // place, to the buffer of an audio input.
+    fn reconnect(&mut self, port: usize, target: usize) {
+        let port = port % self.audio.len().max(1);
+        if port >= self.audio.len() {
+            return;
+        }
+        let inputs = self.ports_of(PortDescriptor::AudioInput, target);
+        let in_place = !self.desc.properties().contains(PROP_INPLACE_BROKEN) && target % 2 == 1;
+        match inputs {
+            Some(input) if in_place && self.desc.port_descriptor(port) == PortDescriptor::AudioOutput => {
+                self.connect(port, input, target / 2 % 2);
+            }
+            _ => self.connect(port, port, target % 2),
+        }
+    }
+
+    // Picks one of the ports of the given kind, if there are any.
+    fn ports_of(&self, kind: PortDescriptor, choice: usize) -> Option<usize> {
+        let ports: Vec<usize> = (0..self.audio.len()).filter(|&i| self.desc.port_descriptor(i) == kind).collect();
+        if ports.is_empty() { None } else { Some(ports[choice % ports.len()]) }
+    }
+
+    fn reinstantiate(&mut self) {
+        self.cleanup();
+        self.instantiate();
+    }
+
+    fn cleanup(&mut self) {
+        self.deactivate();
+        (self.desc.as_raw().cleanup)(self.handle);
+        check(self.desc);
+    }
+}
+
+impl<'a> Drop for Session<'a> {
+    fn drop(&mut self) {
+        if !self.handle.is_null() {
+            self.cleanup();
+        }
+    }
+}
+
+// Fails if the plugin panicked during the last call.
+fn check(desc: &Descriptor) {
+    if let Some(msg) = ffi::take_suppressed_panic() {
+        panic!("{}: {}", desc.label(), msg);
+    }
+}
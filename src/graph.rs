@@ -0,0 +1,405 @@
+//! Connecting hosted plugins together into a processing graph.
+//!
+//! Each node in a ```Graph``` is an ```Instance``` of a foreign or native plugin. Audio outputs
+//! may feed any number of audio inputs, and an audio input fed by several outputs receives their
+//! sum. Control inputs either keep the value set with ```Graph::set_control``` or follow the
+//! control output of another node. Buffers are allocated when nodes are added, so ```process```
+//! never allocates.
+//!
+//! Plugins which report their latency delay the graph's outputs. ```render``` compensates for
+//! this when processing a whole recording at once.
+
+use std::error;
+use std::fmt;
+
+use host::{self, Descriptor, Instance};
+use super::{Data, PluginDescriptor, PortDescriptor};
+
+/// Identifies a node within a ```Graph```.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+/// The ways building a graph can fail.
+#[derive(Debug)]
+pub enum Error {
+    /// A node could not be instantiated.
+    Host(host::Error),
+    /// The ports can't be connected, either because one doesn't exist or because their types
+    /// don't match.
+    PortMismatch,
+    /// The connection would create a cycle.
+    Cycle,
+    /// A control input can only be fed by one control output, and this one already is.
+    ControlConnected,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Host(ref err) => err.fmt(f),
+            Error::PortMismatch => write!(f, "ports cannot be connected"),
+            Error::Cycle => write!(f, "connection would create a cycle"),
+            Error::ControlConnected => write!(f, "control input is already connected"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<host::Error> for Error {
+    fn from(err: host::Error) -> Error {
+        Error::Host(err)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Source {
+    Input(usize),
+    Node(usize, usize),
+}
+
+struct Node {
+    instance: Instance,
+    // For each port, the outputs feeding it.
+    sources: Vec<Vec<Source>>,
+}
+
+/// A set of plugin instances and the connections between them.
+pub struct Graph {
+    sample_rate: u64,
+    max_block_size: usize,
+    inputs: usize,
+    outputs: Vec<Vec<Source>>,
+    nodes: Vec<Node>,
+    order: Vec<usize>,
+}
+
+impl Graph {
+    /// Creates an empty graph with the given number of external audio inputs and outputs.
+    /// Nodes will be instantiated at ```sample_rate``` with buffers of ```max_block_size```.
+    pub fn new(sample_rate: u64, max_block_size: usize, inputs: usize, outputs: usize) -> Graph {
+        Graph {
+            sample_rate: sample_rate,
+            max_block_size: max_block_size,
+            inputs: inputs,
+            outputs: vec![Vec::new(); outputs],
+            nodes: Vec::new(),
+            order: Vec::new(),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u64 {
+        self.sample_rate
+    }
+
+    /// The number of external audio inputs.
+    pub fn inputs(&self) -> usize {
+        self.inputs
+    }
+
+    /// The number of external audio outputs.
+    pub fn outputs(&self) -> usize {
+        self.outputs.len()
+    }
+
+    /// Instantiates the plugin as a new node.
+    pub fn add_node(&mut self, descriptor: &Descriptor) -> Result<NodeId, Error> {
+        let instance = Instance::new(descriptor, self.sample_rate, self.max_block_size)?;
+        let ports = descriptor.port_count();
+        self.nodes.push(Node {
+            instance: instance,
+            sources: vec![Vec::new(); ports],
+        });
+        self.order.push(self.nodes.len() - 1);
+        Ok(NodeId(self.nodes.len() - 1))
+    }
+
+    /// Instantiates a native plugin as a new node.
+    pub fn add_plugin(&mut self, plugin: PluginDescriptor) -> Result<NodeId, Error> {
+        self.add_node(&Descriptor::from_native(plugin))
+    }
+
+    pub fn node(&self, node: NodeId) -> &Instance {
+        &self.nodes[node.0].instance
+    }
+
+    pub fn node_mut(&mut self, node: NodeId) -> &mut Instance {
+        &mut self.nodes[node.0].instance
+    }
+
+    /// Sets the value of a control input. If the port is connected to a control output, the
+    /// value will be overwritten on the next call to ```process```.
+    pub fn set_control(&mut self, node: NodeId, port: usize, value: Data) {
+        self.nodes[node.0].instance.set_control(port, value);
+    }
+
+    /// Connects an audio output to an audio input, or a control output to a control input.
+    /// Audio inputs receive the sum of every output connected to them, but a control input can
+    /// only be connected to one control output.
+    pub fn connect(&mut self,
+                   from: NodeId,
+                   from_port: usize,
+                   to: NodeId,
+                   to_port: usize)
+                   -> Result<(), Error> {
+        let from_desc = self.port_descriptor(from, from_port)?;
+        let to_desc = self.port_descriptor(to, to_port)?;
+        match (from_desc, to_desc) {
+            (PortDescriptor::AudioOutput, PortDescriptor::AudioInput) |
+            (PortDescriptor::ControlOutput, PortDescriptor::ControlInput) => {}
+            _ => return Err(Error::PortMismatch),
+        }
+
+        let source = Source::Node(from.0, from_port);
+        let sources = &mut self.nodes[to.0].sources[to_port];
+        if sources.contains(&source) {
+            return Ok(());
+        }
+        if to_desc == PortDescriptor::ControlInput && !sources.is_empty() {
+            return Err(Error::ControlConnected);
+        }
+        sources.push(source);
+        if !self.sort() {
+            self.nodes[to.0].sources[to_port].pop();
+            self.sort();
+            return Err(Error::Cycle);
+        }
+        Ok(())
+    }
+
+    /// Feeds an external input of the graph into an audio input.
+    pub fn connect_input(&mut self, input: usize, to: NodeId, to_port: usize) -> Result<(), Error> {
+        match self.port_descriptor(to, to_port)? {
+            PortDescriptor::AudioInput if input < self.inputs => {}
+            _ => return Err(Error::PortMismatch),
+        }
+        let source = Source::Input(input);
+        if !self.nodes[to.0].sources[to_port].contains(&source) {
+            self.nodes[to.0].sources[to_port].push(source);
+        }
+        Ok(())
+    }
+
+    /// Feeds an audio output into an external output of the graph.
+    pub fn connect_output(&mut self, from: NodeId, from_port: usize, output: usize) -> Result<(), Error> {
+        match self.port_descriptor(from, from_port)? {
+            PortDescriptor::AudioOutput if output < self.outputs.len() => {}
+            _ => return Err(Error::PortMismatch),
+        }
+        let source = Source::Node(from.0, from_port);
+        if !self.outputs[output].contains(&source) {
+            self.outputs[output].push(source);
+        }
+        Ok(())
+    }
+
+    /// Activates every node.
+    pub fn activate(&mut self) {
+        for node in &mut self.nodes {
+            node.instance.activate();
+        }
+    }
+
+    /// Deactivates every node.
+    pub fn deactivate(&mut self) {
+        for node in &mut self.nodes {
+            node.instance.deactivate();
+        }
+    }
+
+    /// Runs every node in dependency order. All input and output slices must have the same
+    /// length, which may be larger than ```max_block_size```.
+    pub fn process(&mut self, inputs: &[&[Data]], outputs: &mut [&mut [Data]]) {
+        assert_eq!(inputs.len(), self.inputs, "wrong number of graph inputs");
+        assert_eq!(outputs.len(), self.outputs.len(), "wrong number of graph outputs");
+        let len = inputs.iter().map(|x| x.len())
+            .chain(outputs.iter().map(|x| x.len()))
+            .next()
+            .unwrap_or(0);
+        assert!(inputs.iter().all(|x| x.len() == len) && outputs.iter().all(|x| x.len() == len),
+                "graph inputs and outputs must have the same length");
+        if self.max_block_size == 0 {
+            return;
+        }
+
+        let mut offset = 0;
+        while offset < len {
+            let count = ::std::cmp::min(self.max_block_size, len - offset);
+            for &idx in &self.order {
+                Graph::gather(&mut self.nodes, idx, inputs, offset, count);
+                self.nodes[idx].instance.run(count);
+            }
+            for (output, sources) in outputs.iter_mut().zip(&self.outputs) {
+                let output = &mut output[offset..offset + count];
+                for x in output.iter_mut() {
+                    *x = 0.0;
+                }
+                for &source in sources {
+                    if let Source::Node(node, port) = source {
+                        let data = &self.nodes[node].instance.audio(port)[..count];
+                        for (x, y) in output.iter_mut().zip(data) {
+                            *x += *y;
+                        }
+                    }
+                }
+            }
+            offset += count;
+        }
+    }
+
+    /// Processes a whole recording, like ```process```, but removes the latency reported by the
+    /// nodes so that the outputs line up with the inputs. The graph is run for extra samples of
+    /// silence to flush out the end of the recording. Unlike ```process```, this allocates.
+    pub fn render(&mut self, inputs: &[&[Data]], outputs: &mut [&mut [Data]]) {
+        let len = inputs.iter().map(|x| x.len())
+            .chain(outputs.iter().map(|x| x.len()))
+            .next()
+            .unwrap_or(0);
+        let mut rendered = vec![vec![0.0; len]; self.outputs.len()];
+        {
+            let mut rendered: Vec<&mut [Data]> = rendered.iter_mut().map(|x| &mut x[..]).collect();
+            self.process(inputs, &mut rendered);
+        }
+
+        // Plugins only report their latency once they have run.
+        let latency = self.latency();
+        let silence = vec![vec![0.0; latency]; self.inputs];
+        let mut tail = vec![vec![0.0; latency]; self.outputs.len()];
+        {
+            let silence: Vec<&[Data]> = silence.iter().map(|x| &x[..]).collect();
+            let mut tail: Vec<&mut [Data]> = tail.iter_mut().map(|x| &mut x[..]).collect();
+            self.process(&silence, &mut tail);
+        }
+
+        for ((output, rendered), tail) in outputs.iter_mut().zip(&rendered).zip(&tail) {
+            let split = len.saturating_sub(latency);
+            output[..split].copy_from_slice(&rendered[latency.min(len)..]);
+            output[split..].copy_from_slice(&tail[latency - (len - split)..]);
+        }
+    }
+
+    /// The largest latency, in samples, along any path from the graph's inputs to its outputs, as
+    /// most recently reported by the nodes.
+    pub fn latency(&self) -> usize {
+        // The nodes are visited in dependency order, so every source is known before it's used.
+        let mut latencies = vec![0; self.nodes.len()];
+        for &idx in &self.order {
+            let node = &self.nodes[idx];
+            let input = node.sources.iter().enumerate()
+                .filter(|&(port, _)| {
+                    node.instance.descriptor().port_descriptor(port) == PortDescriptor::AudioInput
+                })
+                .flat_map(|(_, sources)| sources)
+                .map(|&source| source_latency(&latencies, source))
+                .max()
+                .unwrap_or(0);
+            latencies[idx] = input + node.instance.latency().unwrap_or(0);
+        }
+        self.outputs.iter()
+            .flat_map(|sources| sources)
+            .map(|&source| source_latency(&latencies, source))
+            .max()
+            .unwrap_or(0)
+    }
+
+    // Fills the inputs of a node from the outputs connected to it.
+    fn gather(nodes: &mut [Node], idx: usize, inputs: &[&[Data]], offset: usize, count: usize) {
+        for port in 0..nodes[idx].sources.len() {
+            if nodes[idx].sources[port].is_empty() {
+                continue;
+            }
+            match nodes[idx].instance.descriptor().port_descriptor(port) {
+                PortDescriptor::AudioInput => {
+                    for x in nodes[idx].instance.audio_mut(port)[..count].iter_mut() {
+                        *x = 0.0;
+                    }
+                    for i in 0..nodes[idx].sources[port].len() {
+                        let source = nodes[idx].sources[port][i];
+                        let (node, other) = split(nodes, idx, source);
+                        let data = match other {
+                            Some((other, from_port)) => &other.instance.audio(from_port)[..count],
+                            None => match source {
+                                Source::Input(input) => &inputs[input][offset..offset + count],
+                                _ => unreachable!(),
+                            },
+                        };
+                        let buf = &mut node.instance.audio_mut(port)[..count];
+                        for (x, y) in buf.iter_mut().zip(data) {
+                            *x += *y;
+                        }
+                    }
+                }
+                PortDescriptor::ControlInput => {
+                    let source = nodes[idx].sources[port][0];
+                    if let (node, Some((other, from_port))) = split(nodes, idx, source) {
+                        let value = other.instance.control(from_port);
+                        node.instance.set_control(port, value);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn port_descriptor(&self, node: NodeId, port: usize) -> Result<PortDescriptor, Error> {
+        match self.nodes.get(node.0) {
+            Some(n) if port < n.sources.len() => Ok(n.instance.descriptor().port_descriptor(port)),
+            _ => Err(Error::PortMismatch),
+        }
+    }
+
+    // Recomputes the processing order, returning false if the graph contains a cycle.
+    fn sort(&mut self) -> bool {
+        let n = self.nodes.len();
+        let mut pending = vec![0; n];
+        let mut dependents = vec![Vec::new(); n];
+        for (idx, node) in self.nodes.iter().enumerate() {
+            for &source in node.sources.iter().flat_map(|x| x) {
+                if let Source::Node(from, _) = source {
+                    pending[idx] += 1;
+                    dependents[from].push(idx);
+                }
+            }
+        }
+
+        let mut order: Vec<usize> = (0..n).filter(|&i| pending[i] == 0).collect();
+        let mut i = 0;
+        while i < order.len() {
+            for &next in &dependents[order[i]] {
+                pending[next] -= 1;
+                if pending[next] == 0 {
+                    order.push(next);
+                }
+            }
+            i += 1;
+        }
+        if order.len() != n {
+            return false;
+        }
+        self.order = order;
+        true
+    }
+}
+
+fn source_latency(latencies: &[usize], source: Source) -> usize {
+    match source {
+        Source::Input(_) => 0,
+        Source::Node(node, _) => latencies[node],
+    }
+}
+
+// Borrows a node mutably along with the node a source refers to, if any.
+fn split(nodes: &mut [Node], idx: usize, source: Source) -> (&mut Node, Option<(&Node, usize)>) {
+    match source {
+        Source::Input(_) => (&mut nodes[idx], None),
+        Source::Node(other, port) => {
+            if other < idx {
+                let (a, b) = nodes.split_at_mut(idx);
+                (&mut b[0], Some((&a[other], port)))
+            } else {
+                let (a, b) = nodes.split_at_mut(other);
+                (&mut a[idx], Some((&b[0], port)))
+            }
+        }
+    }
+}
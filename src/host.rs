@@ -0,0 +1,487 @@
+//! Loading and running LADSPA plugins from Rust.
+//!
+//! A `Descriptor` describes a plugin either exported by a foreign library opened with
+//! `Library::open`, or built from one of your own `PluginDescriptor`s. An `Instance` owns the
+//! buffers for every port of a plugin instance, so it can be driven one block at a time without
+//! any further setup.
+
+use std::env;
+use std::error;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use libc::{self, c_ulong, c_void};
+
+use ffi::{self, ladspa_h};
+use super::{ControlHint, Data, DefaultValue, PluginDescriptor, Port, PortDescriptor, Properties,
+            LATENCY_PORT};
+
+/// The ways hosting a plugin can fail.
+#[derive(Debug)]
+pub enum Error {
+    /// The library could not be opened, or it does not export ```ladspa_descriptor```.
+    Load(String),
+    /// The plugin returned a null handle from ```instantiate```.
+    Instantiate,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Load(ref msg) => write!(f, "failed to load plugin library: {}", msg),
+            Error::Instantiate => write!(f, "plugin failed to instantiate"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+type DescriptorFn = unsafe extern "C" fn(index: c_ulong) -> *const ladspa_h::Descriptor;
+
+/// A shared library containing LADSPA plugins. The library stays loaded for as long as any
+/// ```Descriptor``` obtained from it is alive.
+pub struct Library {
+    handle: *mut c_void,
+    descriptor_fn: DescriptorFn,
+}
+
+unsafe impl Send for Library { }
+unsafe impl Sync for Library { }
+
+impl Library {
+    /// Opens the library at ```path``` with ```dlopen```.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Arc<Library>, Error> {
+        let path = path.as_ref().to_string_lossy().into_owned();
+        let c_path = CString::new(path.clone()).map_err(|_| Error::Load(path.clone()))?;
+        unsafe {
+            let handle = libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+            if handle.is_null() {
+                return Err(Error::Load(dlerror().unwrap_or(path)));
+            }
+            let sym = libc::dlsym(handle, b"ladspa_descriptor\0".as_ptr() as *const _);
+            if sym.is_null() {
+                libc::dlclose(handle);
+                return Err(Error::Load(format!("{}: no ladspa_descriptor symbol", path)));
+            }
+            Ok(Arc::new(Library {
+                handle: handle,
+                descriptor_fn: ::std::mem::transmute::<*mut c_void, DescriptorFn>(sym),
+            }))
+        }
+    }
+
+    /// Returns descriptors for every plugin exported by the library, in index order.
+    pub fn descriptors(lib: &Arc<Library>) -> Vec<Descriptor> {
+        let mut descs = Vec::new();
+        loop {
+            let ptr = unsafe { (lib.descriptor_fn)(descs.len() as c_ulong) };
+            if ptr.is_null() {
+                return descs;
+            }
+            descs.push(Descriptor {
+                raw: Arc::new(Raw {
+                    ptr: ptr,
+                    library: Some(lib.clone()),
+                }),
+            });
+        }
+    }
+}
+
+impl Drop for Library {
+    fn drop(&mut self) {
+        unsafe {
+            libc::dlclose(self.handle);
+        }
+    }
+}
+
+/// Resolves a library name without a directory component by searching the directories in
+/// ```LADSPA_PATH```, followed by the usual system locations. Other paths are returned unchanged.
+pub fn find_library<P: AsRef<Path>>(name: P) -> PathBuf {
+    let name = name.as_ref();
+    if name.components().count() != 1 {
+        return name.to_path_buf();
+    }
+    let mut dirs: Vec<PathBuf> = env::var_os("LADSPA_PATH")
+        .map(|path| env::split_paths(&path).collect())
+        .unwrap_or_else(Vec::new);
+    dirs.push(PathBuf::from("/usr/local/lib/ladspa"));
+    dirs.push(PathBuf::from("/usr/lib/ladspa"));
+    dirs.into_iter()
+        .map(|dir| dir.join(name))
+        .find(|path| path.exists())
+        .unwrap_or_else(|| name.to_path_buf())
+}
+
+unsafe fn dlerror() -> Option<String> {
+    let msg = libc::dlerror();
+    if msg.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(msg).to_string_lossy().into_owned())
+    }
+}
+
+struct Raw {
+    ptr: *const ladspa_h::Descriptor,
+    // Foreign descriptors borrow from their library. Native ones are owned and freed on drop.
+    library: Option<Arc<Library>>,
+}
+
+unsafe impl Send for Raw { }
+unsafe impl Sync for Raw { }
+
+impl Drop for Raw {
+    fn drop(&mut self) {
+        if self.library.is_none() {
+            unsafe {
+                ffi::drop_descriptor(self.ptr as *mut _);
+            }
+        }
+    }
+}
+
+/// A plugin description as seen by a LADSPA host. Cloning is cheap.
+#[derive(Clone)]
+pub struct Descriptor {
+    raw: Arc<Raw>,
+}
+
+impl Descriptor {
+    /// Wraps a native plugin so that it can be hosted in the same way as a foreign one.
+    pub fn from_native(plugin: PluginDescriptor) -> Descriptor {
+        Descriptor {
+            raw: Arc::new(Raw {
+                ptr: unsafe { ffi::build_descriptor(plugin) },
+                library: None,
+            }),
+        }
+    }
+
+    /// The native plugin this descriptor was built from with ```from_native```, if any.
+    pub fn native(&self) -> Option<&PluginDescriptor> {
+        if self.raw.library.is_none() {
+            Some(unsafe { &*(self.as_raw().implementation_data as *const PluginDescriptor) })
+        } else {
+            None
+        }
+    }
+
+    /// The underlying C descriptor.
+    pub fn as_raw(&self) -> &ladspa_h::Descriptor {
+        unsafe { &*self.raw.ptr }
+    }
+
+    // c_ulong is only 32 bits on some platforms.
+    #[allow(clippy::unnecessary_cast)]
+    pub fn unique_id(&self) -> u64 {
+        self.as_raw().unique_id as u64
+    }
+
+    pub fn label(&self) -> String {
+        unsafe { CStr::from_ptr(self.as_raw().label).to_string_lossy().into_owned() }
+    }
+
+    pub fn name(&self) -> String {
+        unsafe { CStr::from_ptr(self.as_raw().name).to_string_lossy().into_owned() }
+    }
+
+    pub fn maker(&self) -> String {
+        unsafe { CStr::from_ptr(self.as_raw().maker).to_string_lossy().into_owned() }
+    }
+
+    pub fn copyright(&self) -> String {
+        unsafe { CStr::from_ptr(self.as_raw().copyright).to_string_lossy().into_owned() }
+    }
+
+    pub fn properties(&self) -> Properties {
+        Properties::from_bits_truncate(self.as_raw().properties)
+    }
+
+    pub fn port_count(&self) -> usize {
+        self.as_raw().port_count as usize
+    }
+
+    /// Returns the type of the given port, or ```PortDescriptor::Invalid``` if the plugin
+    /// declares an inconsistent combination.
+    pub fn port_descriptor(&self, port: usize) -> PortDescriptor {
+        assert!(port < self.port_count(), "port index out of range");
+        let bits = unsafe { *self.as_raw().port_descriptors.add(port) };
+        match bits {
+            x if x == PortDescriptor::AudioInput as i32 => PortDescriptor::AudioInput,
+            x if x == PortDescriptor::AudioOutput as i32 => PortDescriptor::AudioOutput,
+            x if x == PortDescriptor::ControlInput as i32 => PortDescriptor::ControlInput,
+            x if x == PortDescriptor::ControlOutput as i32 => PortDescriptor::ControlOutput,
+            _ => PortDescriptor::Invalid,
+        }
+    }
+
+    pub fn port_name(&self, port: usize) -> String {
+        assert!(port < self.port_count(), "port index out of range");
+        unsafe { CStr::from_ptr(*self.as_raw().port_names.add(port)).to_string_lossy().into_owned() }
+    }
+
+    /// Finds the index of the port with the given name.
+    pub fn port_index(&self, name: &str) -> Option<usize> {
+        (0..self.port_count()).find(|&i| self.port_name(i) == name)
+    }
+
+    /// Finds the control output through which the plugin reports its latency, if it has one.
+    pub fn latency_port(&self) -> Option<usize> {
+        (0..self.port_count()).find(|&i| {
+            self.port_descriptor(i) == PortDescriptor::ControlOutput && self.port_name(i) == LATENCY_PORT
+        })
+    }
+
+    /// The hints attached to the given port.
+    pub fn port_hint(&self, port: usize) -> ControlHint {
+        ControlHint::from_bits_truncate(self.range_hint(port).hint_descriptor)
+    }
+
+    /// The lower and upper bounds of the given port, already multiplied by ```sample_rate``` for
+    /// ports with ```HINT_SAMPLE_RATE```.
+    pub fn port_bounds(&self, port: usize, sample_rate: u64) -> (Option<Data>, Option<Data>) {
+        let hint = self.range_hint(port);
+        let scale = if hint.hint_descriptor & ladspa_h::HINT_SAMPLE_RATE != 0 {
+            sample_rate as Data
+        } else {
+            1.0
+        };
+        let lower = if hint.hint_descriptor & ladspa_h::HINT_BOUNDED_BELOW != 0 {
+            Some(hint.lower_bound * scale)
+        } else {
+            None
+        };
+        let upper = if hint.hint_descriptor & ladspa_h::HINT_BOUNDED_ABOVE != 0 {
+            Some(hint.upper_bound * scale)
+        } else {
+            None
+        };
+        (lower, upper)
+    }
+
+    /// Computes the default value of the given port as described by ```DefaultValue```, or
+    /// ```None``` if the port has no default.
+    pub fn port_default(&self, port: usize, sample_rate: u64) -> Option<Data> {
+        let hint = self.range_hint(port).hint_descriptor;
+        let (lower, upper) = self.port_bounds(port, sample_rate);
+        let log = hint & ladspa_h::HINT_LOGARITHMIC != 0;
+        let between = |w: Data| match (lower, upper) {
+            (Some(lo), Some(hi)) if log && lo > 0.0 && hi > 0.0 => {
+                Some((lo.ln() * (1.0 - w) + hi.ln() * w).exp())
+            }
+            (Some(lo), Some(hi)) => Some(lo * (1.0 - w) + hi * w),
+            _ => None,
+        };
+        match hint & ladspa_h::HINT_DEFAULT_MASK {
+            ladspa_h::HINT_DEFAULT_MINIMUM => lower,
+            ladspa_h::HINT_DEFAULT_LOW => between(0.25),
+            ladspa_h::HINT_DEFAULT_MIDDLE => between(0.5),
+            ladspa_h::HINT_DEFAULT_HIGH => between(0.75),
+            ladspa_h::HINT_DEFAULT_MAXIMUM => upper,
+            ladspa_h::HINT_DEFAULT_0 => Some(0.0),
+            ladspa_h::HINT_DEFAULT_1 => Some(1.0),
+            ladspa_h::HINT_DEFAULT_100 => Some(100.0),
+            ladspa_h::HINT_DEFAULT_440 => Some(440.0),
+            _ => None,
+        }
+    }
+
+    /// Describes the given port as a native ```Port```. Bounds are not multiplied by the sample
+    /// rate, so ports with ```HINT_SAMPLE_RATE``` keep that hint.
+    pub fn port(&self, port: usize) -> Port {
+        let hint = self.range_hint(port);
+        let bits = hint.hint_descriptor;
+        let default = match bits & ladspa_h::HINT_DEFAULT_MASK {
+            ladspa_h::HINT_DEFAULT_MINIMUM => Some(DefaultValue::Minimum),
+            ladspa_h::HINT_DEFAULT_LOW => Some(DefaultValue::Low),
+            ladspa_h::HINT_DEFAULT_MIDDLE => Some(DefaultValue::Middle),
+            ladspa_h::HINT_DEFAULT_HIGH => Some(DefaultValue::High),
+            ladspa_h::HINT_DEFAULT_MAXIMUM => Some(DefaultValue::Maximum),
+            ladspa_h::HINT_DEFAULT_0 => Some(DefaultValue::Value0),
+            ladspa_h::HINT_DEFAULT_1 => Some(DefaultValue::Value1),
+            ladspa_h::HINT_DEFAULT_100 => Some(DefaultValue::Value100),
+            ladspa_h::HINT_DEFAULT_440 => Some(DefaultValue::Value440),
+            _ => None,
+        };
+        let hints = self.port_hint(port);
+        Port {
+            name: self.port_name(port).into(),
+            desc: self.port_descriptor(port),
+            hint: if hints.is_empty() { None } else { Some(hints) },
+            default: default,
+            lower_bound: if bits & ladspa_h::HINT_BOUNDED_BELOW != 0 { Some(hint.lower_bound) } else { None },
+            upper_bound: if bits & ladspa_h::HINT_BOUNDED_ABOVE != 0 { Some(hint.upper_bound) } else { None },
+            aliases: vec![],
+        }
+    }
+
+    fn range_hint(&self, port: usize) -> ladspa_h::PortRangeHint {
+        assert!(port < self.port_count(), "port index out of range");
+        unsafe { *self.as_raw().port_range_hints.add(port) }
+    }
+}
+
+/// A running instance of a plugin, together with the buffers connected to each of its ports.
+///
+/// Audio ports are connected to buffers of ```max_block_size``` samples, and control inputs
+/// start out at their default values.
+pub struct Instance {
+    descriptor: Descriptor,
+    handle: ladspa_h::Handle,
+    sample_rate: u64,
+    max_block_size: usize,
+    audio: Vec<Box<[Data]>>,
+    // The port whose audio buffer each port is connected to, which differs from the port itself
+    // for outputs connected in place.
+    buffers: Vec<usize>,
+    control: Box<[Data]>,
+    active: bool,
+}
+
+unsafe impl Send for Instance { }
+
+impl Instance {
+    /// Instantiates the plugin and connects every port.
+    pub fn new(descriptor: &Descriptor,
+               sample_rate: u64,
+               max_block_size: usize)
+               -> Result<Instance, Error> {
+        let raw = descriptor.as_raw();
+        let handle = (raw.instantiate)(raw, sample_rate as c_ulong);
+        if handle.is_null() {
+            return Err(Error::Instantiate);
+        }
+
+        let ports = descriptor.port_count();
+        let mut instance = Instance {
+            descriptor: descriptor.clone(),
+            handle: handle,
+            sample_rate: sample_rate,
+            max_block_size: max_block_size,
+            audio: (0..ports).map(|i| if descriptor.port_descriptor(i).is_audio() {
+                vec![0.0; max_block_size].into_boxed_slice()
+            } else {
+                Vec::new().into_boxed_slice()
+            }).collect(),
+            buffers: (0..ports).collect(),
+            control: (0..ports).map(|i| {
+                descriptor.port_default(i, sample_rate)
+                    .or(descriptor.port_bounds(i, sample_rate).0)
+                    .unwrap_or(0.0)
+            }).collect::<Vec<_>>().into_boxed_slice(),
+            active: false,
+        };
+
+        for port in 0..ports {
+            let location = if descriptor.port_descriptor(port).is_audio() {
+                instance.audio[port].as_mut_ptr()
+            } else {
+                &mut instance.control[port] as *mut Data
+            };
+            (raw.connect_port)(handle, port as c_ulong, location);
+        }
+        Ok(instance)
+    }
+
+    pub fn descriptor(&self) -> &Descriptor {
+        &self.descriptor
+    }
+
+    pub fn sample_rate(&self) -> u64 {
+        self.sample_rate
+    }
+
+    /// The largest ```sample_count``` that may be passed to ```run```.
+    pub fn max_block_size(&self) -> usize {
+        self.max_block_size
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn activate(&mut self) {
+        if let Some(activate) = self.descriptor.as_raw().activate {
+            activate(self.handle);
+        }
+        self.active = true;
+    }
+
+    pub fn deactivate(&mut self) {
+        if let Some(deactivate) = self.descriptor.as_raw().deactivate {
+            deactivate(self.handle);
+        }
+        self.active = false;
+    }
+
+    /// Runs the plugin on the first ```sample_count``` samples of each audio buffer. Panics if
+    /// ```sample_count``` is larger than ```max_block_size```.
+    pub fn run(&mut self, sample_count: usize) {
+        assert!(sample_count <= self.max_block_size,
+                "Instance::run called with more samples than max_block_size");
+        (self.descriptor.as_raw().run)(self.handle, sample_count as c_ulong);
+    }
+
+    /// Connects an audio output to the buffer of an audio input, so that the plugin processes the
+    /// input in place. ```audio``` and ```audio_mut``` then return the shared buffer for both
+    /// ports. Plugins with ```PROP_INPLACE_BROKEN``` will not work correctly when connected this
+    /// way.
+    pub fn connect_in_place(&mut self, output: usize, input: usize) {
+        assert!(self.descriptor.port_descriptor(output) == PortDescriptor::AudioOutput &&
+                self.descriptor.port_descriptor(input) == PortDescriptor::AudioInput,
+                "Instance::connect_in_place needs an audio output and an audio input");
+        let location = self.audio[input].as_mut_ptr();
+        (self.descriptor.as_raw().connect_port)(self.handle, output as c_ulong, location);
+        self.buffers[output] = input;
+    }
+
+    /// Returns the buffer connected to an audio port. Panics if this is not an audio port.
+    pub fn audio(&self, port: usize) -> &[Data] {
+        if self.descriptor.port_descriptor(port).is_audio() {
+            &self.audio[self.buffers[port]]
+        } else {
+            panic!("Instance::audio called on a non audio port!")
+        }
+    }
+
+    /// Returns the buffer connected to an audio port. Panics if this is not an audio port.
+    pub fn audio_mut(&mut self, port: usize) -> &mut [Data] {
+        if self.descriptor.port_descriptor(port).is_audio() {
+            &mut self.audio[self.buffers[port]]
+        } else {
+            panic!("Instance::audio_mut called on a non audio port!")
+        }
+    }
+
+    /// Returns the current value of a control port.
+    pub fn control(&self, port: usize) -> Data {
+        self.control[port]
+    }
+
+    /// Sets the value of a control port. The plugin sees the new value on the next ```run```.
+    pub fn set_control(&mut self, port: usize, value: Data) {
+        self.control[port] = value;
+    }
+
+    /// The latency most recently reported by the plugin, in samples, or ```None``` if it has no
+    /// latency port. Plugins only report their latency during ```run```.
+    pub fn latency(&self) -> Option<usize> {
+        self.descriptor.latency_port().map(|port| {
+            let value = self.control[port];
+            if value > 0.0 { value.round() as usize } else { 0 }
+        })
+    }
+}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        if self.active {
+            self.deactivate();
+        }
+        (self.descriptor.as_raw().cleanup)(self.handle);
+    }
+}
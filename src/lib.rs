@@ -7,7 +7,7 @@
  * Run ```cargo new my_ladspa_plugin``` to generate a Cargo project for your plugin, then add
  * the following to the generated Cargo.toml:
  *
- * ```
+ * ```toml
  * [dependencies]
  * ladspa = "*"
  *
@@ -34,34 +34,84 @@
  * to equal the directory where you store your plugins.
  */
 
+// Trait objects are written without `dyn`, fields are initialised as `x: x` and the other idioms
+// below are kept as they were when the crate was started, rather than rewritten whenever clippy
+// learns a newer one. bitflags 0.8 expands to the deprecated try! macro.
+#![allow(bare_trait_objects, deprecated)]
+#![allow(clippy::redundant_field_names, clippy::redundant_static_lifetimes, clippy::derivable_impls,
+         clippy::match_like_matches_macro, clippy::needless_borrow, clippy::needless_borrowed_reference,
+         clippy::mem_replace_with_default, clippy::missing_transmute_annotations,
+         clippy::flat_map_identity, clippy::unwrap_or_default, clippy::unnecessary_map_or,
+         clippy::manual_range_contains)]
+
 extern crate libc;
 #[macro_use] extern crate bitflags;
 extern crate vec_map;
+#[cfg(feature = "formats")] extern crate serde;
+#[cfg(feature = "formats")] #[macro_use] extern crate serde_derive;
+#[cfg(feature = "formats")] extern crate serde_json;
+#[cfg(feature = "formats")] extern crate toml;
 
 #[doc(hidden)]
 pub mod ffi;
+pub mod host;
+pub mod graph;
+pub mod preset;
+pub mod oversample;
+pub mod block;
+pub mod bypass;
+pub mod mix;
+pub mod channels;
+pub mod serial;
+pub mod meter;
+pub mod expr;
+pub mod foreign;
+pub mod trace;
+pub mod diagnostics;
+pub mod lifecycle;
+mod buffers;
+mod delay;
+mod resample;
+#[cfg(feature = "formats")]
+pub mod chain;
+#[cfg(feature = "formats")]
+pub mod definitions;
+#[cfg(feature = "testing")]
+pub mod bench;
+#[cfg(feature = "testing")]
+pub mod conformance;
+#[cfg(feature = "testing")]
+pub mod fuzz;
+#[cfg(feature = "testing")]
+pub mod snapshot;
 
 use ffi::ladspa_h;
 
 #[doc(hidden)]
 pub use ffi::ladspa_descriptor;
 
+use std::borrow::Cow;
 use std::cell::{RefCell, RefMut};
 use std::default::Default;
+use std::error;
 
 #[allow(improper_ctypes)]
-extern {
+extern "Rust" {
     /**
      * Your plugin must implement this function.
      * ```get_ladspa_descriptor``` returns a description of a supported plugin for a given plugin
      * index. When the index is out of bounds for the number of plugins supported by your library,
      * you are expected to return ```None```.
      *
+     * It is declared with the Rust ABI, as ```PluginDescriptor``` can't cross a C interface, so
+     * it must be a plain ```pub fn```. Libraries written for version 0.3 declared it as
+     * ```pub extern fn```, which no longer matches and must be changed.
+     *
      * Example no-op implementation:
      *
      * ```rust{.ignore}
      * #[no_mangle]
-     * pub extern fn get_ladspa_descriptor(index: u64) -> Option<ladspa::PluginDescriptor> {
+     * pub fn get_ladspa_descriptor(index: u64) -> Option<ladspa::PluginDescriptor> {
      *     None
      * }
      * ```
@@ -72,7 +122,17 @@ extern {
 /// The data type used internally by LADSPA for audio and control ports.
 pub type Data = f32;
 
+/// Why a plugin couldn't be created or activated. Any error converts into one with ```?```, and
+/// so does a ```String``` or ```&str``` describing the problem, with ```.into()```.
+pub type PluginError = Box<error::Error + Send + Sync>;
+
+/// The type of ```PluginDescriptor::new```.
+pub type NewPlugin = Fn(&PluginDescriptor, u64) -> Result<Box<Plugin + Send>, PluginError> + Send + Sync;
+
 /// Describes the properties of a ```Plugin``` to be exposed as a LADSPA plugin.
+///
+/// Strings in descriptors are ```Cow```s, so they can be written as string literals with
+/// ```.into()``` or generated at runtime, for example from a configuration file.
 pub struct PluginDescriptor {
     /// Unique IDs are an unfortunate remnant of the LADSPA API. During development, it is
     /// suggested to pick one under 1000, but it should be changed before release. More information
@@ -81,42 +141,92 @@ pub struct PluginDescriptor {
 
     /// Plugin labels are expected to be a unique descriptor string for this specific plugin within
     /// the library. Labels are case sensitive and expected not to contain spaces.
-    pub label: &'static str,
+    pub label: Cow<'static, str>,
 
     /// The properties of a plugin describe restrictions and features for it's use. See
     /// documentation for ```Properties``` for info on available options.
     pub properties: Properties,
 
     /// The name of the plugin. This is usually how it is identified.
-    pub name: &'static str,
+    pub name: Cow<'static, str>,
 
     /// The maker of the plugin. Can be empty.
-    pub maker: &'static str,
+    pub maker: Cow<'static, str>,
 
     /// Indicates copyright of the plugin. If no copyright applies, "None" should be used.
-    pub copyright: &'static str,
+    pub copyright: Cow<'static, str>,
 
     /// A vector of input and output ports exposed by the plugin. See the documentation for
     /// ```Port``` for more information.
     pub ports: Vec<Port>,
 
-    /// A function which creates a new instance of the plugin.
+    /// A function which creates a new instance of the plugin. Usually this is just
+    /// ```Box::new(my_new_function)```, but closures may be used to build descriptors at runtime.
+    ///
+    /// A plugin which can't run at the given sample rate, or can't allocate what it needs, returns
+    /// an error. The host is then told that the plugin failed to instantiate, and the error is
+    /// reported through ```diagnostics```.
     ///
     /// Note: Initialization, such as resetting plugin state, should go in ```Plugin::activate``` rather
     /// than here. This should just return a basic instance, ready to be activated.
     /// If your plugin has no internal state, you may optionally not implement ```Plugin::activate```
     /// and do everything here.
-    pub new: fn(desc: &PluginDescriptor, sample_rate: u64) -> Box<Plugin + Send>,
+    pub new: Box<NewPlugin>,
+
+    /// Presets shipped with the plugin. See the ```preset``` module for how hosts can use them.
+    pub presets: Vec<FactoryPreset>,
+}
+
+/// The name of the control output through which hosts such as Ardour expect plugins to report
+/// their latency.
+pub const LATENCY_PORT: &'static str = "latency";
+
+impl PluginDescriptor {
+    /// Appends a ```LATENCY_PORT``` control output, which will be set to the value of
+    /// ```Plugin::latency``` after every call to ```run```.
+    pub fn with_latency_port(mut self) -> PluginDescriptor {
+        self.ports.push(Port {
+            name: LATENCY_PORT.into(),
+            desc: PortDescriptor::ControlOutput,
+            hint: Some(HINT_INTEGER),
+            lower_bound: Some(0.0),
+            ..Default::default()
+        });
+        self
+    }
 }
 
-#[derive(Copy, Clone, Default)]
+// Returns name for a port added by one of the adapters, followed by a number if the plugin being
+// wrapped already has a port with that name.
+fn unused_port_name(ports: &[Port], name: &'static str) -> Cow<'static, str> {
+    if ports.iter().all(|port| port.name != name) {
+        return name.into();
+    }
+    (2..).map(|n| format!("{} {}", name, n))
+        .find(|candidate| ports.iter().all(|port| port.name != **candidate))
+        .unwrap()
+        .into()
+}
+
+#[derive(Clone)]
+/// A named set of control input values built into a plugin.
+pub struct FactoryPreset {
+    /// The name of the preset.
+    pub name: Cow<'static, str>,
+
+    /// Values for control input ports, by port name. Ports which aren't listed keep their default
+    /// values.
+    pub values: Vec<(Cow<'static, str>, Data)>,
+}
+
+#[derive(Clone, Default)]
 /// Represents an input or output to the plugin representing either audio or
 /// control data.
 pub struct Port {
     /// The name of the port. For control ports, this will likely be shown by the host in an
     /// automatically generated GUI next to the control. For audio ports, it is mostly just
     /// for identification in your code but some hosts may display it.
-    pub name: &'static str,
+    pub name: Cow<'static, str>,
 
     /// Describes the type of port: audio or control, input or output.
     pub desc: PortDescriptor,
@@ -132,9 +242,13 @@ pub struct Port {
 
     /// The upper bound of values to accepted by default (the host may ignore this).
     pub upper_bound: Option<Data>,
+
+    /// Names this port was known by in earlier versions of the plugin. Values stored in presets
+    /// under these names are applied to this port.
+    pub aliases: Vec<Cow<'static, str>>,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 /// Represents the 4 types of ports: audio or control, input or output.
 pub enum PortDescriptor {
     Invalid = 0,
@@ -144,6 +258,40 @@ pub enum PortDescriptor {
     ControlOutput = (ladspa_h::PORT_CONTROL | ladspa_h::PORT_OUTPUT) as isize,
 }
 
+impl PortDescriptor {
+    /// Returns true for ```AudioInput``` and ```AudioOutput``` ports.
+    pub fn is_audio(&self) -> bool {
+        match *self {
+            PortDescriptor::AudioInput | PortDescriptor::AudioOutput => true,
+            _ => false,
+        }
+    }
+
+    /// Returns true for ```ControlInput``` and ```ControlOutput``` ports.
+    pub fn is_control(&self) -> bool {
+        match *self {
+            PortDescriptor::ControlInput | PortDescriptor::ControlOutput => true,
+            _ => false,
+        }
+    }
+
+    /// Returns true for ```AudioInput``` and ```ControlInput``` ports.
+    pub fn is_input(&self) -> bool {
+        match *self {
+            PortDescriptor::AudioInput | PortDescriptor::ControlInput => true,
+            _ => false,
+        }
+    }
+
+    /// Returns true for ```AudioOutput``` and ```ControlOutput``` ports.
+    pub fn is_output(&self) -> bool {
+        match *self {
+            PortDescriptor::AudioOutput | PortDescriptor::ControlOutput => true,
+            _ => false,
+        }
+    }
+}
+
 impl Default for PortDescriptor {
     fn default() -> PortDescriptor {
         PortDescriptor::Invalid
@@ -214,7 +362,7 @@ pub enum DefaultValue {
 /// host.
 pub struct PortConnection<'a> {
     /// The port which the data is connected to.
-    pub port: Port,
+    pub port: &'a Port,
 
     /// The data connected to the port. It's usually simpler to use the various unwrap_* functions
     /// than to interface with this directly.
@@ -296,20 +444,16 @@ bitflags!(
         #[doc="Indicates that the plugin is capable of running not only in a conventional host but
         also in a 'hard real-time' environment. To qualify for this the plugin must
         satisfy all of the following:
-
         * The plugin must not use malloc(), free() or other heap memory
         management within its run() function. All new
         memory used in run() must be managed via the stack. These
         restrictions only apply to the run() function.
-
         * The plugin will not attempt to make use of any library
         functions with the exceptions of functions in the ANSI standard C
         and C maths libraries, which the host is expected to provide.
-
         * The plugin will not access files, devices, pipes, sockets, IPC
         or any other mechanism that might result in process or thread
         blocking.
-
         * The plugin will take an amount of time to execute a run()
         call approximately of form (A+B*SampleCount) where A
         and B depend on the machine and host in use. This amount of time
@@ -326,11 +470,21 @@ pub trait Plugin {
     /// The plugin instance must reset all state information dependent
     /// on the history of the plugin instance here.
     /// Will be called before `run` is called for the first time.
-    fn activate(&mut self) { }
+    /// ```lifecycle::state``` tells the first activation apart from later ones.
+    ///
+    /// If this returns an error, the error is reported through ```diagnostics``` and the instance
+    /// writes silence instead of running until the host deactivates it and activates it again.
+    /// ```deactivate``` is not called after a failed activation.
+    fn activate(&mut self) -> Result<(), PluginError> { Ok(()) }
 
     /// Runs the plugin on a number of samples, given the connected ports.
     fn run<'a>(&mut self, sample_count: usize, ports: &[&'a PortConnection<'a>]);
 
     /// Indicates the plugin is no longer live.
     fn deactivate(&mut self) { }
+
+    /// The number of samples by which the output of the plugin lags behind its input. Hosts learn
+    /// this through a control output named ```LATENCY_PORT```, which can be added with
+    /// ```PluginDescriptor::with_latency_port``` and is filled in after every call to ```run```.
+    fn latency(&self) -> usize { 0 }
 }
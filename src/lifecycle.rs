@@ -0,0 +1,82 @@
+//! The lifecycle the LADSPA specification requires hosts to follow for each instance.
+//!
+//! A host must connect every port before calling ```run```, call ```activate``` before the first
+//! ```run``` and again after each ```deactivate```, pair every ```activate``` with a
+//! ```deactivate``` before ```cleanup```, and never call an instance after cleaning it up. Not
+//! every host does. The C interface tracks the state of each instance and deals with the calls
+//! a host makes out of order, so that plugins only ever see the sequence the specification
+//! describes.
+//!
+//! By default the calls are repaired quietly: an instance which is run without being activated
+//! is activated first, and one activated twice is deactivated in between. In strict mode, which
+//! is enabled by the ```strict``` feature, every such call is reported through ```diagnostics```
+//! and refused instead: ```run``` writes silence rather than running the plugin, and repeated
+//! ```activate``` and ```deactivate``` calls are ignored. Either way, an instance cleaned up
+//! while active is deactivated first, ```run``` writes silence if some port has never been
+//! connected, and calls with a null instance are ignored. None of this takes a lock on the audio
+//! thread. Calls on an instance which has been cleaned up can't be caught, and are undefined
+//! behaviour as with any other LADSPA plugin.
+//!
+//! An instance whose ```Plugin::activate``` fails is ```Failed``` rather than ```Active```: it
+//! writes silence until the host deactivates it, and the plugin's ```deactivate``` isn't called.
+
+use std::cell::Cell;
+use std::fmt;
+
+/// Whether calls which break the specification are reported and refused rather than repaired.
+pub const STRICT: bool = cfg!(feature = "strict");
+
+/// Where an instance is in its lifecycle.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum State {
+    /// Instantiated, but never activated.
+    Instantiated,
+
+    /// Activated, so ```run``` may be called.
+    Active,
+
+    /// Deactivated, after having been active.
+    Deactivated,
+
+    /// Activated by the host, but ```Plugin::activate``` failed or panicked, so ```run``` writes
+    /// silence until the host deactivates the instance and activates it again.
+    Failed,
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            State::Instantiated => "instantiated",
+            State::Active => "active",
+            State::Deactivated => "deactivated",
+            State::Failed => "failed",
+        };
+        f.pad(name)
+    }
+}
+
+thread_local! {
+    // The state of the instance whose plugin code is running on this thread, if any.
+    static CURRENT: Cell<Option<State>> = const { Cell::new(None) };
+}
+
+/// The state of the instance being called, while ```Plugin::activate```, ```Plugin::run``` or
+/// ```Plugin::deactivate``` is running through the C interface, and ```None``` otherwise.
+///
+/// During ```activate``` and ```deactivate``` this is the state before the call, so a plugin can
+/// tell its first activation (```Instantiated```) from later ones (```Deactivated```, or
+/// ```Failed``` if a lenient host activates it again after a failure).
+pub fn state() -> Option<State> {
+    CURRENT.with(|current| current.get())
+}
+
+// Sets the state returned by state() while the C interface calls into a plugin.
+#[doc(hidden)]
+pub fn enter(state: State) {
+    CURRENT.with(|current| current.set(Some(state)));
+}
+
+#[doc(hidden)]
+pub fn leave() {
+    CURRENT.with(|current| current.set(None));
+}
@@ -0,0 +1,217 @@
+//! Level meters for driving control output ports.
+//!
+//! Each meter is fed the samples of a block from within ```Plugin::run```, then written to a
+//! ```ControlOutput``` port with ```Meter::write``` or ```Meter::write_db```. Meters allocate only
+//! when they are created, so they can be updated from ```run``` without allocating.
+//!
+//! For example, a gain plugin with a "Peak" output would create a ```Peak``` in
+//! ```PluginDescriptor::new```, reset it in ```activate```, and at the end of ```run``` pass the
+//! block it has just written to ```Peak::process``` before calling ```write_db``` on the port.
+
+use resample::Upsampler;
+use super::{Data, PortConnection};
+
+/// The level reported in decibels for silence.
+pub const MIN_DB: Data = -120.0;
+
+/// Converts a linear amplitude to decibels, reporting ```MIN_DB``` for silence.
+pub fn to_db(value: Data) -> Data {
+    if value > 0.0 { (20.0 * value.log10()).max(MIN_DB) } else { MIN_DB }
+}
+
+/// The common interface of the meters in this module.
+pub trait Meter {
+    /// Feeds a block of samples to the meter.
+    fn process(&mut self, input: &[Data]);
+
+    /// The current reading, as a linear amplitude.
+    fn value(&self) -> Data;
+
+    /// Returns the meter to silence, usually from ```Plugin::activate```.
+    fn reset(&mut self);
+
+    /// Sets a control output to the current reading, as a linear amplitude.
+    fn write<'a>(&self, port: &'a PortConnection<'a>) {
+        **port.unwrap_control_mut() = self.value();
+    }
+
+    /// Sets a control output to the current reading, in decibels.
+    fn write_db<'a>(&self, port: &'a PortConnection<'a>) {
+        **port.unwrap_control_mut() = to_db(self.value());
+    }
+}
+
+/// The largest absolute sample value, held for a while and then falling at a constant rate in
+/// decibels.
+pub struct Peak {
+    value: Data,
+    hold: usize,
+    held: usize,
+    decay: Data,
+}
+
+impl Peak {
+    /// Creates a meter which holds peaks for ```hold``` seconds, then falls by ```decay```
+    /// decibels per second.
+    pub fn new(sample_rate: u64, hold: f32, decay: f32) -> Peak {
+        Peak {
+            value: 0.0,
+            hold: (hold * sample_rate as f32) as usize,
+            held: 0,
+            decay: (10.0 as Data).powf(-decay / 20.0 / sample_rate as Data),
+        }
+    }
+
+    fn update(&mut self, x: Data) {
+        if x >= self.value {
+            self.value = x;
+            self.held = 0;
+        } else if self.held < self.hold {
+            self.held += 1;
+        } else {
+            self.value = (self.value * self.decay).max(x);
+        }
+    }
+}
+
+impl Meter for Peak {
+    fn process(&mut self, input: &[Data]) {
+        for &x in input {
+            self.update(x.abs());
+        }
+    }
+
+    fn value(&self) -> Data {
+        self.value
+    }
+
+    fn reset(&mut self) {
+        self.value = 0.0;
+        self.held = 0;
+    }
+}
+
+/// The root mean square of the most recent samples. Samples which aren't finite are left out.
+pub struct Rms {
+    // The squares of the samples, and their sum, in double precision so that neither overflows
+    // and rounding errors stay small as samples come and go.
+    window: Vec<f64>,
+    pos: usize,
+    sum: f64,
+}
+
+impl Rms {
+    /// Creates a meter averaging over the last ```window``` seconds.
+    pub fn new(sample_rate: u64, window: f32) -> Rms {
+        let len = ((window * sample_rate as f32) as usize).max(1);
+        Rms {
+            window: vec![0.0; len],
+            pos: 0,
+            sum: 0.0,
+        }
+    }
+}
+
+impl Meter for Rms {
+    fn process(&mut self, input: &[Data]) {
+        for &x in input {
+            // A single NaN or infinity would otherwise stay in the sum for good.
+            let square = if x.is_finite() { x as f64 * x as f64 } else { 0.0 };
+            let removed = self.window[self.pos];
+            self.sum += square - removed;
+            self.window[self.pos] = square;
+            self.pos = (self.pos + 1) % self.window.len();
+            // Start again from the exact sum once per window, or sooner if a sample much louder
+            // than the rest has just left and taken the precision of the sum with it.
+            if self.pos == 0 || removed > self.sum * 1e6 {
+                self.sum = self.window.iter().sum();
+            }
+        }
+    }
+
+    fn value(&self) -> Data {
+        (self.sum.max(0.0) / self.window.len() as f64).sqrt() as Data
+    }
+
+    fn reset(&mut self) {
+        for x in &mut self.window {
+            *x = 0.0;
+        }
+        self.sum = 0.0;
+    }
+}
+
+/// A peak meter which also catches peaks between samples, by measuring the signal upsampled by 4
+/// as recommended by ITU-R BS.1770. Readings are delayed slightly by the upsampling filter.
+pub struct TruePeak {
+    peak: Peak,
+    up: Upsampler,
+}
+
+/// How much the signal is upsampled by to find the peaks between samples.
+const TRUE_PEAK_FACTOR: usize = 4;
+
+impl TruePeak {
+    /// Creates a meter which holds peaks for ```hold``` seconds, then falls by ```decay```
+    /// decibels per second.
+    pub fn new(sample_rate: u64, hold: f32, decay: f32) -> TruePeak {
+        TruePeak {
+            peak: Peak::new(sample_rate * TRUE_PEAK_FACTOR as u64, hold, decay),
+            up: Upsampler::new(TRUE_PEAK_FACTOR),
+        }
+    }
+}
+
+impl Meter for TruePeak {
+    fn process(&mut self, input: &[Data]) {
+        let mut upsampled = [0.0; TRUE_PEAK_FACTOR];
+        for x in input {
+            self.up.process(&[*x], &mut upsampled);
+            self.peak.process(&upsampled);
+        }
+    }
+
+    fn value(&self) -> Data {
+        self.peak.value()
+    }
+
+    fn reset(&mut self) {
+        self.peak.reset();
+        self.up.reset();
+    }
+}
+
+/// The gain applied by a dynamics processor such as a compressor, as the smallest gain seen
+/// recently, recovering towards unity at a constant rate in decibels. ```write_db``` reports the
+/// reduction as a negative number of decibels.
+pub struct GainReduction {
+    value: Data,
+    release: Data,
+}
+
+impl GainReduction {
+    /// Creates a meter which recovers by ```release``` decibels per second.
+    pub fn new(sample_rate: u64, release: f32) -> GainReduction {
+        GainReduction {
+            value: 1.0,
+            release: (10.0 as Data).powf(release / 20.0 / sample_rate as Data),
+        }
+    }
+}
+
+impl Meter for GainReduction {
+    /// Feeds the linear gains applied to a block of samples to the meter.
+    fn process(&mut self, gains: &[Data]) {
+        for &gain in gains {
+            self.value = (self.value * self.release).min(1.0).min(gain);
+        }
+    }
+
+    fn value(&self) -> Data {
+        self.value
+    }
+
+    fn reset(&mut self) {
+        self.value = 1.0;
+    }
+}
@@ -0,0 +1,188 @@
+//! Adding dry/wet and output gain controls to a plugin.
+//!
+//! ```wrap``` adds a "Dry/Wet" control input, blending the processed output with the dry input,
+//! and an "Output Gain (dB)" control input applied to the blend. The dry input is delayed by the
+//! latency of the inner plugin so that the two line up. Changes to either control are ramped
+//! over each call to ```run``` to avoid zipper noise.
+//!
+//! If the plugin already has ports with those names, a number is added to the new ones. As with
+//! ```bypass```, each audio output is paired with the audio input at the same position
+//! among the inputs, and outputs without a matching input have no dry signal.
+
+use std::borrow::Cow;
+use std::cmp;
+use std::sync::Arc;
+
+use buffers::PortBuffers;
+use delay::Delay;
+use super::{unused_port_name, Data, DefaultValue, Plugin, PluginDescriptor, PluginError, Port,
+            PortConnection, PortDescriptor};
+
+/// The number of samples processed at a time.
+const BLOCK_SIZE: usize = 256;
+
+/// The lower bound of the "Output Gain (dB)" port.
+pub const MIN_GAIN_DB: Data = -60.0;
+
+/// The upper bound of the "Output Gain (dB)" port.
+pub const MAX_GAIN_DB: Data = 24.0;
+
+/// Wraps a plugin with "Dry/Wet" and "Output Gain (dB)" control inputs. The returned descriptor
+/// has the ports of ```desc```, followed by the two new ports, so it's a different plugin to hosts
+/// and is given its own ```unique_id``` and ```label```.
+pub fn wrap(desc: PluginDescriptor, unique_id: u64, label: Cow<'static, str>) -> PluginDescriptor {
+    let mut ports = desc.ports.clone();
+    ports.push(Port {
+        name: unused_port_name(&desc.ports, "Dry/Wet"),
+        desc: PortDescriptor::ControlInput,
+        default: Some(DefaultValue::Value1),
+        lower_bound: Some(0.0),
+        upper_bound: Some(1.0),
+        ..Default::default()
+    });
+    ports.push(Port {
+        name: unused_port_name(&desc.ports, "Output Gain (dB)"),
+        desc: PortDescriptor::ControlInput,
+        default: Some(DefaultValue::Value0),
+        lower_bound: Some(MIN_GAIN_DB),
+        upper_bound: Some(MAX_GAIN_DB),
+        ..Default::default()
+    });
+    let presets = desc.presets.clone();
+    let inner = Arc::new(desc);
+    PluginDescriptor {
+        unique_id: unique_id,
+        label: label,
+        properties: inner.properties,
+        name: inner.name.clone(),
+        maker: inner.maker.clone(),
+        copyright: inner.copyright.clone(),
+        ports: ports,
+        new: Box::new(move |_: &PluginDescriptor, sample_rate: u64| {
+            Ok(Box::new(Mixed::new(&inner, sample_rate)?) as Box<Plugin + Send>)
+        }),
+        presets: presets,
+    }
+}
+
+/// A plugin with dry/wet and output gain controls. Its ports are those of the inner plugin,
+/// followed by "Dry/Wet" and "Output Gain (dB)".
+///
+/// Most of the time it's simpler to use ```wrap```, but this can also be returned from your own
+/// ```PluginDescriptor::new```.
+pub struct Mixed {
+    plugin: Box<Plugin + Send>,
+    ports: Vec<Port>,
+    buffers: PortBuffers,
+    // The audio input ports, and the one each audio output takes its dry signal from.
+    inputs: Vec<usize>,
+    dry: Vec<Option<usize>>,
+    delays: Vec<Delay>,
+    // The mix and linear gain reached at the end of the last run, which the next run ramps from.
+    // None until the first run after activation, which starts at the requested values.
+    last: Option<(Data, Data)>,
+}
+
+impl Mixed {
+    /// Instantiates ```inner```.
+    pub fn new(inner: &PluginDescriptor, sample_rate: u64) -> Result<Mixed, PluginError> {
+        let inputs: Vec<usize> = (0..inner.ports.len())
+            .filter(|&i| inner.ports[i].desc == PortDescriptor::AudioInput)
+            .collect();
+        let outputs = inner.ports.iter().filter(|p| p.desc == PortDescriptor::AudioOutput).count();
+        Ok(Mixed {
+            plugin: (inner.new)(inner, sample_rate)?,
+            ports: inner.ports.clone(),
+            buffers: PortBuffers::new(&inner.ports, BLOCK_SIZE),
+            dry: (0..outputs).map(|i| if i < inputs.len() { Some(i) } else { None }).collect(),
+            delays: inputs.iter().map(|_| Delay::new()).collect(),
+            inputs: inputs,
+            last: None,
+        })
+    }
+}
+
+impl Plugin for Mixed {
+    fn activate(&mut self) -> Result<(), PluginError> {
+        self.last = None;
+        self.plugin.activate()?;
+        let latency = self.plugin.latency();
+        for delay in &mut self.delays {
+            delay.reset();
+            delay.reserve(latency);
+        }
+        Ok(())
+    }
+
+    fn run<'a>(&mut self, sample_count: usize, ports: &[&'a PortConnection<'a>]) {
+        let n = self.ports.len();
+        let mix = ports[n].unwrap_control().clamp(0.0, 1.0);
+        let gain_db = ports[n + 1].unwrap_control().clamp(MIN_GAIN_DB, MAX_GAIN_DB);
+        let gain = (10.0 as Data).powf(gain_db / 20.0);
+        let latency = self.plugin.latency();
+        for delay in &mut self.delays {
+            delay.set_len(latency);
+        }
+
+        // Ramp linearly from the previous values to the new ones over the whole call.
+        let (start_mix, start_gain) = self.last.unwrap_or((mix, gain));
+        let at = |from: Data, to: Data, k: usize| from + (to - from) * (k + 1) as Data / sample_count as Data;
+
+        let mut offset = 0;
+        while offset < sample_count {
+            let count = cmp::min(BLOCK_SIZE, sample_count - offset);
+            for (i, port) in self.ports.iter().enumerate() {
+                match port.desc {
+                    PortDescriptor::ControlInput => {
+                        self.buffers.set_control(i, *ports[i].unwrap_control());
+                    }
+                    PortDescriptor::AudioInput => {
+                        self.buffers.audio_mut(i)[..count]
+                            .copy_from_slice(&ports[i].unwrap_audio()[offset..offset + count]);
+                    }
+                    _ => {}
+                }
+            }
+
+            self.buffers.run(&mut *self.plugin, count);
+
+            let mut output = 0;
+            for (i, port) in self.ports.iter().enumerate() {
+                match port.desc {
+                    PortDescriptor::ControlOutput => {
+                        **ports[i].unwrap_control_mut() = self.buffers.control(i);
+                    }
+                    PortDescriptor::AudioOutput => {
+                        let dry = self.dry[output]
+                            .map(|input| (&self.delays[input], self.buffers.audio(self.inputs[input])));
+                        let wet = self.buffers.audio(i);
+                        let mut out = ports[i].unwrap_audio_mut();
+                        for (k, y) in out[offset..offset + count].iter_mut().enumerate() {
+                            let dry = dry.map_or(0.0, |(delay, input)| delay.get(input, k));
+                            let mix = at(start_mix, mix, offset + k);
+                            *y = (wet[k] * mix + dry * (1.0 - mix)) * at(start_gain, gain, offset + k);
+                        }
+                        output += 1;
+                    }
+                    _ => {}
+                }
+            }
+
+            for (delay, &port) in self.delays.iter_mut().zip(&self.inputs) {
+                delay.push(&self.buffers.audio(port)[..count]);
+            }
+            offset += count;
+        }
+        if sample_count > 0 {
+            self.last = Some((mix, gain));
+        }
+    }
+
+    fn deactivate(&mut self) {
+        self.plugin.deactivate();
+    }
+
+    fn latency(&self) -> usize {
+        self.plugin.latency()
+    }
+}
@@ -0,0 +1,259 @@
+//! Running a plugin at a multiple of the host's sample rate.
+//!
+//! Nonlinear plugins such as distortion generate harmonics above the Nyquist frequency, which fold
+//! back into the audible range as aliasing. ```wrap``` instantiates a plugin at 2, 4 or 8 times
+//! the host's sample rate, upsampling every audio input and downsampling every audio output with
+//! polyphase FIR filters. The filters delay the signal by ```latency(factor)``` samples at the
+//! host's sample rate.
+
+use std::borrow::Cow;
+use std::cmp;
+use std::sync::Arc;
+
+use buffers::PortBuffers;
+use diagnostics;
+use resample::{Downsampler, Upsampler, TAPS_PER_PHASE};
+use super::{unused_port_name, Data, DefaultValue, Plugin, PluginDescriptor, PluginError, Port,
+            PortConnection, PortDescriptor, HINT_INTEGER};
+
+/// The number of samples processed at a time, at the host's sample rate.
+const BLOCK_SIZE: usize = 256;
+
+/// How much a wrapped plugin is oversampled by.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Factor {
+    /// Always oversample by this factor, which must be 1, 2, 4 or 8 for instances to be created.
+    Fixed(usize),
+
+    /// Adds an integer control input named "Oversampling", numbered if the plugin already has a
+    /// port with that name, ranging from 1 to ```max```, which must be at most 8. Its value is rounded down to a power of two. An instance of the plugin
+    /// is created for every available factor, and switching between them activates the newly
+    /// selected one.
+    Control { max: usize },
+}
+
+/// Returns the latency added by oversampling by ```factor```, in samples at the host's sample
+/// rate.
+pub fn latency(factor: usize) -> usize {
+    if factor > 1 { TAPS_PER_PHASE } else { 0 }
+}
+
+/// Wraps a plugin so that it runs oversampled. The returned descriptor has the ports of ```desc```,
+/// plus the "Oversampling" port when using ```Factor::Control```. Its ports and latency differ
+/// from those of ```desc```, so it's a different plugin to hosts, and is given its own
+/// ```unique_id``` and ```label```.
+pub fn wrap(desc: PluginDescriptor,
+            unique_id: u64,
+            label: Cow<'static, str>,
+            factor: Factor)
+            -> PluginDescriptor {
+    let mut ports = desc.ports.clone();
+    if let Factor::Control { max } = factor {
+        ports.push(Port {
+            name: unused_port_name(&desc.ports, "Oversampling"),
+            desc: PortDescriptor::ControlInput,
+            hint: Some(HINT_INTEGER),
+            default: Some(DefaultValue::Minimum),
+            lower_bound: Some(1.0),
+            upper_bound: Some(max as Data),
+            ..Default::default()
+        });
+    }
+    let presets = desc.presets.clone();
+    let inner = Arc::new(desc);
+    PluginDescriptor {
+        unique_id: unique_id,
+        label: label,
+        properties: inner.properties,
+        name: inner.name.clone(),
+        maker: inner.maker.clone(),
+        copyright: inner.copyright.clone(),
+        ports: ports,
+        new: Box::new(move |_: &PluginDescriptor, sample_rate: u64| {
+            Ok(Box::new(Oversampled::new(&inner, sample_rate, factor)?) as Box<Plugin + Send>)
+        }),
+        presets: presets,
+    }
+}
+
+/// A plugin running at a multiple of the host's sample rate. Its ports are those of the inner
+/// plugin, followed by the "Oversampling" port when using ```Factor::Control```.
+///
+/// Most of the time it's simpler to use ```wrap```, but this can also be returned from your own
+/// ```PluginDescriptor::new```.
+pub struct Oversampled {
+    ports: Vec<Port>,
+    stages: Vec<Stage>,
+    current: usize,
+    factor_port: Option<usize>,
+}
+
+// The inner plugin at a particular oversampling factor.
+struct Stage {
+    factor: usize,
+    plugin: Box<Plugin + Send>,
+    buffers: PortBuffers,
+    up: Vec<Upsampler>,
+    down: Vec<Downsampler>,
+    active: bool,
+    // Set when switching to this stage failed, so that it isn't tried again until the next
+    // activation.
+    failed: bool,
+}
+
+impl Oversampled {
+    /// Instantiates ```inner``` at ```sample_rate``` times each available factor. Fails if the
+    /// factor is out of range, or if ```inner``` fails to instantiate.
+    pub fn new(inner: &PluginDescriptor,
+               sample_rate: u64,
+               factor: Factor)
+               -> Result<Oversampled, PluginError> {
+        let (factors, factor_port) = match factor {
+            Factor::Fixed(factor) => {
+                if !factor.is_power_of_two() || factor > 8 {
+                    return Err(format!("oversampling factor {} isn't 1, 2, 4 or 8", factor).into());
+                }
+                (vec![factor], None)
+            }
+            Factor::Control { max } => {
+                if max < 1 || max > 8 {
+                    return Err(format!("maximum oversampling factor {} isn't 1 to 8", max).into());
+                }
+                ((0..4).map(|x| 1 << x).filter(|&x| x <= max).collect(), Some(inner.ports.len()))
+            }
+        };
+
+        let ports = inner.ports.clone();
+        let stages = factors.into_iter().map(|factor| {
+            let audio = |kind| ports.iter().filter(|p| p.desc == kind).count();
+            Ok(Stage {
+                factor: factor,
+                plugin: (inner.new)(inner, sample_rate * factor as u64)?,
+                buffers: PortBuffers::new(&ports, BLOCK_SIZE * factor),
+                up: (0..audio(PortDescriptor::AudioInput)).map(|_| Upsampler::new(factor)).collect(),
+                down: (0..audio(PortDescriptor::AudioOutput)).map(|_| Downsampler::new(factor)).collect(),
+                active: false,
+                failed: false,
+            })
+        }).collect::<Result<_, PluginError>>()?;
+
+        Ok(Oversampled {
+            ports: ports,
+            stages: stages,
+            current: 0,
+            factor_port: factor_port,
+        })
+    }
+
+    /// The factor currently in use.
+    pub fn factor(&self) -> usize {
+        self.stages[self.current].factor
+    }
+
+    // Switches to the largest available factor not exceeding value.
+    fn select(&mut self, value: Data) {
+        let value = if value >= 1.0 { value as u64 } else { 1 };
+        let idx = cmp::min(63 - value.leading_zeros() as usize, self.stages.len() - 1);
+        if idx != self.current && !self.stages[idx].failed {
+            if self.stages[self.current].active {
+                // The new stage is activated first, so that the current one keeps running if it
+                // fails.
+                if let Err(err) = self.stages[idx].activate() {
+                    let msg = format!("can't switch to {}x oversampling, so staying at {}x: {}",
+                                      self.stages[idx].factor,
+                                      self.stages[self.current].factor,
+                                      err);
+                    diagnostics::report(&msg);
+                    self.stages[idx].failed = true;
+                    return;
+                }
+                self.stages[self.current].deactivate();
+            }
+            self.current = idx;
+        }
+    }
+}
+
+impl Stage {
+    fn activate(&mut self) -> Result<(), PluginError> {
+        for up in &mut self.up {
+            up.reset();
+        }
+        for down in &mut self.down {
+            down.reset();
+        }
+        self.plugin.activate()?;
+        self.active = true;
+        Ok(())
+    }
+
+    fn deactivate(&mut self) {
+        self.plugin.deactivate();
+        self.active = false;
+    }
+}
+
+impl Plugin for Oversampled {
+    fn activate(&mut self) -> Result<(), PluginError> {
+        for stage in &mut self.stages {
+            stage.failed = false;
+        }
+        self.stages[self.current].activate()
+    }
+
+    fn run<'a>(&mut self, sample_count: usize, ports: &[&'a PortConnection<'a>]) {
+        if let Some(port) = self.factor_port {
+            self.select(*ports[port].unwrap_control());
+        }
+        let stage = &mut self.stages[self.current];
+        let factor = stage.factor;
+
+        let mut offset = 0;
+        while offset < sample_count {
+            let count = cmp::min(BLOCK_SIZE, sample_count - offset);
+            let (mut inputs, mut outputs) = (0, 0);
+            for (i, port) in self.ports.iter().enumerate() {
+                match port.desc {
+                    PortDescriptor::ControlInput => {
+                        stage.buffers.set_control(i, *ports[i].unwrap_control());
+                    }
+                    PortDescriptor::AudioInput => {
+                        stage.up[inputs].process(&ports[i].unwrap_audio()[offset..offset + count],
+                                                 &mut stage.buffers.audio_mut(i)[..count * factor]);
+                        inputs += 1;
+                    }
+                    _ => {}
+                }
+            }
+
+            stage.buffers.run(&mut *stage.plugin, count * factor);
+
+            for (i, port) in self.ports.iter().enumerate() {
+                match port.desc {
+                    PortDescriptor::ControlOutput => {
+                        **ports[i].unwrap_control_mut() = stage.buffers.control(i);
+                    }
+                    PortDescriptor::AudioOutput => {
+                        stage.down[outputs].process(&stage.buffers.audio(i)[..count * factor],
+                                                    &mut ports[i].unwrap_audio_mut()[offset..offset + count]);
+                        outputs += 1;
+                    }
+                    _ => {}
+                }
+            }
+            offset += count;
+        }
+    }
+
+    fn deactivate(&mut self) {
+        if self.stages[self.current].active {
+            self.stages[self.current].deactivate();
+        }
+    }
+
+    fn latency(&self) -> usize {
+        let stage = &self.stages[self.current];
+        // The inner plugin's latency is at the oversampled rate.
+        latency(stage.factor) + (stage.plugin.latency() + stage.factor / 2) / stage.factor
+    }
+}
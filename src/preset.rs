@@ -0,0 +1,194 @@
+//! Saving and restoring the control input values of hosted plugins.
+//!
+//! A ```Preset``` stores values by port name under the ```unique_id``` and ```label``` of the
+//! plugin it was captured from, so it keeps working when later versions of the plugin add,
+//! remove or reorder ports. Presets are matched to plugins by unique ID, as labels are only
+//! unique within one library. Ports renamed in a later version can list their old names in
+//! ```Port::aliases```. With the ```formats``` feature, presets can be read from and written to
+//! TOML or JSON files.
+
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+#[cfg(feature = "formats")]
+use std::fs::File;
+#[cfg(feature = "formats")]
+use std::io::{Read, Write};
+#[cfg(feature = "formats")]
+use std::path::Path;
+
+#[cfg(feature = "formats")]
+use serde_json;
+#[cfg(feature = "formats")]
+use toml;
+
+use host::{Descriptor, Instance};
+use super::{Data, FactoryPreset, PortDescriptor};
+
+/// The ways loading or applying a preset can fail.
+#[derive(Debug)]
+pub enum Error {
+    /// The preset was captured from a plugin with a different ```unique_id```.
+    WrongPlugin,
+    /// The preset file could not be read, written or parsed.
+    Format(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::WrongPlugin => write!(f, "preset belongs to a different plugin"),
+            Error::Format(ref msg) => write!(f, "invalid preset: {}", msg),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// A named set of control input values for a particular plugin.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "formats", derive(Serialize, Deserialize))]
+pub struct Preset {
+    pub name: String,
+    pub unique_id: u64,
+    pub label: String,
+
+    /// Control input values, by port name.
+    pub values: BTreeMap<String, Data>,
+}
+
+impl Preset {
+    /// Records the current value of every control input of the instance.
+    pub fn capture(instance: &Instance, name: &str) -> Preset {
+        let desc = instance.descriptor();
+        Preset {
+            name: name.to_string(),
+            unique_id: desc.unique_id(),
+            label: desc.label(),
+            values: control_inputs(desc)
+                .into_iter()
+                .map(|port| (desc.port_name(port), instance.control(port)))
+                .collect(),
+        }
+    }
+
+    /// Converts a preset built into a plugin.
+    pub fn from_factory(descriptor: &Descriptor, preset: &FactoryPreset) -> Preset {
+        Preset {
+            name: preset.name.to_string(),
+            unique_id: descriptor.unique_id(),
+            label: descriptor.label(),
+            values: preset.values.iter().map(|&(ref name, value)| (name.to_string(), value)).collect(),
+        }
+    }
+
+    /// Returns the presets built into a plugin. Only native plugins can declare factory presets.
+    pub fn factory(descriptor: &Descriptor) -> Vec<Preset> {
+        descriptor.native()
+            .map(|native| native.presets.iter().map(|p| Preset::from_factory(descriptor, p)).collect())
+            .unwrap_or_else(Vec::new)
+    }
+
+    /// Returns true if the preset was captured from the plugin, or from another version of it.
+    /// The unique ID must match, while the label may have changed between versions.
+    pub fn matches(&self, descriptor: &Descriptor) -> bool {
+        self.unique_id == descriptor.unique_id()
+    }
+
+    /// Finds the plugin the preset was captured from. If several share its unique ID, the one
+    /// with the same label is preferred.
+    pub fn find<'a>(&self, descriptors: &'a [Descriptor]) -> Option<&'a Descriptor> {
+        descriptors.iter()
+            .find(|desc| self.matches(desc) && desc.label() == self.label)
+            .or_else(|| descriptors.iter().find(|desc| self.matches(desc)))
+    }
+
+    /// Adapts the preset to the ports of the given plugin. Values stored under an alias of a port
+    /// are moved to its current name, and values for ports which no longer exist are dropped.
+    pub fn migrate(&self, descriptor: &Descriptor) -> Preset {
+        let aliases = descriptor.native().map(|native| &native.ports[..]).unwrap_or(&[]);
+        let mut values = BTreeMap::new();
+        for port in control_inputs(descriptor) {
+            let name = descriptor.port_name(port);
+            let value = self.values.get(&name).or_else(|| {
+                aliases.get(port)
+                    .and_then(|p| p.aliases.iter().filter_map(|alias| self.values.get(&**alias)).next())
+            });
+            if let Some(&value) = value {
+                values.insert(name, value);
+            }
+        }
+        Preset {
+            name: self.name.clone(),
+            unique_id: descriptor.unique_id(),
+            label: descriptor.label(),
+            values: values,
+        }
+    }
+
+    /// Sets the control inputs of the instance to the values in the preset. Ports which the
+    /// preset doesn't mention keep their current values.
+    pub fn apply(&self, instance: &mut Instance) -> Result<(), Error> {
+        if !self.matches(instance.descriptor()) {
+            return Err(Error::WrongPlugin);
+        }
+        let migrated = self.migrate(instance.descriptor());
+        for (name, &value) in &migrated.values {
+            let port = instance.descriptor().port_index(name).unwrap();
+            instance.set_control(port, value);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "formats")]
+impl Preset {
+    pub fn from_toml(text: &str) -> Result<Preset, Error> {
+        toml::from_str(text).map_err(|e| Error::Format(e.to_string()))
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).unwrap()
+    }
+
+    pub fn from_json(text: &str) -> Result<Preset, Error> {
+        serde_json::from_str(text).map_err(|e| Error::Format(e.to_string()))
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// Reads a preset file, which is parsed as JSON if it has a ```.json``` extension and TOML
+    /// otherwise.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Preset, Error> {
+        let path = path.as_ref();
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .map_err(|e| Error::Format(format!("{}: {}", path.display(), e)))?;
+        if is_json(path) {
+            Preset::from_json(&text)
+        } else {
+            Preset::from_toml(&text)
+        }
+    }
+
+    /// Writes the preset to a file, as JSON if it has a ```.json``` extension and TOML otherwise.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        let text = if is_json(path) { self.to_json() } else { self.to_toml() };
+        File::create(path)
+            .and_then(|mut f| f.write_all(text.as_bytes()))
+            .map_err(|e| Error::Format(format!("{}: {}", path.display(), e)))
+    }
+}
+
+#[cfg(feature = "formats")]
+fn is_json(path: &Path) -> bool {
+    path.extension().map_or(false, |ext| ext == "json")
+}
+
+fn control_inputs(desc: &Descriptor) -> Vec<usize> {
+    (0..desc.port_count()).filter(|&i| desc.port_descriptor(i) == PortDescriptor::ControlInput).collect()
+}
@@ -0,0 +1,130 @@
+// Polyphase FIR filters for changing the sample rate by a power of two, used by the oversampling
+// adapter and the true-peak meter.
+
+use std::f64::consts::PI;
+
+use super::Data;
+
+// The number of samples (at the original sample rate) spanned by each filter.
+pub const TAPS_PER_PHASE: usize = 32;
+
+// Designs a windowed sinc lowpass filter with a cutoff at the original Nyquist frequency and a
+// group delay of TAPS_PER_PHASE / 2 samples at the original rate. The taps sum to one.
+fn lowpass(factor: usize) -> Vec<Data> {
+    let len = TAPS_PER_PHASE * factor + 1;
+    let center = (len - 1) as f64 / 2.0;
+    let cutoff = 0.5 / factor as f64;
+    let taps: Vec<f64> = (0..len).map(|i| {
+        let x = i as f64 - center;
+        let sinc = if x == 0.0 { 1.0 } else { (2.0 * PI * cutoff * x).sin() / (2.0 * PI * cutoff * x) };
+        // Blackman window
+        let w = 2.0 * PI * i as f64 / (len - 1) as f64;
+        sinc * (0.42 - 0.5 * w.cos() + 0.08 * (2.0 * w).cos())
+    }).collect();
+    let sum: f64 = taps.iter().sum();
+    taps.iter().map(|&x| (x / sum) as Data).collect()
+}
+
+// A delay line which can be read as a contiguous slice, newest sample first.
+struct History {
+    buf: Vec<Data>,
+    pos: usize,
+}
+
+impl History {
+    fn new(len: usize) -> History {
+        History {
+            buf: vec![0.0; len * 2],
+            pos: 0,
+        }
+    }
+
+    fn push(&mut self, x: Data) {
+        let len = self.buf.len() / 2;
+        self.pos = (self.pos + len - 1) % len;
+        self.buf[self.pos] = x;
+        self.buf[self.pos + len] = x;
+    }
+
+    fn get(&self) -> &[Data] {
+        &self.buf[self.pos..self.pos + self.buf.len() / 2]
+    }
+
+    fn reset(&mut self) {
+        for x in &mut self.buf {
+            *x = 0.0;
+        }
+    }
+}
+
+pub struct Upsampler {
+    // phases[p][k] is the tap applied to the kth most recent input for output phase p.
+    phases: Vec<Vec<Data>>,
+    history: History,
+}
+
+impl Upsampler {
+    pub fn new(factor: usize) -> Upsampler {
+        let taps = lowpass(factor);
+        Upsampler {
+            phases: (0..factor)
+                .map(|p| taps.iter().skip(p).step_by(factor).map(|&x| x * factor as Data).collect())
+                .collect(),
+            history: History::new(TAPS_PER_PHASE + 1),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.history.reset();
+    }
+
+    pub fn process(&mut self, input: &[Data], output: &mut [Data]) {
+        let factor = self.phases.len();
+        if factor == 1 {
+            output.copy_from_slice(input);
+            return;
+        }
+        for (x, out) in input.iter().zip(output.chunks_mut(factor)) {
+            self.history.push(*x);
+            let history = self.history.get();
+            for (y, phase) in out.iter_mut().zip(&self.phases) {
+                *y = phase.iter().zip(history).map(|(a, b)| a * b).sum();
+            }
+        }
+    }
+}
+
+pub struct Downsampler {
+    factor: usize,
+    taps: Vec<Data>,
+    history: History,
+}
+
+impl Downsampler {
+    pub fn new(factor: usize) -> Downsampler {
+        let taps = lowpass(factor);
+        Downsampler {
+            factor: factor,
+            history: History::new(taps.len()),
+            taps: taps,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.history.reset();
+    }
+
+    pub fn process(&mut self, input: &[Data], output: &mut [Data]) {
+        if self.factor == 1 {
+            output.copy_from_slice(input);
+            return;
+        }
+        for (chunk, y) in input.chunks(self.factor).zip(output.iter_mut()) {
+            self.history.push(chunk[0]);
+            *y = self.taps.iter().zip(self.history.get()).map(|(a, b)| a * b).sum();
+            for &x in &chunk[1..] {
+                self.history.push(x);
+            }
+        }
+    }
+}
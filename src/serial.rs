@@ -0,0 +1,215 @@
+//! Combining several plugins into one which runs them in series.
+//!
+//! ```compose``` builds a single ```PluginDescriptor```, which can be returned from
+//! ```get_ladspa_descriptor``` like any other, out of a list of stages such as an EQ, a compressor
+//! and a gain. The audio inputs of the first stage and the audio outputs of the last stage become
+//! the plugin's audio ports. Every other audio output feeds the audio input at the same position
+//! in the next stage through an internal buffer. The control ports of every stage are exposed
+//! with the stage's prefix added to their names, except for ```LATENCY_PORT```s: if any stage
+//! has one, the combined plugin has a single latency port reporting their sum.
+
+use std::borrow::Cow;
+use std::cmp;
+use std::sync::Arc;
+
+use buffers::PortBuffers;
+use super::{Plugin, PluginDescriptor, PluginError, Port, PortConnection, PortDescriptor,
+            LATENCY_PORT, PROP_HARD_REALTIME_CAPABLE, PROP_NONE, PROP_REALTIME};
+
+/// The number of samples processed at a time.
+const BLOCK_SIZE: usize = 256;
+
+/// A plugin to run as part of a combined plugin.
+pub struct Stage {
+    /// Added to the start of the names of the plugin's control ports, separated by a space.
+    pub prefix: Cow<'static, str>,
+    pub plugin: PluginDescriptor,
+}
+
+/// Combines the stages into a single plugin, which runs each of them in turn. Panics unless each
+/// stage has as many audio outputs as the next has audio inputs, and its own prefix so that the
+/// names of the control ports stay distinct.
+pub fn compose(unique_id: u64,
+               label: Cow<'static, str>,
+               name: Cow<'static, str>,
+               maker: Cow<'static, str>,
+               copyright: Cow<'static, str>,
+               stages: Vec<Stage>)
+               -> PluginDescriptor {
+    assert!(!stages.is_empty(), "a serial plugin needs at least one stage");
+    for (idx, stage) in stages.iter().enumerate() {
+        assert!(stages[..idx].iter().all(|earlier| earlier.prefix != stage.prefix),
+                "more than one stage has the prefix \"{}\"",
+                stage.prefix);
+    }
+    for pair in stages.windows(2) {
+        let outputs = pair[0].plugin.ports.iter().filter(|p| p.desc == PortDescriptor::AudioOutput);
+        let inputs = pair[1].plugin.ports.iter().filter(|p| p.desc == PortDescriptor::AudioInput);
+        assert!(outputs.count() == inputs.count(),
+                "stage \"{}\" has a different number of audio outputs than \"{}\" has inputs",
+                pair[0].prefix,
+                pair[1].prefix);
+    }
+
+    let mut ports = Vec::new();
+    let mut maps = Vec::new();
+    for (idx, stage) in stages.iter().enumerate() {
+        let mut map = Vec::new();
+        for port in &stage.plugin.ports {
+            let exposed = match port.desc {
+                PortDescriptor::AudioInput => idx == 0,
+                PortDescriptor::AudioOutput => idx == stages.len() - 1,
+                PortDescriptor::ControlOutput => port.name != LATENCY_PORT,
+                _ => true,
+            };
+            if !exposed {
+                map.push(None);
+                continue;
+            }
+            map.push(Some(ports.len()));
+            ports.push(if port.desc.is_control() {
+                Port {
+                    name: format!("{} {}", stage.prefix, port.name).into(),
+                    // Old names are only meaningful within the stage.
+                    aliases: vec![],
+                    ..port.clone()
+                }
+            } else {
+                port.clone()
+            });
+        }
+        maps.push(map);
+    }
+
+    let mut properties = PROP_NONE;
+    if stages.iter().any(|stage| stage.plugin.properties.contains(PROP_REALTIME)) {
+        properties.insert(PROP_REALTIME);
+    }
+    if stages.iter().all(|stage| stage.plugin.properties.contains(PROP_HARD_REALTIME_CAPABLE)) {
+        properties.insert(PROP_HARD_REALTIME_CAPABLE);
+    }
+    let has_latency = stages.iter().any(|stage| {
+        stage.plugin.ports.iter().any(|p| p.desc == PortDescriptor::ControlOutput && p.name == LATENCY_PORT)
+    });
+    let stages: Vec<(Arc<PluginDescriptor>, Vec<Option<usize>>)> =
+        stages.into_iter().map(|stage| Arc::new(stage.plugin)).zip(maps).collect();
+    let desc = PluginDescriptor {
+        unique_id: unique_id,
+        label: label,
+        properties: properties,
+        name: name,
+        maker: maker,
+        copyright: copyright,
+        ports: ports,
+        new: Box::new(move |_: &PluginDescriptor, sample_rate: u64| {
+            Ok(Box::new(Serial::new(&stages, sample_rate)?) as Box<Plugin + Send>)
+        }),
+        presets: vec![],
+    };
+    if has_latency { desc.with_latency_port() } else { desc }
+}
+
+/// The plugin created by the descriptors returned from ```compose```.
+pub struct Serial {
+    stages: Vec<Running>,
+}
+
+// A stage which has been instantiated.
+struct Running {
+    plugin: Box<Plugin + Send>,
+    ports: Vec<Port>,
+    buffers: PortBuffers,
+    // The port of the combined plugin each port is exposed as, if any.
+    map: Vec<Option<usize>>,
+    audio_inputs: Vec<usize>,
+    audio_outputs: Vec<usize>,
+}
+
+impl Serial {
+    fn new(stages: &[(Arc<PluginDescriptor>, Vec<Option<usize>>)],
+           sample_rate: u64)
+           -> Result<Serial, PluginError> {
+        Ok(Serial {
+            stages: stages.iter().map(|&(ref desc, ref map)| {
+                let audio = |kind| (0..desc.ports.len()).filter(|&i| desc.ports[i].desc == kind).collect();
+                Ok(Running {
+                    plugin: (desc.new)(desc, sample_rate)?,
+                    ports: desc.ports.clone(),
+                    buffers: PortBuffers::new(&desc.ports, BLOCK_SIZE),
+                    map: map.clone(),
+                    audio_inputs: audio(PortDescriptor::AudioInput),
+                    audio_outputs: audio(PortDescriptor::AudioOutput),
+                })
+            }).collect::<Result<_, PluginError>>()?,
+        })
+    }
+}
+
+impl Plugin for Serial {
+    fn activate(&mut self) -> Result<(), PluginError> {
+        for i in 0..self.stages.len() {
+            if let Err(err) = self.stages[i].plugin.activate() {
+                // Only the stages which were activated are deactivated.
+                for stage in &mut self.stages[..i] {
+                    stage.plugin.deactivate();
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    fn run<'a>(&mut self, sample_count: usize, ports: &[&'a PortConnection<'a>]) {
+        let mut offset = 0;
+        while offset < sample_count {
+            let count = cmp::min(BLOCK_SIZE, sample_count - offset);
+            for idx in 0..self.stages.len() {
+                let (done, rest) = self.stages.split_at_mut(idx);
+                let stage = &mut rest[0];
+                for (i, port) in stage.ports.iter().enumerate() {
+                    match (port.desc, stage.map[i]) {
+                        (PortDescriptor::ControlInput, Some(outer)) => {
+                            stage.buffers.set_control(i, *ports[outer].unwrap_control());
+                        }
+                        (PortDescriptor::AudioInput, Some(outer)) => {
+                            stage.buffers.audio_mut(i)[..count]
+                                .copy_from_slice(&ports[outer].unwrap_audio()[offset..offset + count]);
+                        }
+                        _ => {}
+                    }
+                }
+                if let Some(prev) = done.last() {
+                    for (&from, &to) in prev.audio_outputs.iter().zip(&stage.audio_inputs) {
+                        stage.buffers.audio_mut(to)[..count].copy_from_slice(&prev.buffers.audio(from)[..count]);
+                    }
+                }
+
+                stage.buffers.run(&mut *stage.plugin, count);
+
+                for (i, port) in stage.ports.iter().enumerate() {
+                    match (port.desc, stage.map[i]) {
+                        (PortDescriptor::ControlOutput, Some(outer)) => {
+                            **ports[outer].unwrap_control_mut() = stage.buffers.control(i);
+                        }
+                        (PortDescriptor::AudioOutput, Some(outer)) => {
+                            ports[outer].unwrap_audio_mut()[offset..offset + count]
+                                .copy_from_slice(&stage.buffers.audio(i)[..count]);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            offset += count;
+        }
+    }
+
+    fn deactivate(&mut self) {
+        for stage in &mut self.stages {
+            stage.plugin.deactivate();
+        }
+    }
+
+    fn latency(&self) -> usize {
+        self.stages.iter().map(|stage| stage.plugin.latency()).sum()
+    }
+}
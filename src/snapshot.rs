@@ -0,0 +1,504 @@
+//! Comparing a plugin's output with reference recordings, to catch changes in its sound.
+//!
+//! ```assert_snapshot``` renders a plugin through the C interface, with the input signal and
+//! control values described by a ```Render```, and compares every audio output with a reference
+//! recording stored as a 32-bit float WAV file. How far the output may stray from the reference
+//! is set by a ```Tolerance```, as a maximum absolute error per sample, a minimum
+//! signal-to-noise ratio or a maximum difference between the two spectra.
+//!
+//! Reference recordings are written by running the tests with the ```LADSPA_BLESS```
+//! environment variable set to 1, which replaces every recording with the current output. Check
+//! the new recordings in alongside the change that altered the sound.
+//!
+//! ```rust,ignore
+//! #[test]
+//! fn delay_sound() {
+//!     ladspa::snapshot::assert_snapshot(
+//!         delay_descriptor(),
+//!         concat!(env!("CARGO_MANIFEST_DIR"), "/tests/snapshots/delay.wav"),
+//!         &Render { controls: vec![("Delay (seconds)".into(), 0.01)], ..Default::default() },
+//!         &Tolerance::default());
+//! }
+//! ```
+//!
+//! This module needs the ```testing``` feature.
+
+use std::borrow::Cow;
+use std::cmp;
+use std::env;
+use std::error;
+use std::f64::consts::PI;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use ffi;
+use host::{Descriptor, Instance};
+use super::{Data, PluginDescriptor, PortDescriptor};
+
+/// The environment variable which makes ```check``` write reference recordings.
+pub const BLESS_VAR: &str = "LADSPA_BLESS";
+
+// The length of the windows spectra are measured over, and how far apart they are.
+const WINDOW: usize = 1024;
+const HOP: usize = WINDOW / 2;
+
+// Spectral differences in bins quieter than this, relative to the loudest bin, are ignored.
+const SPECTRAL_FLOOR: f64 = 1e-8;
+
+/// The ways rendering or comparing a snapshot can fail.
+#[derive(Debug)]
+pub enum Error {
+    /// There is no reference recording at this path.
+    Missing(PathBuf),
+    /// A reference recording could not be read or written.
+    File(String),
+    /// The plugin panicked.
+    Panic(String),
+    /// The plugin couldn't be instantiated.
+    Instantiate,
+    /// A control value was given for a port the plugin doesn't have.
+    UnknownPort(String),
+    /// The output doesn't match the reference recording.
+    Mismatch(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Missing(ref path) => {
+                write!(f, "{} does not exist, run with {}=1 to create it", path.display(), BLESS_VAR)
+            }
+            Error::File(ref msg) => write!(f, "{}", msg),
+            Error::Panic(ref msg) => write!(f, "{}", msg),
+            Error::Instantiate => write!(f, "failed to instantiate"),
+            Error::UnknownPort(ref name) => write!(f, "no control input named \"{}\"", name),
+            Error::Mismatch(ref msg) => write!(f, "output differs from the reference: {}", msg),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// The signal fed to every audio input.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Signal {
+    Silence,
+
+    /// A single sample of 1 at the start.
+    Impulse,
+
+    /// White noise between -0.5 and 0.5, from the given seed. Each input gets a different
+    /// sequence.
+    Noise(u32),
+
+    /// A sine wave of amplitude 0.5 at the given frequency in Hz.
+    Sine(Data),
+
+    /// A sine wave of amplitude 0.5 sweeping exponentially between two frequencies in Hz over
+    /// the whole rendering.
+    Sweep(Data, Data),
+}
+
+/// How a plugin is rendered for a snapshot.
+#[derive(Clone, Debug)]
+pub struct Render {
+    pub sample_rate: u64,
+
+    /// The number of samples rendered.
+    pub length: usize,
+
+    /// The largest number of samples passed to each ```run```.
+    pub block_size: usize,
+
+    pub input: Signal,
+
+    /// Values of control inputs, by port name. Other control inputs are left at their defaults.
+    pub controls: Vec<(Cow<'static, str>, Data)>,
+}
+
+impl Default for Render {
+    /// One second of noise at 48kHz, in blocks of 256 samples, with the controls at their
+    /// defaults.
+    fn default() -> Render {
+        Render {
+            sample_rate: 48000,
+            length: 48000,
+            block_size: 256,
+            input: Signal::Noise(1),
+            controls: vec![],
+        }
+    }
+}
+
+/// How far output may differ from the reference recording. Each measure is applied to every
+/// audio output separately, and ```None``` disables it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Tolerance {
+    /// The largest allowed absolute difference between any two samples.
+    pub max_error: Option<Data>,
+
+    /// The smallest allowed ratio of the reference's power to that of the difference, in dB.
+    pub min_snr: Option<f64>,
+
+    /// The largest allowed difference in level between the average spectra of the output and
+    /// the reference at any frequency, in dB. Frequencies more than 80dB below the loudest are
+    /// ignored.
+    pub max_spectral_difference: Option<f64>,
+}
+
+impl Default for Tolerance {
+    /// Allows only rounding errors, with a ```max_error``` of 1e-6.
+    fn default() -> Tolerance {
+        Tolerance {
+            max_error: Some(1e-6),
+            min_snr: None,
+            max_spectral_difference: None,
+        }
+    }
+}
+
+/// Renders a plugin, returning the samples written to each audio output.
+pub fn render(desc: &Descriptor, render: &Render) -> Result<Vec<Vec<Data>>, Error> {
+    let block_size = cmp::max(render.block_size, 1);
+    let instance = Instance::new(desc, render.sample_rate, block_size);
+    suppressed()?;
+    let mut instance = instance.map_err(|_| Error::Instantiate)?;
+    for &(ref name, value) in &render.controls {
+        match desc.port_index(name).filter(|&i| desc.port_descriptor(i) == PortDescriptor::ControlInput) {
+            Some(port) => instance.set_control(port, value),
+            None => return Err(Error::UnknownPort(name.to_string())),
+        }
+    }
+
+    let ports = |kind| (0..desc.port_count()).filter(|&i| desc.port_descriptor(i) == kind).collect::<Vec<_>>();
+    let inputs = ports(PortDescriptor::AudioInput);
+    let outputs = ports(PortDescriptor::AudioOutput);
+    let mut signals: Vec<Generator> = (0..inputs.len())
+        .map(|i| Generator::new(render.input, i as u32, render.sample_rate, render.length))
+        .collect();
+    let mut rendered = vec![Vec::with_capacity(render.length); outputs.len()];
+
+    instance.activate();
+    suppressed()?;
+    let mut offset = 0;
+    while offset < render.length {
+        let n = cmp::min(block_size, render.length - offset);
+        for (&port, signal) in inputs.iter().zip(&mut signals) {
+            for x in &mut instance.audio_mut(port)[..n] {
+                *x = signal.next();
+            }
+        }
+        instance.run(n);
+        suppressed()?;
+        for (&port, samples) in outputs.iter().zip(&mut rendered) {
+            samples.extend_from_slice(&instance.audio(port)[..n]);
+        }
+        offset += n;
+    }
+    drop(instance);
+    suppressed()?;
+    Ok(rendered)
+}
+
+/// Compares rendered outputs with a reference recording.
+pub fn compare(expected: &[Vec<Data>],
+               actual: &[Vec<Data>],
+               sample_rate: u64,
+               tolerance: &Tolerance)
+               -> Result<(), Error> {
+    if expected.len() != actual.len() {
+        return Err(Error::Mismatch(format!("{} outputs instead of {}", actual.len(), expected.len())));
+    }
+    for (channel, (expected, actual)) in expected.iter().zip(actual).enumerate() {
+        let mismatch = |msg: String| Err(Error::Mismatch(format!("output {}: {}", channel + 1, msg)));
+        if expected.len() != actual.len() {
+            return mismatch(format!("{} samples instead of {}", actual.len(), expected.len()));
+        }
+        if let Some(max_error) = tolerance.max_error {
+            let worst = expected.iter()
+                .zip(actual)
+                .map(|(&x, &y)| if x.is_nan() && y.is_nan() { 0.0 } else { not_nan((x - y).abs() as f64) })
+                .enumerate()
+                .find(|&(_, error)| error > max_error as f64);
+            if let Some((k, _)) = worst {
+                return mismatch(format!("sample {} is {} instead of {}", k, actual[k], expected[k]));
+            }
+        }
+        if let Some(min_snr) = tolerance.min_snr {
+            let signal: f64 = expected.iter().map(|&x| (x as f64).powi(2)).sum();
+            let noise: f64 = expected.iter().zip(actual).map(|(&x, &y)| (x as f64 - y as f64).powi(2)).sum();
+            let snr = 10.0 * (signal / noise).log10();
+            if noise != 0.0 && (snr.is_nan() || snr < min_snr) {
+                return mismatch(format!("signal-to-noise ratio is {:.1}dB", snr));
+            }
+        }
+        if let Some(max_difference) = tolerance.max_spectral_difference {
+            let (expected, actual) = (spectrum(expected), spectrum(actual));
+            let peak = expected.iter().chain(&actual).cloned().fold(0.0, f64::max);
+            let floor = peak * SPECTRAL_FLOOR + f64::MIN_POSITIVE;
+            let worst = expected.iter()
+                .zip(&actual)
+                .map(|(&x, &y)| not_nan((10.0 * ((y + floor) / (x + floor)).log10()).abs()))
+                .enumerate()
+                .fold((0, 0.0), |worst, (bin, difference)| {
+                    if difference > worst.1 { (bin, difference) } else { worst }
+                });
+            if worst.1 > max_difference {
+                let frequency = worst.0 as f64 * sample_rate as f64 / WINDOW as f64;
+                return mismatch(format!("spectrum differs by {:.1}dB at {:.0}Hz", worst.1, frequency));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Renders a plugin and compares its output with the reference recording at ```path```, or
+/// replaces the recording if ```LADSPA_BLESS``` is set to anything but 0.
+pub fn check<P: AsRef<Path>>(desc: &Descriptor,
+                             path: P,
+                             render: &Render,
+                             tolerance: &Tolerance)
+                             -> Result<(), Error> {
+    let path = path.as_ref();
+    let actual = self::render(desc, render)?;
+    if env::var(BLESS_VAR).is_ok_and(|value| !value.is_empty() && value != "0") {
+        return write(path, render.sample_rate, &actual);
+    }
+    if !path.exists() {
+        return Err(Error::Missing(path.to_path_buf()));
+    }
+    let (sample_rate, expected) = read(path)?;
+    if sample_rate != render.sample_rate {
+        return Err(Error::Mismatch(format!("rendered at {}Hz instead of {}Hz", render.sample_rate, sample_rate)));
+    }
+    compare(&expected, &actual, sample_rate, tolerance)
+}
+
+/// Checks a native plugin against a reference recording, panicking if it doesn't match.
+pub fn assert_snapshot<P: AsRef<Path>>(desc: PluginDescriptor,
+                                       path: P,
+                                       render: &Render,
+                                       tolerance: &Tolerance) {
+    let desc = Descriptor::from_native(desc);
+    match check(&desc, path.as_ref(), render, tolerance) {
+        Ok(()) => {}
+        Err(err @ Error::Missing(_)) => panic!("{}: {}", desc.label(), err),
+        Err(err) => panic!("{} ({}): {}", desc.label(), path.as_ref().display(), err),
+    }
+}
+
+/// Writes audio to a 32-bit float WAV file, creating its directory if needed.
+pub fn write<P: AsRef<Path>>(path: P, sample_rate: u64, channels: &[Vec<Data>]) -> Result<(), Error> {
+    let path = path.as_ref();
+    let len = channels.first().map_or(0, |c| c.len());
+    assert!(channels.iter().all(|c| c.len() == len), "every channel needs the same length");
+    let block_align = 4 * channels.len() as u32;
+    let data_size = block_align * len as u32;
+
+    let mut bytes = Vec::with_capacity(44 + data_size as usize);
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_size).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&3u16.to_le_bytes());
+    bytes.extend_from_slice(&(channels.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(&(sample_rate as u32).to_le_bytes());
+    bytes.extend_from_slice(&(block_align * sample_rate as u32).to_le_bytes());
+    bytes.extend_from_slice(&(block_align as u16).to_le_bytes());
+    bytes.extend_from_slice(&32u16.to_le_bytes());
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_size.to_le_bytes());
+    for k in 0..len {
+        for channel in channels {
+            bytes.extend_from_slice(&channel[k].to_le_bytes());
+        }
+    }
+
+    let err = |e: ::std::io::Error| Error::File(format!("{}: {}", path.display(), e));
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(err)?;
+    }
+    File::create(path).and_then(|mut f| f.write_all(&bytes)).map_err(err)
+}
+
+/// Reads a 32-bit float WAV file, returning its sample rate and channels.
+pub fn read<P: AsRef<Path>>(path: P) -> Result<(u64, Vec<Vec<Data>>), Error> {
+    let path = path.as_ref();
+    let mut bytes = Vec::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut bytes))
+        .map_err(|e| Error::File(format!("{}: {}", path.display(), e)))?;
+    let invalid = |msg: &str| Error::File(format!("{}: {}", path.display(), msg));
+    if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(invalid("not a WAV file"));
+    }
+
+    let u16_at = |at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+    let u32_at = |at: usize| u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]);
+    let mut format = None;
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let size = u32_at(pos + 4) as usize;
+        let body = pos + 8;
+        if body + size > bytes.len() {
+            return Err(invalid("truncated chunk"));
+        }
+        match &bytes[pos..pos + 4] {
+            b"fmt " if size >= 16 => {
+                if u16_at(body) != 3 || u16_at(body + 14) != 32 {
+                    return Err(invalid("samples are not 32-bit floats"));
+                }
+                format = Some((u16_at(body + 2) as usize, u32_at(body + 4) as u64));
+            }
+            b"data" => {
+                let (channels, sample_rate) = format.ok_or_else(|| invalid("data before format"))?;
+                if channels == 0 {
+                    return Err(invalid("no channels"));
+                }
+                let samples: Vec<Data> = bytes[body..body + size]
+                    .chunks_exact(4)
+                    .map(|b| Data::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                    .collect();
+                let data = (0..channels)
+                    .map(|c| samples.iter().skip(c).step_by(channels).cloned().collect())
+                    .collect();
+                return Ok((sample_rate, data));
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even length.
+        pos = body + size + size % 2;
+    }
+    Err(invalid("no data"))
+}
+
+// Generates the input signal for one audio input.
+struct Generator {
+    signal: Signal,
+    sample_rate: f64,
+    length: usize,
+    position: usize,
+    phase: f64,
+    state: u32,
+}
+
+impl Generator {
+    fn new(signal: Signal, input: u32, sample_rate: u64, length: usize) -> Generator {
+        let seed = match signal {
+            Signal::Noise(seed) => seed.wrapping_add(input.wrapping_mul(0x9e37_79b9)),
+            _ => 0,
+        };
+        Generator {
+            signal: signal,
+            sample_rate: sample_rate as f64,
+            length: length,
+            position: 0,
+            phase: 0.0,
+            state: if seed == 0 { 1 } else { seed },
+        }
+    }
+
+    fn next(&mut self) -> Data {
+        let k = self.position;
+        self.position += 1;
+        match self.signal {
+            Signal::Silence => 0.0,
+            Signal::Impulse => if k == 0 { 1.0 } else { 0.0 },
+            Signal::Noise(_) => {
+                self.state ^= self.state << 13;
+                self.state ^= self.state >> 17;
+                self.state ^= self.state << 5;
+                (self.state as f64 / u32::MAX as f64 - 0.5) as Data
+            }
+            Signal::Sine(frequency) => self.oscillate(frequency as f64),
+            Signal::Sweep(from, to) => {
+                let t = k as f64 / cmp::max(self.length, 1) as f64;
+                self.oscillate(from as f64 * (to as f64 / from as f64).powf(t))
+            }
+        }
+    }
+
+    fn oscillate(&mut self, frequency: f64) -> Data {
+        let x = 0.5 * (2.0 * PI * self.phase).sin();
+        self.phase = (self.phase + frequency / self.sample_rate).fract();
+        x as Data
+    }
+}
+
+// The average power spectrum of a signal over Hann windowed frames, with WINDOW / 2 + 1 bins.
+// Signals shorter than a window are measured over a single frame padded with zeros.
+fn spectrum(signal: &[Data]) -> Vec<f64> {
+    let window: Vec<f64> = (0..WINDOW).map(|k| 0.5 - 0.5 * (2.0 * PI * k as f64 / WINDOW as f64).cos()).collect();
+    let mut power = vec![0.0; WINDOW / 2 + 1];
+    let mut frames = 0;
+    let mut start = 0;
+    loop {
+        let mut re: Vec<f64> = (0..WINDOW)
+            .map(|k| signal.get(start + k).map_or(0.0, |&x| x as f64) * window[k])
+            .collect();
+        let mut im = vec![0.0; WINDOW];
+        fft(&mut re, &mut im);
+        for (bin, p) in power.iter_mut().enumerate() {
+            *p += re[bin] * re[bin] + im[bin] * im[bin];
+        }
+        frames += 1;
+        start += HOP;
+        if start + WINDOW > signal.len() {
+            break;
+        }
+    }
+    for p in &mut power {
+        *p /= frames as f64;
+    }
+    power
+}
+
+// An in-place radix-2 FFT. The length must be a power of two.
+fn fft(re: &mut [f64], im: &mut [f64]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f64;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (w_re, w_im) = ((angle * k as f64).cos(), (angle * k as f64).sin());
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * w_re - im[b] * w_im;
+                let t_im = re[b] * w_im + im[b] * w_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+// Treats NaN as infinitely wrong, so that comparisons with tolerances fail.
+fn not_nan(x: f64) -> f64 {
+    if x.is_nan() { f64::INFINITY } else { x }
+}
+
+// Fails if the plugin panicked during the last call.
+fn suppressed() -> Result<(), Error> {
+    match ffi::take_suppressed_panic() {
+        Some(msg) => Err(Error::Panic(msg)),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,535 @@
+//! Recording the calls a host makes to a plugin, and replaying them offline.
+//!
+//! When the ```LADSPA_TRACE``` environment variable names a directory, every instance of a plugin
+//! written with this crate records the calls the host makes to it into a file in that directory:
+//! each ```connect_port```, ```activate```, ```run```, ```deactivate``` and ```cleanup```, with the
+//! block size and control input values of every ```run```. If ```LADSPA_TRACE_AUDIO``` is also
+//! set to 1, the audio input of every ```run``` is recorded too. Each call is written before the
+//! plugin sees it, so the trace of a host which crashes ends with the call which crashed it.
+//!
+//! ```read``` loads a trace, and ```replay``` drives a plugin through the same calls offline, with
+//! ports connected to the same buffers as the host connected them to. Audio inputs which weren't
+//! recorded are silent. The ```tools/replay``` directory of this repository contains a command line
+//! tool which replays a trace against a plugin library.
+//!
+//! Traces are compact little-endian binary files. After a header holding the plugin's label,
+//! unique ID, port count and the sample rate it was instantiated at, each call is a tag byte
+//! followed by its arguments. Buffers are numbered in the order the host first connected them,
+//! and control values are only recorded when they change.
+
+use std::env;
+use std::error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use libc::c_ulong;
+use vec_map::VecMap;
+
+use diagnostics;
+use ffi::{self, ladspa_h};
+use host::Descriptor;
+use super::{Data, PluginDescriptor, PortConnection, PortData, PortDescriptor};
+
+/// The environment variable naming the directory traces are written to.
+pub const TRACE_VAR: &str = "LADSPA_TRACE";
+
+/// The environment variable which makes traces include audio input.
+pub const AUDIO_VAR: &str = "LADSPA_TRACE_AUDIO";
+
+const MAGIC: &[u8] = b"LADSPATR";
+const VERSION: u8 = 1;
+
+const CONNECT_PORT: u8 = 0;
+const ACTIVATE: u8 = 1;
+const RUN: u8 = 2;
+const DEACTIVATE: u8 = 3;
+const CLEANUP: u8 = 4;
+
+// The most samples replay allocates for the buffers the host connected, so that a damaged trace
+// can't exhaust memory.
+const MAX_BUFFER_SAMPLES: usize = 1 << 26;
+
+// Numbers the traces written by this process, so that every instance gets its own file.
+static TRACES: AtomicUsize = AtomicUsize::new(0);
+
+/// The ways reading or replaying a trace can fail.
+#[derive(Debug)]
+pub enum Error {
+    /// The trace could not be read.
+    File(String),
+    /// The file is not a trace, or is damaged.
+    Format(String),
+    /// The plugin is not the one the trace was recorded from.
+    Mismatch(String),
+    /// The plugin panicked.
+    Panic(String),
+    /// The plugin couldn't be instantiated.
+    Instantiate,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::File(ref msg) => write!(f, "{}", msg),
+            Error::Format(ref msg) => write!(f, "invalid trace: {}", msg),
+            Error::Mismatch(ref msg) => write!(f, "the trace was recorded from another plugin: {}", msg),
+            Error::Panic(ref msg) => write!(f, "{}", msg),
+            Error::Instantiate => write!(f, "failed to instantiate"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// A call made by the host after instantiating the plugin.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Call {
+    /// A port was connected to a buffer, numbered in the order the host first used them.
+    ConnectPort { port: usize, buffer: usize },
+
+    Activate,
+
+    /// The control inputs which changed since the last ```run```, and the audio inputs if they
+    /// were recorded.
+    Run {
+        sample_count: usize,
+        controls: Vec<(usize, Data)>,
+        audio: Vec<(usize, Vec<Data>)>,
+    },
+
+    Deactivate,
+
+    Cleanup,
+}
+
+/// The calls made to one instance of a plugin.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trace {
+    pub label: String,
+    pub unique_id: u64,
+    pub port_count: usize,
+    pub sample_rate: u64,
+    pub calls: Vec<Call>,
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let runs = self.calls.iter().filter_map(|call| match *call {
+            Call::Run { sample_count, .. } => Some(sample_count),
+            _ => None,
+        });
+        let (blocks, samples) = runs.fold((0, 0), |(blocks, samples), n| (blocks + 1, samples + n));
+        write!(f,
+               "{} ({}) at {}Hz: {} calls, running {} samples in {} blocks",
+               self.label,
+               self.unique_id,
+               self.sample_rate,
+               self.calls.len(),
+               samples,
+               blocks)
+    }
+}
+
+/// Reads a trace written by a plugin. A trace which ends part way through a call, as happens when
+/// the host crashes, is read up to the last complete call. One which connects more or larger
+/// buffers than could be replayed is taken to be damaged.
+pub fn read<P: AsRef<Path>>(path: P) -> Result<Trace, Error> {
+    let path = path.as_ref();
+    let mut bytes = Vec::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut bytes))
+        .map_err(|err| Error::File(format!("{}: {}", path.display(), err)))?;
+    let mut input = Input { bytes: &bytes };
+    if input.take(MAGIC.len()) != Some(MAGIC) {
+        return Err(Error::Format("not a trace".to_string()));
+    }
+    let truncated = || Error::Format("the header is incomplete".to_string());
+    match input.byte() {
+        Some(VERSION) => {}
+        Some(version) => return Err(Error::Format(format!("unknown version {}", version))),
+        None => return Err(truncated()),
+    }
+    let label = input.u32()
+        .and_then(|len| input.take(len as usize))
+        .map(|label| String::from_utf8_lossy(label).into_owned())
+        .ok_or_else(truncated)?;
+    let unique_id = input.u64().ok_or_else(truncated)?;
+    let port_count = input.u32().ok_or_else(truncated)? as usize;
+    let sample_rate = input.u64().ok_or_else(truncated)?;
+
+    let mut calls = Vec::new();
+    let mut buffers = 0;
+    while let Some(tag) = input.byte() {
+        let call = match tag {
+            CONNECT_PORT => input.connect_port(port_count),
+            ACTIVATE => Ok(Some(Call::Activate)),
+            RUN => input.run(port_count),
+            DEACTIVATE => Ok(Some(Call::Deactivate)),
+            CLEANUP => Ok(Some(Call::Cleanup)),
+            _ => Err(Error::Format(format!("unknown call {} after {} calls", tag, calls.len()))),
+        };
+        match call? {
+            // Buffers are numbered as they're first used, so a new one is always the next number.
+            Some(Call::ConnectPort { buffer, .. }) if buffer > buffers => {
+                return Err(Error::Format(format!("buffer {} is connected before buffer {}",
+                                                 buffer,
+                                                 buffers)));
+            }
+            Some(call) => {
+                if let Call::ConnectPort { buffer, .. } = call {
+                    buffers = buffers.max(buffer + 1);
+                }
+                calls.push(call);
+            }
+            None => break,
+        }
+    }
+    buffer_sizes(&calls)?;
+    Ok(Trace {
+        label: label,
+        unique_id: unique_id,
+        port_count: port_count,
+        sample_rate: sample_rate,
+        calls: calls,
+    })
+}
+
+// Reads little-endian values from a trace, giving None once it runs out.
+struct Input<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Input<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.bytes.len() {
+            return None;
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Some(taken)
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take(8).map(|b| u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+    }
+
+    fn data(&mut self) -> Option<Data> {
+        self.u32().map(Data::from_bits)
+    }
+
+    fn port(&mut self, port_count: usize) -> Result<Option<usize>, Error> {
+        match self.u32() {
+            Some(port) if port as usize >= port_count => {
+                Err(Error::Format(format!("port {} of a plugin with {} ports", port, port_count)))
+            }
+            port => Ok(port.map(|port| port as usize)),
+        }
+    }
+
+    fn connect_port(&mut self, port_count: usize) -> Result<Option<Call>, Error> {
+        let port = match self.port(port_count)? {
+            Some(port) => port,
+            None => return Ok(None),
+        };
+        Ok(self.u32().map(|buffer| Call::ConnectPort { port: port, buffer: buffer as usize }))
+    }
+
+    fn run(&mut self, port_count: usize) -> Result<Option<Call>, Error> {
+        let sample_count = match self.u32() {
+            Some(n) => n as usize,
+            None => return Ok(None),
+        };
+        let mut controls = Vec::new();
+        let count = match self.u32() {
+            Some(count) => count,
+            None => return Ok(None),
+        };
+        for _ in 0..count {
+            match (self.port(port_count)?, self.data()) {
+                (Some(port), Some(value)) => controls.push((port, value)),
+                _ => return Ok(None),
+            }
+        }
+        let mut audio = Vec::new();
+        let count = match self.u32() {
+            Some(count) => count,
+            None => return Ok(None),
+        };
+        for _ in 0..count {
+            let port = match self.port(port_count)? {
+                Some(port) => port,
+                None => return Ok(None),
+            };
+            match self.take(sample_count * 4) {
+                Some(samples) => {
+                    let samples = samples.chunks(4)
+                        .map(|b| Data::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                        .collect();
+                    audio.push((port, samples));
+                }
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(Call::Run { sample_count: sample_count, controls: controls, audio: audio }))
+    }
+}
+
+/// Drives a plugin through the calls in a trace, returning everything written to its audio
+/// outputs, in port order.
+///
+/// Calls are made exactly as they were recorded, even if they break the LADSPA specification.
+/// If the trace ends without ```cleanup```, the plugin is cleaned up afterwards.
+pub fn replay(desc: &Descriptor, trace: &Trace) -> Result<Vec<Vec<Data>>, Error> {
+    if desc.label() != trace.label || desc.unique_id() != trace.unique_id {
+        return Err(Error::Mismatch(format!("{} ({})", trace.label, trace.unique_id)));
+    }
+    if desc.port_count() != trace.port_count {
+        return Err(Error::Mismatch(format!("{} ports instead of {}", trace.port_count, desc.port_count())));
+    }
+    let (block_size, buffer_count) = buffer_sizes(&trace.calls)?;
+    let mut buffers = vec![vec![0.0; block_size]; buffer_count];
+    let mut connections: Vec<Option<usize>> = vec![None; desc.port_count()];
+    let outputs: Vec<usize> = (0..desc.port_count())
+        .filter(|&i| desc.port_descriptor(i) == PortDescriptor::AudioOutput)
+        .collect();
+    let mut rendered = vec![Vec::new(); outputs.len()];
+
+    let raw = desc.as_raw();
+    let handle = (raw.instantiate)(raw, trace.sample_rate as c_ulong);
+    suppressed(0)?;
+    if handle.is_null() {
+        return Err(Error::Instantiate);
+    }
+    let mut session = Session { raw: raw, handle: handle };
+    for (i, call) in trace.calls.iter().enumerate() {
+        match *call {
+            Call::ConnectPort { port, buffer } => {
+                connections[port] = Some(buffer);
+                (raw.connect_port)(session.handle, port as c_ulong, buffers[buffer].as_mut_ptr());
+            }
+            Call::Activate => {
+                if let Some(activate) = raw.activate {
+                    activate(session.handle);
+                }
+            }
+            Call::Run { sample_count, ref controls, ref audio } => {
+                for &(port, value) in controls {
+                    if let Some(buffer) = connections[port] {
+                        buffers[buffer][0] = value;
+                    }
+                }
+                for &(port, ref samples) in audio {
+                    if let Some(buffer) = connections[port] {
+                        buffers[buffer][..samples.len()].copy_from_slice(samples);
+                    }
+                }
+                (raw.run)(session.handle, sample_count as c_ulong);
+                for (&port, rendered) in outputs.iter().zip(&mut rendered) {
+                    match connections[port] {
+                        Some(buffer) => rendered.extend_from_slice(&buffers[buffer][..sample_count]),
+                        None => rendered.extend((0..sample_count).map(|_| 0.0)),
+                    }
+                }
+            }
+            Call::Deactivate => {
+                if let Some(deactivate) = raw.deactivate {
+                    deactivate(session.handle);
+                }
+            }
+            Call::Cleanup => {
+                session.cleanup();
+            }
+        }
+        suppressed(i + 1)?;
+        if session.handle.is_null() {
+            break;
+        }
+    }
+    session.cleanup();
+    suppressed(trace.calls.len() + 1)?;
+    Ok(rendered)
+}
+
+// Returns the largest block size run and the number of buffers connected, failing if buffers of
+// that size would take more than MAX_BUFFER_SAMPLES.
+fn buffer_sizes(calls: &[Call]) -> Result<(usize, usize), Error> {
+    let block_size = calls.iter()
+        .filter_map(|call| match *call {
+            Call::Run { sample_count, .. } => Some(sample_count),
+            _ => None,
+        })
+        .fold(1, usize::max);
+    let buffer_count = calls.iter()
+        .filter_map(|call| match *call {
+            Call::ConnectPort { buffer, .. } => Some(buffer + 1),
+            _ => None,
+        })
+        .fold(0, usize::max);
+    if block_size.checked_mul(buffer_count).map_or(true, |samples| samples > MAX_BUFFER_SAMPLES) {
+        return Err(Error::Format(format!("{} buffers of {} samples are too large to replay",
+                                         buffer_count,
+                                         block_size)));
+    }
+    Ok((block_size, buffer_count))
+}
+
+// An instance being replayed, cleaned up even if replaying fails part way through.
+struct Session<'a> {
+    raw: &'a ladspa_h::Descriptor,
+    handle: ladspa_h::Handle,
+}
+
+impl<'a> Session<'a> {
+    fn cleanup(&mut self) {
+        if !self.handle.is_null() {
+            (self.raw.cleanup)(self.handle);
+            self.handle = 0 as ladspa_h::Handle;
+        }
+    }
+}
+
+impl<'a> Drop for Session<'a> {
+    fn drop(&mut self) {
+        self.cleanup();
+        let _ = ffi::take_suppressed_panic();
+    }
+}
+
+// Fails if the plugin panicked during a call, counting instantiate as call 0.
+fn suppressed(call: usize) -> Result<(), Error> {
+    match ffi::take_suppressed_panic() {
+        Some(msg) => Err(Error::Panic(format!("{} (call {} of the trace)", msg, call))),
+        None => Ok(()),
+    }
+}
+
+// Writes the trace of one instance, if tracing is enabled. Used by the C interface.
+#[doc(hidden)]
+pub struct Recorder {
+    file: Option<File>,
+    audio: bool,
+    // The address of each buffer the host has connected a port to, numbered by position.
+    buffers: Vec<usize>,
+    // The control input values recorded so far, so that only changes are written.
+    controls: Vec<Option<u32>>,
+}
+
+impl Recorder {
+    // Starts a trace in the directory named by TRACE_VAR, if it is set.
+    pub fn start(desc: &PluginDescriptor, sample_rate: u64) -> Option<Recorder> {
+        let dir = match env::var_os(TRACE_VAR) {
+            Some(ref dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => return None,
+        };
+        let name: String = desc.label.chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+            .collect();
+        let path = dir.join(format!("{}-{}-{}.trace",
+                                    name,
+                                    process::id(),
+                                    TRACES.fetch_add(1, Ordering::Relaxed)));
+        let file = match fs::create_dir_all(&dir).and_then(|_| File::create(&path)) {
+            Ok(file) => file,
+            Err(err) => {
+                diagnostics::report(&format!("can't write trace {}: {}", path.display(), err));
+                return None;
+            }
+        };
+        let mut recorder = Recorder {
+            file: Some(file),
+            audio: env::var(AUDIO_VAR).map(|value| value != "0" && !value.is_empty()).unwrap_or(false),
+            buffers: Vec::new(),
+            controls: vec![None; desc.ports.len()],
+        };
+        let mut header = MAGIC.to_vec();
+        header.push(VERSION);
+        header.extend_from_slice(&(desc.label.len() as u32).to_le_bytes());
+        header.extend_from_slice(desc.label.as_bytes());
+        header.extend_from_slice(&desc.unique_id.to_le_bytes());
+        header.extend_from_slice(&(desc.ports.len() as u32).to_le_bytes());
+        header.extend_from_slice(&sample_rate.to_le_bytes());
+        recorder.write(&header);
+        Some(recorder)
+    }
+
+    pub fn connect_port(&mut self, port: usize, location: *mut Data) {
+        let address = location as usize;
+        let buffer = match self.buffers.iter().position(|&b| b == address) {
+            Some(buffer) => buffer,
+            None => {
+                self.buffers.push(address);
+                self.buffers.len() - 1
+            }
+        };
+        // The buffer may now hold a different value to the one last recorded.
+        self.controls[port] = None;
+        let mut bytes = vec![CONNECT_PORT];
+        bytes.extend_from_slice(&(port as u32).to_le_bytes());
+        bytes.extend_from_slice(&(buffer as u32).to_le_bytes());
+        self.write(&bytes);
+    }
+
+    pub fn activate(&mut self) {
+        self.write(&[ACTIVATE]);
+    }
+
+    // Records a run, with ports already sized to sample_count.
+    pub fn run(&mut self, sample_count: usize, ports: &VecMap<PortConnection>) {
+        let mut controls = Vec::new();
+        let mut audio = Vec::new();
+        for (port, conn) in ports.iter() {
+            match conn.data {
+                PortData::ControlInput(value) if self.controls[port] != Some(value.to_bits()) => {
+                    self.controls[port] = Some(value.to_bits());
+                    controls.push((port, *value));
+                }
+                PortData::AudioInput(samples) if self.audio => audio.push((port, samples)),
+                _ => {}
+            }
+        }
+        let mut bytes = vec![RUN];
+        bytes.extend_from_slice(&(sample_count as u32).to_le_bytes());
+        bytes.extend_from_slice(&(controls.len() as u32).to_le_bytes());
+        for (port, value) in controls {
+            bytes.extend_from_slice(&(port as u32).to_le_bytes());
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&(audio.len() as u32).to_le_bytes());
+        for (port, samples) in audio {
+            bytes.extend_from_slice(&(port as u32).to_le_bytes());
+            for x in samples {
+                bytes.extend_from_slice(&x.to_le_bytes());
+            }
+        }
+        self.write(&bytes);
+    }
+
+    pub fn deactivate(&mut self) {
+        self.write(&[DEACTIVATE]);
+    }
+
+    pub fn cleanup(&mut self) {
+        self.write(&[CLEANUP]);
+    }
+
+    // Writes a whole call at once, so that it reaches the file even if the plugin then crashes.
+    // Tracing stops if the file can't be written.
+    fn write(&mut self, bytes: &[u8]) {
+        if let Some(Err(err)) = self.file.as_mut().map(|file| file.write_all(bytes)) {
+            diagnostics::report(&format!("can't write trace, so tracing has stopped: {}", err));
+            self.file = None;
+        }
+    }
+}
@@ -0,0 +1,129 @@
+//! Checks that benchmarks notice a plugin whose processing time depends on its input.
+
+#![cfg(feature = "testing")]
+
+// Written in the same style as the crate itself.
+#![allow(bare_trait_objects)]
+
+extern crate ladspa;
+
+use ladspa::{Plugin, PluginDescriptor, Port, PortConnection, PortDescriptor, PROP_HARD_REALTIME_CAPABLE};
+use std::time::Duration;
+
+use ladspa::bench::{self, Benchmark, Report, Signal};
+use ladspa::host::Descriptor;
+
+// Does far more work on loud samples than on quiet ones.
+struct Uneven;
+
+impl Plugin for Uneven {
+    fn run<'a>(&mut self, sample_count: usize, ports: &[&'a PortConnection<'a>]) {
+        let input = ports[0].unwrap_audio();
+        let mut output = ports[1].unwrap_audio_mut();
+        for (x, y) in input[..sample_count].iter().zip(output.iter_mut()) {
+            let mut value = *x;
+            if value.abs() > 0.01 {
+                for _ in 0..200 {
+                    value = value.sin();
+                }
+            }
+            *y = value;
+        }
+    }
+}
+
+fn uneven() -> PluginDescriptor {
+    PluginDescriptor {
+        unique_id: 1,
+        label: "uneven".into(),
+        properties: PROP_HARD_REALTIME_CAPABLE,
+        name: "Uneven".into(),
+        maker: "".into(),
+        copyright: "".into(),
+        ports: vec![
+            Port { name: "Input".into(), desc: PortDescriptor::AudioInput, ..Default::default() },
+            Port { name: "Output".into(), desc: PortDescriptor::AudioOutput, ..Default::default() },
+        ],
+        new: Box::new(|_: &PluginDescriptor, _| Ok(Box::new(Uneven) as Box<Plugin + Send>)),
+        presets: vec![],
+    }
+}
+
+fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() <= 1e-9 * b.abs()
+}
+
+#[test]
+fn fit() {
+    // 100ns a call plus 2ns a sample, which fits exactly.
+    let sizes = [1, 16, 256, 4096];
+    let times: Vec<_> = sizes.iter().map(|&x| Duration::from_nanos(100 + 2 * x as u64)).collect();
+    let fit = bench::fit(&sizes, &times);
+    assert!(close(fit.a, 100e-9) && close(fit.b, 2e-9), "{}", fit);
+    assert!(fit.max_residual < 1e-9, "{}", fit);
+    assert!(close(fit.predict(1000), 2100e-9));
+
+    // Doubling one time shows up in the worst fit, relative to that time.
+    let mut uneven = times.clone();
+    uneven[2] *= 2;
+    assert!(bench::fit(&sizes, &uneven).max_residual > 0.3);
+
+    // Times of zero are left out, and a single time is all fixed cost.
+    let single = bench::fit(&[0, 64], &[Duration::from_nanos(0), Duration::from_nanos(640)]);
+    assert_eq!((single.a, single.b), (640e-9, 0.0));
+    let empty = bench::fit(&[], &[]);
+    assert_eq!((empty.a, empty.b, empty.max_residual), (0.0, 0.0, 0.0));
+}
+
+#[test]
+fn report() {
+    let sizes = vec![16, 256, 4096];
+    let nanos = |times: &[u64]| -> Vec<_> {
+        times.iter().map(|&t| Duration::from_nanos(t)).collect()
+    };
+    let signal = |signal, times: &[u64]| {
+        (signal, nanos(times), bench::fit(&sizes, &nanos(times)))
+    };
+    let mut report = Report {
+        label: "uneven".to_string(),
+        hard_realtime_capable: false,
+        block_sizes: sizes.clone(),
+        max_ratio: 1.5,
+        signals: vec![signal(Signal::Silence, &[100, 1000, 10000]),
+                      signal(Signal::Noise, &[140, 3000, 10000]),
+                      signal(Signal::Denormals, &[100, 1000, 0])],
+    };
+    // At 4096 samples one time is zero, so the signals can't be compared.
+    assert_eq!(report.signal_dependent(), [(256, 3.0)]);
+    assert!(!report.violates_hard_realtime());
+    report.hard_realtime_capable = true;
+    assert!(report.violates_hard_realtime());
+    let text = report.to_string();
+    assert!(text.starts_with("uneven\n  silence "), "{}", text);
+    let slowest = "at 256 samples the slowest signal takes 3.0 times as long as the fastest";
+    assert!(text.contains(slowest), "{}", text);
+    let claims = "claims PROP_HARD_REALTIME_CAPABLE, but its timing depends on the signal\n";
+    assert!(text.ends_with(claims), "{}", text);
+
+    report.max_ratio = 4.0;
+    assert!(report.signal_dependent().is_empty());
+    assert!(!report.violates_hard_realtime());
+}
+
+// Timings depend on whatever else the machine is doing, so this is only run when asked for.
+#[test]
+#[ignore]
+fn signal_dependent() {
+    let report = bench::run(&Descriptor::from_native(uneven()), &Benchmark {
+        block_sizes: vec![64, 256, 1024],
+        signals: vec![Signal::Silence, Signal::Noise],
+        repetitions: 11,
+        warm_up: 1024,
+        ..Default::default()
+    }).unwrap();
+    assert_eq!(report.signals.len(), 2);
+    let (silence, noise) = (report.signals[0].2, report.signals[1].2);
+    assert!(noise.b > silence.b * 10.0, "{}", report);
+    assert_eq!(report.signal_dependent().len(), 3, "{}", report);
+    assert!(report.violates_hard_realtime());
+}
@@ -0,0 +1,65 @@
+//! Checks the latency and overlap-add of plugins run on fixed blocks.
+
+extern crate ladspa;
+
+use ladspa::{block, expr, Data};
+use ladspa::block::FixedBlock;
+use ladspa::host::{Descriptor, Instance};
+
+// Feeds an impulse through a pass-through plugin run on blocks of block_size every hop samples,
+// with the host calling run on host_block samples at a time.
+fn impulse(block_size: usize, hop: usize, host_block: usize) -> (Vec<Data>, Option<usize>) {
+    let inner = expr::descriptor(1, "through".into(), "Through".into(), "".into(), "".into(), "
+        input in
+        out = in
+    ").unwrap();
+    let desc = block::wrap(inner, 2, "blocks".into(), block_size, hop).with_latency_port();
+    let desc = Descriptor::from_native(desc);
+    let mut instance = Instance::new(&desc, 48000, host_block).unwrap();
+    instance.activate();
+
+    let mut output = Vec::new();
+    for start in (0..4 * block_size).step_by(host_block) {
+        for (k, x) in instance.audio_mut(0).iter_mut().enumerate() {
+            *x = if start + k == 0 { 1.0 } else { 0.0 };
+        }
+        instance.run(host_block);
+        output.extend_from_slice(instance.audio(1));
+    }
+    (output, instance.latency())
+}
+
+#[test]
+fn latency() {
+    for &(block_size, hop) in &[(1, 1), (4, 4), (5, 5), (8, 2), (8, 4)] {
+        assert_eq!(block::latency(block_size), block_size - 1);
+        for &host_block in &[1, 3, 7, 16] {
+            let (output, latency) = impulse(block_size, hop, host_block);
+            assert_eq!(latency, Some(block_size - 1));
+            // Each of the block_size / hop frames containing the impulse passes it through.
+            let peak = output.iter().position(|&x| x != 0.0);
+            let case = format!("block {}, hop {}, run on {}", block_size, hop, host_block);
+            assert_eq!(peak, Some(block_size - 1), "{}: {:?}", case, output);
+            assert_eq!(output[block_size - 1], (block_size / hop) as Data);
+            assert_eq!(output.iter().filter(|&&x| x != 0.0).count(), 1);
+        }
+    }
+}
+
+#[test]
+fn invalid_sizes() {
+    let inner = expr::descriptor(1, "through".into(), "Through".into(), "".into(), "".into(), "
+        input in
+        out = in
+    ").unwrap();
+    assert_eq!(block::latency(0), 0);
+    for &(block_size, hop, msg) in &[(0, 0, "hop 0 isn't between 1 and the block size 0"),
+                                     (4, 0, "hop 0 isn't between 1 and the block size 4"),
+                                     (4, 5, "hop 5 isn't between 1 and the block size 4")] {
+        let err = FixedBlock::new(&inner, 48000, block_size, hop).err().expect("instantiated");
+        assert_eq!(err.to_string(), msg);
+    }
+    // Through the C interface, the host is refused an instance.
+    let desc = Descriptor::from_native(block::wrap(inner, 2, "blocks".into(), 4, 0));
+    assert!(Instance::new(&desc, 48000, 4).is_err());
+}
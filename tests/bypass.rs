@@ -0,0 +1,35 @@
+//! Checks how a plugin is switched off and on again by the bypass adapter.
+
+extern crate ladspa;
+
+use ladspa::{bypass, expr, Data};
+use ladspa::host::{Descriptor, Instance};
+
+// Runs two samples of ones, returning the output.
+fn run(instance: &mut Instance, bypassed: bool) -> Vec<Data> {
+    instance.set_control(2, if bypassed { 1.0 } else { 0.0 });
+    instance.audio_mut(0).copy_from_slice(&[1.0, 1.0]);
+    instance.run(2);
+    instance.audio(1).to_vec()
+}
+
+#[test]
+fn restart() {
+    let sum = expr::descriptor(1, "sum".into(), "Sum".into(), "".into(), "".into(), "
+        input in
+        y = y + in
+    ").unwrap();
+    let desc = Descriptor::from_native(bypass::wrap(sum, 2, "bypassed_sum".into(), 0.0));
+    assert_eq!((desc.unique_id(), desc.label()), (2, "bypassed_sum".to_string()));
+    assert_eq!(desc.port_name(2), "Bypass");
+
+    let mut instance = Instance::new(&desc, 48000, 2).unwrap();
+    instance.activate();
+    assert_eq!(run(&mut instance, false), [1.0, 2.0]);
+    // Without a fade, the first sample is the last one processed.
+    assert_eq!(run(&mut instance, true), [3.0, 1.0]);
+    assert_eq!(run(&mut instance, true), [1.0, 1.0]);
+    // The sum starts again rather than carrying on from before the bypass.
+    assert_eq!(run(&mut instance, false), [1.0, 2.0]);
+    assert_eq!(run(&mut instance, false), [3.0, 4.0]);
+}
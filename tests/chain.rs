@@ -0,0 +1,119 @@
+//! Builds chains of the delay example from TOML and checks how they are routed.
+
+#![cfg(feature = "formats")]
+
+extern crate ladspa;
+
+use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+use std::path::PathBuf;
+use std::process::Command;
+
+use ladspa::Data;
+use ladspa::chain::{Chain, Error};
+use ladspa::graph::Graph;
+
+// At this sample rate a delay of 0.25 seconds is exactly 2 samples.
+const SAMPLE_RATE: u64 = 8;
+
+// Builds the delay example, returning the path to the library.
+fn build_delay() -> PathBuf {
+    let manifest = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/delay/Cargo.toml");
+    let target = env!("CARGO_TARGET_TMPDIR");
+    let status = Command::new(option_env!("CARGO").unwrap_or("cargo"))
+        .args(["build", "--quiet", "--manifest-path", manifest, "--target-dir", target])
+        .status()
+        .expect("failed to run cargo");
+    assert!(status.success(), "failed to build the delay example");
+    PathBuf::from(target).join("debug").join(format!("{}rustdelay{}", DLL_PREFIX, DLL_SUFFIX))
+}
+
+// Builds a chain from TOML, in which {delay} stands for the path to the delay example.
+fn build(toml: &str) -> Result<Graph, Error> {
+    let toml = toml.replace("{delay}", &build_delay().display().to_string());
+    Chain::from_toml(&toml)?.build(SAMPLE_RATE, 4)
+}
+
+fn process(graph: &mut Graph, input: &[Data]) -> Vec<Vec<Data>> {
+    let mut outputs = vec![vec![0.0; input.len()]; graph.outputs()];
+    {
+        let mut outputs: Vec<&mut [Data]> = outputs.iter_mut().map(|x| &mut x[..]).collect();
+        graph.activate();
+        graph.process(&[input], &mut outputs);
+    }
+    outputs
+}
+
+#[test]
+fn serial() {
+    // Without connections, the plugins are chained in order.
+    let mut graph = build(r#"
+        [[plugin]]
+        library = "{delay}"
+        label = "mono_delay"
+        controls = { "Delay (seconds)" = 0.125, "Dry/Wet" = 1.0 }
+
+        [[plugin]]
+        library = "{delay}"
+        id = 402
+        controls = { "Delay (seconds)" = 0.25, "Dry/Wet" = 1.0 }
+    "#).unwrap();
+    assert_eq!((graph.inputs(), graph.outputs()), (1, 1));
+    assert_eq!(process(&mut graph, &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]),
+               [[0.0, 0.0, 0.0, 1.0, 2.0, 3.0]]);
+}
+
+#[test]
+fn routing() {
+    // The input goes through a delay to the second output, and straight to the first.
+    let mut graph = build(r#"
+        inputs = 1
+        outputs = 2
+
+        [[plugin]]
+        name = "wet"
+        library = "{delay}"
+        label = "mono_delay"
+        controls = { "Delay (seconds)" = 0.25, "Dry/Wet" = 1.0 }
+
+        [[plugin]]
+        library = "{delay}"
+        label = "mono_delay"
+        controls = { "Dry/Wet" = 0.0 }
+
+        [[connect]]
+        from = "input.0"
+        to = "wet.Audio In"
+
+        [[connect]]
+        from = "input.0"
+        to = "1.0"
+
+        [[connect]]
+        from = "wet.Audio Out"
+        to = "output.1"
+
+        [[connect]]
+        from = "1.Audio Out"
+        to = "output.0"
+    "#).unwrap();
+    assert_eq!(process(&mut graph, &[1.0, 2.0, 3.0, 4.0, 5.0]),
+               [[1.0, 2.0, 3.0, 4.0, 5.0], [0.0, 0.0, 1.0, 2.0, 3.0]]);
+}
+
+#[test]
+fn errors() {
+    let plugin = "[[plugin]]\nname = \"a\"\nlibrary = \"{delay}\"\nlabel = \"mono_delay\"\n";
+    let error = |toml: &str| build(toml).err().expect("the chain built").to_string();
+    assert_eq!(error(&format!("{}controls = {{ Volume = 1.0 }}", plugin)),
+               "unknown port: mono_delay.Volume");
+    assert_eq!(error(&format!("{}[[connect]]\nfrom = \"input.0\"\nto = \"a.Nope\"", plugin)),
+               "unknown port: a.Nope");
+    assert_eq!(error(&format!("{}[[connect]]\nfrom = \"input.0\"\nto = \"b.0\"", plugin)),
+               "unknown port: b.0");
+    let cycle = "[[connect]]\nfrom = \"a.Audio Out\"\nto = \"a.Audio In\"";
+    assert_eq!(error(&format!("{}{}", plugin, cycle)), "connection would create a cycle");
+    assert_eq!(error(&format!("{}[[connect]]\nfrom = \"a.Audio In\"\nto = \"output.0\"", plugin)),
+               "ports cannot be connected");
+    assert!(error("[[plugin]]\nlibrary = \"{delay}\"\nlabel = \"reverb\"")
+        .starts_with("plugin not found: reverb in "));
+}
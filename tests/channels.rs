@@ -0,0 +1,107 @@
+//! Expands a single channel plugin into multichannel variants, and checks their ports and output.
+
+extern crate ladspa;
+
+use std::sync::{Arc, Mutex};
+
+use ladspa::{block, channels, diagnostics, expr, Data, FactoryPreset, PluginDescriptor};
+use ladspa::channels::Variant;
+use ladspa::host::{Descriptor, Instance};
+
+fn gain() -> PluginDescriptor {
+    let mut desc = expr::descriptor(1, "gain".into(), "Gain".into(), "".into(), "".into(), "
+        input in
+        control gain = 1 [0, 4]
+        out = in * gain
+    ").unwrap();
+    desc.presets.push(FactoryPreset { name: "Double".into(), values: vec![("gain".into(), 2.0)] });
+    desc
+}
+
+fn variant(id: u64, label: &'static str, count: usize, shared: bool) -> Variant {
+    Variant {
+        unique_id: id,
+        label: label.into(),
+        name: label.into(),
+        channels: count,
+        linked: shared,
+    }
+}
+
+fn names(desc: &PluginDescriptor) -> Vec<String> {
+    desc.ports.iter().map(|port| port.name.to_string()).collect()
+}
+
+// Runs an instance with each channel's input set to its channel number, plus one.
+fn run(desc: PluginDescriptor, controls: &[(usize, Data)]) -> Vec<Data> {
+    let desc = Descriptor::from_native(desc);
+    let mut instance = Instance::new(&desc, 48000, 1).unwrap();
+    for &(port, value) in controls {
+        instance.set_control(port, value);
+    }
+    let ports: Vec<_> = (0..desc.port_count()).collect();
+    let inputs = ports.iter().filter(|&&port| desc.port_name(port).ends_with(" in"));
+    for (channel, &port) in inputs.enumerate() {
+        instance.audio_mut(port)[0] = (channel + 1) as Data;
+    }
+    instance.activate();
+    instance.run(1);
+    let outputs = ports.iter().filter(|&&port| desc.port_name(port).ends_with(" out"));
+    outputs.map(|&port| instance.audio(port)[0]).collect()
+}
+
+#[test]
+fn naming() {
+    let variants = channels::expand(gain(), &[variant(10, "mono", 1, false),
+                                              variant(11, "stereo", 2, false),
+                                              variant(12, "linked", 2, true),
+                                              variant(13, "triple", 3, true)]);
+    let labels: Vec<_> = variants.iter().map(|desc| (desc.unique_id, &*desc.label)).collect();
+    assert_eq!(labels, [(10, "mono"), (11, "stereo"), (12, "linked"), (13, "triple")]);
+    assert_eq!(names(&variants[0]), ["in", "gain", "out"]);
+    assert_eq!(names(&variants[1]),
+               ["Left in", "Right in", "Left gain", "Right gain", "Left out", "Right out"]);
+    assert_eq!(names(&variants[2]), ["Left in", "Right in", "gain", "Left out", "Right out"]);
+    assert_eq!(names(&variants[3]), ["Channel 1 in", "Channel 2 in", "Channel 3 in", "gain",
+                                     "Channel 1 out", "Channel 2 out", "Channel 3 out"]);
+    // Presets name the single channel's controls, which are gone when they aren't linked.
+    let presets: Vec<_> = variants.iter().map(|desc| desc.presets.len()).collect();
+    assert_eq!(presets, [1, 0, 1, 1]);
+}
+
+#[test]
+fn controls() {
+    // Linked, one control sets the gain of every channel.
+    let linked = channels::expand(gain(), &[variant(10, "linked", 3, true)]).pop().unwrap();
+    assert_eq!(run(linked, &[(3, 2.0)]), [2.0, 4.0, 6.0]);
+    // Otherwise each channel has its own.
+    let unlinked = channels::expand(gain(), &[variant(11, "unlinked", 3, false)]).pop().unwrap();
+    assert_eq!(run(unlinked, &[(3, 2.0), (4, 0.5), (5, 0.0)]), [2.0, 1.0, 0.0]);
+}
+
+#[test]
+fn latency() {
+    let latent = block::wrap(gain(), 2, "blocks".into(), 4, 4).with_latency_port();
+    let stereo = channels::expand(latent, &[variant(10, "stereo", 2, false)]).pop().unwrap();
+    // The latency port is shared rather than repeated.
+    assert_eq!(names(&stereo).iter().filter(|name| name.contains("latency")).count(), 1);
+    let desc = Descriptor::from_native(stereo);
+    let mut instance = Instance::new(&desc, 48000, 4).unwrap();
+    instance.activate();
+    instance.run(4);
+    assert_eq!(instance.latency(), Some(3));
+}
+
+#[test]
+fn no_channels() {
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let hook_reports = reports.clone();
+    diagnostics::set_hook(Box::new(move |msg: &str| {
+        hook_reports.lock().unwrap().push(msg.to_string())
+    }));
+    let variants = channels::expand(gain(), &[variant(10, "empty", 0, true),
+                                              variant(11, "mono", 1, true)]);
+    diagnostics::clear_hook();
+    assert_eq!(variants.iter().map(|desc| &*desc.label).collect::<Vec<_>>(), ["mono"]);
+    assert_eq!(*reports.lock().unwrap(), ["empty has no channels, so it is skipped"]);
+}
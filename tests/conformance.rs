@@ -0,0 +1,39 @@
+//! Checks the plugins and adapters provided by this crate against the LADSPA specification.
+
+#![cfg(feature = "testing")]
+
+#[macro_use] extern crate ladspa;
+
+use ladspa::{block, bypass, expr, mix, oversample, serial, PluginDescriptor};
+use ladspa::channels::{self, Variant};
+use ladspa::serial::Stage;
+
+// A plugin with state, a logarithmic control and an integer control.
+fn filter() -> PluginDescriptor {
+    expr::descriptor(1, "filter".into(), "Filter".into(), "".into(), "".into(), "
+        input in
+        control cutoff = 1000 [20, 20000] log
+        control gain = 1 [1, 4] integer
+        out = out + (1 - exp(-2 * pi * cutoff / sr)) * (in * gain - out)
+    ").unwrap()
+}
+
+ladspa_conformance!(expr, filter());
+ladspa_conformance!(block, block::wrap(filter(), 4, "block".into(), 64, 32));
+ladspa_conformance!(oversample, oversample::wrap(filter(),
+                                                 5,
+                                                 "oversampled".into(),
+                                                 oversample::Factor::Control { max: 8 }));
+ladspa_conformance!(bypass, bypass::wrap(filter(), 6, "bypass".into(), 0.01));
+ladspa_conformance!(mix, mix::wrap(filter(), 7, "mix".into()));
+ladspa_conformance!(stereo, channels::expand(filter(), &[Variant {
+    unique_id: 2,
+    label: "stereo".into(),
+    name: "Stereo".into(),
+    channels: 2,
+    linked: false,
+}]).remove(0));
+ladspa_conformance!(serial, serial::compose(3, "serial".into(), "Serial".into(), "".into(), "".into(), vec![
+    Stage { prefix: "A".into(), plugin: filter() },
+    Stage { prefix: "B".into(), plugin: block::wrap(filter(), 4, "block".into(), 32, 32) },
+]));
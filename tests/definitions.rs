@@ -0,0 +1,189 @@
+//! Parses and validates plugin definitions, and exports them as a plugin library would.
+
+#![cfg(feature = "formats")]
+
+extern crate ladspa;
+
+use std::fs;
+use std::sync::{Arc, Mutex};
+
+use ladspa::{diagnostics, expr, PluginDescriptor};
+use ladspa::definitions::{self, Definitions, Error, Template};
+
+fn gain() -> PluginDescriptor {
+    expr::descriptor(1, "gain".into(), "Gain".into(), "Someone".into(), "None".into(), "
+        input in
+        control level = 1 [0, 2]
+        control mode = 0 [0, 1] toggled
+        out = in * level
+    ").unwrap()
+}
+
+fn offset() -> PluginDescriptor {
+    expr::descriptor(2, "offset".into(), "Offset".into(), "".into(), "".into(), "out = 1").unwrap()
+}
+
+const TEMPLATES: [Template; 1] = [Template { name: "gain", build: gain }];
+
+const MORE_TEMPLATES: [Template; 2] = [
+    Template { name: "gain", build: gain },
+    Template { name: "offset", build: offset },
+];
+
+// Builds a single definition, returning the descriptor or the error message.
+fn build(toml: &str) -> Result<PluginDescriptor, String> {
+    let defs = Definitions::from_toml(toml).map_err(|err| err.to_string())?;
+    assert_eq!(defs.plugins.len(), 1);
+    defs.build(&TEMPLATES).pop().unwrap().map_err(|err| err.to_string())
+}
+
+fn error(toml: &str) -> String {
+    build(toml).err().expect("the definition built")
+}
+
+#[test]
+fn parse() {
+    let defs = Definitions::from_toml(r#"
+        [[plugin]]
+        template = "gain"
+        unique_id = 5000
+        label = "quiet_gain"
+        name = "Quiet Gain"
+        channels = 2
+        linked = true
+
+        [plugin.ports.level]
+        upper_bound = 0.5
+        default = "maximum"
+
+        [[plugin.preset]]
+        name = "Half"
+        values = { level = 0.25 }
+    "#).unwrap();
+    let def = &defs.plugins[0];
+    assert_eq!((&*def.template, def.unique_id, &*def.label), ("gain", 5000, "quiet_gain"));
+    assert_eq!(def.name.as_deref(), Some("Quiet Gain"));
+    assert_eq!(def.maker, None);
+    assert_eq!((def.channels, def.linked), (Some(2), true));
+    assert_eq!(def.ports["level"].upper_bound, Some(0.5));
+    assert_eq!(def.presets[0].values["level"], 0.25);
+
+    let desc = defs.build(&TEMPLATES).pop().unwrap().unwrap();
+    assert_eq!((desc.unique_id, &*desc.label, &*desc.name, &*desc.maker),
+               (5000, "quiet_gain", "Quiet Gain", "Someone"));
+    // Two channels of audio sharing one set of controls.
+    let names: Vec<_> = desc.ports.iter().map(|port| &*port.name).collect();
+    assert_eq!(names.iter().filter(|name| name.contains("level")).count(), 1);
+    assert_eq!(desc.ports.iter().filter(|port| port.desc.is_audio()).count(), 4);
+    assert_eq!(desc.presets.len(), 1);
+
+    let unknown_field = "[[plugin]]\ntemplate = \"gain\"\nunique_id = 1\nlabel = \"x\"\ncolour = 1";
+    match Definitions::from_toml(unknown_field) {
+        Err(Error::Parse(_)) => {}
+        result => panic!("parsed a definition with an unknown field: {:?}", result),
+    }
+}
+
+#[test]
+fn validation() {
+    let plugin = "[[plugin]]\ntemplate = \"gain\"\nunique_id = 5000\n";
+    assert_eq!(error(&format!("{}label = \"has space\"", plugin)),
+               "invalid plugin definition: has space: labels must not be empty or contain spaces");
+    // Strings handed to the host as C strings can't contain NUL.
+    let nul = "the label, name, maker, copyright and preset names must not contain NUL";
+    assert_eq!(error(&format!("{}label = \"x\"\nname = \"a\\u0000b\"", plugin)),
+               format!("invalid plugin definition: x: {}", nul));
+    assert_eq!(error(&format!("{}label = \"x\\u0000\"", plugin)),
+               format!("invalid plugin definition: x\0: {}", nul));
+    let preset = "[[plugin.preset]]\nname = \"\\u0000\"\nvalues = {}";
+    assert_eq!(error(&format!("{}label = \"x\"\n{}", plugin, preset)),
+               format!("invalid plugin definition: x: {}", nul));
+    assert_eq!(error("[[plugin]]\ntemplate = \"reverb\"\nunique_id = 5000\nlabel = \"x\""),
+               "unknown template: reverb in x");
+    assert_eq!(error(&format!("{}label = \"x\"\n[plugin.ports.volume]\ndefault = \"1\"", plugin)),
+               "unknown port: x.volume");
+    assert_eq!(error(&format!("{}label = \"x\"\n[plugin.ports.level]\ndefault = \"loud\"", plugin)),
+               "invalid plugin definition: x: loud is not a default value for level");
+    assert_eq!(error(&format!("{}label = \"x\"\n[plugin.ports.level]\nlower_bound = 3.0", plugin)),
+               "invalid plugin definition: x: level has a lower bound above its upper bound");
+    let preset = "[[plugin.preset]]\nname = \"Loud\"\nvalues = { level = 3.0 }";
+    assert_eq!(error(&format!("{}label = \"x\"\n{}", plugin, preset)),
+               "invalid plugin definition: x: preset \"Loud\" sets level out of range");
+    assert_eq!(error(&format!("{}label = \"x\"\nchannels = 0", plugin)),
+               "invalid plugin definition: x: a plugin needs at least one channel");
+    let preset = "[[plugin.preset]]\nname = \"Half\"\nvalues = { level = 0.5 }";
+    assert_eq!(error(&format!("{}label = \"x\"\nchannels = 2\n{}", plugin, preset)),
+               "invalid plugin definition: x: presets need linked controls when there is more than one \
+                channel");
+
+    // Later definitions can't reuse a label or unique ID, but the earlier one still builds.
+    let defs = Definitions::from_toml(r#"
+        [[plugin]]
+        template = "gain"
+        unique_id = 5000
+        label = "a"
+
+        [[plugin]]
+        template = "gain"
+        unique_id = 5001
+        label = "a"
+
+        [[plugin]]
+        template = "gain"
+        unique_id = 5000
+        label = "b"
+    "#).unwrap();
+    let results: Vec<_> = defs.build(&TEMPLATES)
+        .into_iter()
+        .map(|result| result.map(|_| ()).map_err(|err| err.to_string()))
+        .collect();
+    assert_eq!(results, [
+        Ok(()),
+        Err("invalid plugin definition: a: label is used by an earlier plugin".to_string()),
+        Err("invalid plugin definition: b: unique_id 5000 is used by an earlier plugin".to_string()),
+    ]);
+}
+
+#[test]
+fn exported() {
+    // The definitions file is found next to the library, which here is the test executable.
+    let path = definitions::definitions_path().unwrap();
+    fs::write(&path, r#"
+        [[plugin]]
+        template = "gain"
+        unique_id = 5000
+        label = "gain_a"
+
+        [[plugin]]
+        template = "offset"
+        unique_id = 5001
+        label = "offset_a"
+
+        [[plugin]]
+        template = "gain"
+        unique_id = 5002
+        label = "gain_b"
+    "#).unwrap();
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let hook_reports = reports.clone();
+    diagnostics::set_hook(Box::new(move |msg: &str| {
+        hook_reports.lock().unwrap().push(msg.to_string())
+    }));
+
+    let labels = |templates: &[Template]| {
+        (0..).map(|index| definitions::descriptor(templates, index))
+            .take_while(|desc| desc.is_some())
+            .map(|desc| desc.unwrap().label.into_owned())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(labels(&TEMPLATES), ["gain_a", "gain_b"]);
+    assert_eq!(reports.lock().unwrap().len(), 1);
+    assert!(reports.lock().unwrap()[0].ends_with("unknown template: offset in offset_a"));
+    // Other templates are validated separately.
+    assert_eq!(labels(&MORE_TEMPLATES), ["gain_a", "offset_a", "gain_b"]);
+    assert_eq!(labels(&TEMPLATES), ["gain_a", "gain_b"]);
+    assert_eq!(reports.lock().unwrap().len(), 1);
+
+    diagnostics::clear_hook();
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,155 @@
+//! Checks how expression programs are parsed, compiled and run.
+
+extern crate ladspa;
+
+use ladspa::{expr, Data, PortDescriptor, HINT_LOGARITHMIC};
+use ladspa::host::{Descriptor, Instance};
+
+fn compile(source: &str) -> Result<Descriptor, expr::Error> {
+    expr::descriptor(1, "test".into(), "Test".into(), "".into(), "".into(), source)
+        .map(Descriptor::from_native)
+}
+
+// Runs a program with every audio input fed the same samples, returning its first output.
+fn run(source: &str, input: &[Data]) -> Vec<Data> {
+    let desc = compile(source).unwrap();
+    let mut instance = Instance::new(&desc, 48000, input.len()).unwrap();
+    for port in 0..desc.port_count() {
+        if desc.port_descriptor(port) == PortDescriptor::AudioInput {
+            instance.audio_mut(port).copy_from_slice(input);
+        }
+    }
+    instance.activate();
+    instance.run(input.len());
+    let output = (0..desc.port_count())
+        .find(|&port| desc.port_descriptor(port) == PortDescriptor::AudioOutput)
+        .unwrap();
+    instance.audio(output).to_vec()
+}
+
+// The value of a program without inputs.
+fn value(source: &str) -> Data {
+    run(source, &[0.0])[0]
+}
+
+fn error(source: &str) -> String {
+    compile(source).err().expect("the program compiled").to_string()
+}
+
+#[test]
+fn precedence() {
+    assert_eq!(value("out = 1 + 2 * 3"), 7.0);
+    assert_eq!(value("out = (1 + 2) * 3"), 9.0);
+    assert_eq!(value("out = 10 - 4 - 3"), 3.0);
+    assert_eq!(value("out = 7 % 4 * 2"), 6.0);
+    assert_eq!(value("out = 1 + 2 < 4"), 1.0);
+    assert_eq!(value("out = 2 * 3 == 6"), 1.0);
+    assert_eq!(value("out = if(1 > 2, 10, 20) + clamp(5, 0, 1)"), 21.0);
+}
+
+#[test]
+fn powers() {
+    assert_eq!(value("out = 2 ^ 3 ^ 2"), 512.0);
+    assert_eq!(value("out = 2 * 3 ^ 2"), 18.0);
+    assert_eq!(value("out = 2 ^ -1"), 0.5);
+    assert_eq!(value("out = pow(2, 10)"), 1024.0);
+}
+
+#[test]
+fn unary_minus() {
+    assert_eq!(value("out = -2 ^ 2"), -4.0);
+    assert_eq!(value("out = (-2) ^ 2"), 4.0);
+    assert_eq!(value("out = --3"), 3.0);
+    assert_eq!(value("out = 2 * -3"), -6.0);
+    assert_eq!(value("out = 1 - -1"), 2.0);
+}
+
+#[test]
+fn previous_sample() {
+    // An output read before it is assigned has its value from the previous sample.
+    assert_eq!(run("input in; y = y + in", &[1.0, 2.0, 3.0]), [1.0, 3.0, 6.0]);
+    // So does a temporary assigned after it is read.
+    assert_eq!(run("input in\nout = last\nlet last = in", &[1.0, 2.0, 3.0]), [0.0, 1.0, 2.0]);
+
+    // Activation starts again from 0.
+    let desc = compile("input in; y = y + in").unwrap();
+    let mut instance = Instance::new(&desc, 48000, 2).unwrap();
+    instance.audio_mut(0).copy_from_slice(&[1.0, 1.0]);
+    instance.activate();
+    instance.run(2);
+    assert_eq!(instance.audio(1), [1.0, 2.0]);
+    instance.deactivate();
+    instance.activate();
+    instance.run(2);
+    assert_eq!(instance.audio(1), [1.0, 2.0]);
+}
+
+#[test]
+fn errors() {
+    assert_eq!(error("input in\n\nout = in +\n"),
+               "syntax error on line 3: expected an expression, found end of statement");
+    assert_eq!(error("# Comment\nout = nope"), "error on line 2: unknown name nope");
+    assert_eq!(error("input in\nin = 1"), "error on line 2: in is an input");
+    assert_eq!(error("input a, a"), "error on line 1: a is already declared");
+    assert_eq!(error("input sin\nout = 1"), "error on line 1: sin is a reserved name");
+    assert_eq!(error("out = 1\nout2 = max(1)"), "syntax error on line 2: max takes 2 arguments");
+    assert_eq!(error("input in"), "error on line 1: the program has no outputs");
+    assert_eq!(error("out = 1 $ 2"), "syntax error on line 1: unexpected character $");
+    assert_eq!(error("control x = 5\nout = x"),
+               "syntax error on line 1: x needs a range for its default to be 5");
+    assert_eq!(error("out = 1\ncontrol x = 5 [1, 0]"),
+               "syntax error on line 2: x has a lower bound above its upper bound");
+    assert_eq!(error("control x = 5 [0, 1]"), "syntax error on line 1: the default of x is out of range");
+    assert_eq!(error("control x = 5 [-1e39, 1e39]\nout = x"),
+               "syntax error on line 1: 1000000000000000000000000000000000000000 is out of range");
+}
+
+#[test]
+fn nesting() {
+    // Deeply nested expressions fail to compile rather than overflowing the stack.
+    let too_deep = "syntax error on line 1: the expression is nested too deeply";
+    let deep = 100000;
+    assert_eq!(error(&format!("out = {}1{}", "(".repeat(deep), ")".repeat(deep))), too_deep);
+    assert_eq!(error(&format!("out = {}1", "-".repeat(deep))), too_deep);
+    assert_eq!(error(&format!("out = 1{}", "^1".repeat(deep))), too_deep);
+    assert_eq!(error(&format!("out = {}1{}", "sin(".repeat(deep), ")".repeat(deep))), too_deep);
+    assert_eq!(error(&format!("out = 1{}", "+1".repeat(deep))), too_deep);
+    assert_eq!(error(&format!("out = 1{}", "*1".repeat(deep))), too_deep);
+
+    // Reasonably deep ones are fine.
+    assert_eq!(value(&format!("out = {}1{}", "(".repeat(100), ")".repeat(100))), 1.0);
+    assert_eq!(value(&format!("out = 0{}", "+1".repeat(200))), 200.0);
+}
+
+#[test]
+fn ports() {
+    let desc = compile("
+        input left, right
+        control gain = 1 [0, 2]
+        control mix = 0.3 [0, 1]
+        control cutoff = 1000 [20, 20000] log
+        let sum = left + right
+        out = sum * gain
+        dry = left
+    ").unwrap();
+    let ports: Vec<_> = (0..desc.port_count())
+        .map(|port| (desc.port_name(port), desc.port_descriptor(port)))
+        .collect();
+    assert_eq!(ports, [
+        ("left".to_string(), PortDescriptor::AudioInput),
+        ("right".to_string(), PortDescriptor::AudioInput),
+        ("gain".to_string(), PortDescriptor::ControlInput),
+        ("mix".to_string(), PortDescriptor::ControlInput),
+        ("cutoff".to_string(), PortDescriptor::ControlInput),
+        ("out".to_string(), PortDescriptor::AudioOutput),
+        ("dry".to_string(), PortDescriptor::AudioOutput),
+    ]);
+
+    assert_eq!(desc.port_bounds(2, 48000), (Some(0.0), Some(2.0)));
+    assert_eq!(desc.port_default(2, 48000), Some(1.0));
+    // Defaults LADSPA can't describe are rounded to the nearest one it can.
+    assert_eq!(desc.port_default(3, 48000), Some(0.25));
+    assert!(desc.port_hint(4).contains(HINT_LOGARITHMIC));
+    let middle = desc.port_default(4, 48000).unwrap();
+    assert!((middle - (20.0 * 20000.0 as Data).sqrt()).abs() < 0.01, "{}", middle);
+}
@@ -0,0 +1,115 @@
+//! Checks that plugins which refuse to be created or activated fail safely at the C interface.
+
+// Written in the same style as the crate itself.
+#![allow(bare_trait_objects)]
+
+extern crate ladspa;
+
+use std::sync::{Arc, Mutex};
+
+use ladspa::{channels, diagnostics, lifecycle, Data, Plugin, PluginDescriptor, PluginError, Port,
+             PortConnection, PortDescriptor, PROP_NONE};
+use ladspa::channels::Variant;
+use ladspa::ffi::ladspa_h;
+use ladspa::host::Descriptor;
+use ladspa::lifecycle::State;
+
+// Only runs at up to 96kHz, and can't be activated the first time.
+struct Picky {
+    deactivated: Arc<Mutex<usize>>,
+}
+
+impl Plugin for Picky {
+    fn activate(&mut self) -> Result<(), PluginError> {
+        if lifecycle::state() == Some(State::Instantiated) {
+            return Err("not yet".into());
+        }
+        Ok(())
+    }
+
+    fn run<'a>(&mut self, sample_count: usize, ports: &[&'a PortConnection<'a>]) {
+        for x in ports[0].unwrap_audio_mut()[..sample_count].iter_mut() {
+            *x = 1.0;
+        }
+    }
+
+    fn deactivate(&mut self) {
+        *self.deactivated.lock().unwrap() += 1;
+    }
+}
+
+fn picky(deactivated: Arc<Mutex<usize>>) -> PluginDescriptor {
+    PluginDescriptor {
+        unique_id: 1,
+        label: "picky".into(),
+        properties: PROP_NONE,
+        name: "Picky".into(),
+        maker: "".into(),
+        copyright: "".into(),
+        ports: vec![Port { name: "Output".into(), desc: PortDescriptor::AudioOutput, ..Default::default() }],
+        new: Box::new(move |_: &PluginDescriptor, sample_rate| {
+            if sample_rate > 96000 {
+                return Err(format!("{}Hz is not supported", sample_rate).into());
+            }
+            Ok(Box::new(Picky { deactivated: deactivated.clone() }) as Box<Plugin + Send>)
+        }),
+        presets: vec![],
+    }
+}
+
+#[test]
+fn failures() {
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let hook_reports = reports.clone();
+    diagnostics::set_hook(Box::new(move |msg: &str| hook_reports.lock().unwrap().push(msg.to_string())));
+    let deactivated = Arc::new(Mutex::new(0));
+
+    let desc = Descriptor::from_native(picky(deactivated.clone()));
+    let raw: &ladspa_h::Descriptor = desc.as_raw();
+    assert!((raw.instantiate)(raw, 192000).is_null());
+    assert_eq!(reports.lock().unwrap().pop().unwrap(),
+               "picky failed to instantiate at 192000Hz: 192000Hz is not supported");
+
+    let (activate, deactivate) = (raw.activate.unwrap(), raw.deactivate.unwrap());
+    let mut output = [0.5 as Data; 4];
+    let handle = (raw.instantiate)(raw, 48000);
+    (raw.connect_port)(handle, 0, output.as_mut_ptr());
+    activate(handle);
+    assert_eq!(reports.lock().unwrap().pop().unwrap(),
+               "picky failed to activate, so it will output silence: not yet");
+    (raw.run)(handle, 4);
+    assert_eq!(output, [0.0; 4]);
+    deactivate(handle);
+    assert_eq!(*deactivated.lock().unwrap(), 0);
+
+    // The second activation succeeds.
+    activate(handle);
+    (raw.run)(handle, 4);
+    assert_eq!(output, [1.0; 4]);
+    deactivate(handle);
+    (raw.cleanup)(handle);
+    assert_eq!(*deactivated.lock().unwrap(), 1);
+
+    // Adapters pass failures on.
+    let stereo = channels::expand(picky(deactivated.clone()), &[Variant {
+        unique_id: 2,
+        label: "picky_stereo".into(),
+        name: "Picky (stereo)".into(),
+        channels: 2,
+        linked: true,
+    }]).pop().unwrap();
+    let stereo = Descriptor::from_native(stereo);
+    assert!((stereo.as_raw().instantiate)(stereo.as_raw(), 192000).is_null());
+    assert_eq!(reports.lock().unwrap().pop().unwrap(),
+               "picky_stereo failed to instantiate at 192000Hz: 192000Hz is not supported");
+
+    // Strings which can't be C strings are cut short rather than failing.
+    let mut cut = picky(deactivated.clone());
+    cut.name = "Pic\0ky".into();
+    let cut = Descriptor::from_native(cut);
+    assert_eq!(cut.name(), "Pic");
+    assert_eq!(reports.lock().unwrap().pop().unwrap(),
+               "\"Pic\\0ky\" contains a NUL, so it is cut short");
+    diagnostics::clear_hook();
+    assert!(reports.lock().unwrap().iter().all(|msg| !msg.starts_with("host error")));
+}
@@ -0,0 +1,73 @@
+//! Uses plugins loaded through the C interface as native ones.
+
+extern crate ladspa;
+
+use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+use std::path::PathBuf;
+use std::process::Command;
+
+use ladspa::{block, expr, foreign, mix, Data};
+use ladspa::host::{Descriptor, Instance, Library};
+
+// Builds the delay example, returning the path to the library.
+fn build_delay() -> PathBuf {
+    let manifest = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/delay/Cargo.toml");
+    let target = env!("CARGO_TARGET_TMPDIR");
+    let status = Command::new(option_env!("CARGO").unwrap_or("cargo"))
+        .args(["build", "--quiet", "--manifest-path", manifest, "--target-dir", target])
+        .status()
+        .expect("failed to run cargo");
+    assert!(status.success(), "failed to build the delay example");
+    PathBuf::from(target).join("debug").join(format!("{}rustdelay{}", DLL_PREFIX, DLL_SUFFIX))
+}
+
+// Runs an instance on a ramp with the given control values, returning its first output.
+fn run(desc: &Descriptor, controls: &[(usize, Data)]) -> Vec<Data> {
+    let mut instance = Instance::new(desc, 8, 6).unwrap();
+    for &(port, value) in controls {
+        instance.set_control(port, value);
+    }
+    instance.audio_mut(0).copy_from_slice(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+    instance.activate();
+    instance.run(6);
+    instance.audio(1).to_vec()
+}
+
+#[test]
+fn library() {
+    let lib = Library::open(build_delay()).unwrap();
+    let original = Library::descriptors(&lib).into_iter().find(|desc| desc.label() == "mono_delay")
+        .unwrap();
+    let wrapped = foreign::wrap(&original);
+    assert_eq!((wrapped.unique_id, &*wrapped.label, &*wrapped.name),
+               (402, "mono_delay", "Mono Delay"));
+    assert_eq!(wrapped.properties, original.properties());
+    let names: Vec<_> = wrapped.ports.iter().map(|port| port.name.to_string()).collect();
+    assert_eq!(names, ["Audio In", "Audio Out", "Delay (seconds)", "Dry/Wet"]);
+
+    // At 8Hz, 0.25 seconds is 2 samples.
+    let controls = [(2, 0.25), (3, 1.0)];
+    let expected = [0.0, 0.0, 1.0, 2.0, 3.0, 4.0];
+    assert_eq!(run(&original, &controls), expected);
+    assert_eq!(run(&Descriptor::from_native(wrapped), &controls), expected);
+
+    // Wrapped again with a dry/wet control, the plugin is run through the original library.
+    let mixed = Descriptor::from_native(mix::wrap(foreign::wrap(&original), 4402, "mixed".into()));
+    assert_eq!(run(&mixed, &[(2, 0.25), (3, 1.0), (4, 0.5)]), [0.5, 1.0, 2.0, 3.0, 4.0, 5.0]);
+}
+
+#[test]
+fn latency() {
+    // The latency reported through the C interface becomes that of the wrapped plugin, which
+    // keeps the original's latency port.
+    let inner = expr::descriptor(1, "through".into(), "Through".into(), "".into(), "".into(), "
+        input in
+        out = in
+    ").unwrap();
+    let latent = block::wrap(inner, 2, "blocks".into(), 4, 4).with_latency_port();
+    let desc = Descriptor::from_native(foreign::wrap(&Descriptor::from_native(latent)));
+    let mut instance = Instance::new(&desc, 48000, 4).unwrap();
+    instance.activate();
+    instance.run(4);
+    assert_eq!(instance.latency(), Some(3));
+}
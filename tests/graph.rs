@@ -0,0 +1,142 @@
+//! Connects plugins into graphs and checks what comes out.
+
+// Written in the same style as the crate itself.
+#![allow(bare_trait_objects)]
+
+extern crate ladspa;
+
+use ladspa::{block, expr, Data, Plugin, PluginDescriptor, Port, PortConnection, PortDescriptor,
+             PROP_NONE};
+use ladspa::graph::{Error, Graph};
+
+// A plugin with one audio input and one audio output.
+fn plugin(source: &str) -> PluginDescriptor {
+    expr::descriptor(1, "test".into(), "Test".into(), "".into(), "".into(), source).unwrap()
+}
+
+// Outputs a constant from its only port, a control output.
+struct Constant {
+    value: Data,
+}
+
+impl Plugin for Constant {
+    fn run<'a>(&mut self, _: usize, ports: &[&'a PortConnection<'a>]) {
+        **ports[0].unwrap_control_mut() = self.value;
+    }
+}
+
+fn constant(output: Data) -> PluginDescriptor {
+    PluginDescriptor {
+        unique_id: 2,
+        label: "constant".into(),
+        properties: PROP_NONE,
+        name: "Constant".into(),
+        maker: "".into(),
+        copyright: "".into(),
+        ports: vec![Port {
+            name: "Value".into(),
+            desc: PortDescriptor::ControlOutput,
+            ..Default::default()
+        }],
+        new: Box::new(move |_: &PluginDescriptor, _| {
+            Ok(Box::new(Constant { value: output }) as Box<Plugin + Send>)
+        }),
+        presets: vec![],
+    }
+}
+
+#[test]
+fn fan_in() {
+    let mut graph = Graph::new(48000, 2, 1, 1);
+    let double = graph.add_plugin(plugin("input in; out = in * 2")).unwrap();
+    let triple = graph.add_plugin(plugin("input in; out = in * 3")).unwrap();
+    let sum = graph.add_plugin(plugin("input in; out = in")).unwrap();
+    graph.connect_input(0, double, 0).unwrap();
+    graph.connect_input(0, triple, 0).unwrap();
+    // Both outputs feed the same input, which receives their sum.
+    graph.connect(double, 1, sum, 0).unwrap();
+    graph.connect(triple, 1, sum, 0).unwrap();
+    graph.connect_output(sum, 1, 0).unwrap();
+    // So does a graph output fed twice.
+    graph.connect_output(double, 1, 0).unwrap();
+
+    let mut output = [0.0; 3];
+    graph.activate();
+    graph.process(&[&[1.0, 2.0, 3.0]], &mut [&mut output]);
+    assert_eq!(output, [7.0, 14.0, 21.0]);
+}
+
+#[test]
+fn cycles() {
+    let mut graph = Graph::new(48000, 4, 1, 1);
+    let a = graph.add_plugin(plugin("input in; out = in + 1")).unwrap();
+    let b = graph.add_plugin(plugin("input in; out = in * 2")).unwrap();
+    graph.connect_input(0, a, 0).unwrap();
+    graph.connect(a, 1, b, 0).unwrap();
+    graph.connect_output(b, 1, 0).unwrap();
+    match graph.connect(b, 1, a, 0) {
+        Err(Error::Cycle) => {}
+        result => panic!("connected a cycle: {:?}", result),
+    }
+    match graph.connect(a, 1, a, 0) {
+        Err(Error::Cycle) => {}
+        result => panic!("connected a node to itself: {:?}", result),
+    }
+    match graph.connect(a, 0, b, 0) {
+        Err(Error::PortMismatch) => {}
+        result => panic!("connected an input to an input: {:?}", result),
+    }
+
+    // The rejected connections leave the graph as it was.
+    let mut output = [0.0; 2];
+    graph.activate();
+    graph.process(&[&[1.0, 2.0]], &mut [&mut output]);
+    assert_eq!(output, [4.0, 6.0]);
+}
+
+#[test]
+fn controls() {
+    let mut graph = Graph::new(48000, 2, 1, 1);
+    let two = graph.add_plugin(constant(2.0)).unwrap();
+    let three = graph.add_plugin(constant(3.0)).unwrap();
+    let gain = plugin("input in; control gain = 1 [0, 4]; out = in * gain");
+    let gain = graph.add_plugin(gain).unwrap();
+    graph.connect_input(0, gain, 0).unwrap();
+    graph.connect_output(gain, 2, 0).unwrap();
+    graph.connect(two, 0, gain, 1).unwrap();
+    // Connecting the same output again changes nothing, but a control input takes only one.
+    graph.connect(two, 0, gain, 1).unwrap();
+    match graph.connect(three, 0, gain, 1) {
+        Err(Error::ControlConnected) => {}
+        result => panic!("connected a second control output: {:?}", result),
+    }
+
+    let mut output = [0.0; 2];
+    graph.activate();
+    graph.process(&[&[1.0, 2.0]], &mut [&mut output]);
+    assert_eq!(output, [2.0, 4.0]);
+}
+
+// Delays its input by running it in blocks, and reports the latency to the host.
+fn latent(unique_id: u64, block_size: usize) -> PluginDescriptor {
+    let label = format!("block_{}", block_size);
+    block::wrap(plugin("input in; out = in"), unique_id, label.into(), block_size, block_size)
+        .with_latency_port()
+}
+
+#[test]
+fn latency() {
+    let mut graph = Graph::new(48000, 16, 1, 1);
+    let short = graph.add_plugin(latent(2, 4)).unwrap();
+    let long = graph.add_plugin(latent(3, 8)).unwrap();
+    graph.connect_input(0, short, 0).unwrap();
+    graph.connect(short, 1, long, 0).unwrap();
+    graph.connect_output(long, 1, 0).unwrap();
+
+    let input: Vec<_> = (1..11).map(|x| x as f32).collect();
+    let mut output = vec![0.0; input.len()];
+    graph.activate();
+    graph.render(&[&input], &mut [&mut output]);
+    assert_eq!(graph.latency(), 3 + 7);
+    assert_eq!(output, input);
+}
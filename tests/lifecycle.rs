@@ -0,0 +1,123 @@
+//! Drives a plugin through calls in an order the LADSPA specification doesn't allow, and checks
+//! that the plugin only sees a valid lifecycle.
+
+// Written in the same style as the crate itself.
+#![allow(bare_trait_objects)]
+
+extern crate ladspa;
+
+use std::sync::{Arc, Mutex};
+
+use ladspa::{diagnostics, lifecycle, Data, Plugin, PluginDescriptor, PluginError, Port, PortConnection,
+             PortDescriptor};
+use ladspa::ffi::ladspa_h;
+use ladspa::host::Descriptor;
+use ladspa::lifecycle::State;
+
+// The calls a plugin received, with the state the C interface reported during each.
+type Calls = Arc<Mutex<Vec<(&'static str, Option<State>)>>>;
+
+// Records every call it receives.
+struct Recorder {
+    calls: Calls,
+}
+
+impl Recorder {
+    fn record(&self, call: &'static str) {
+        self.calls.lock().unwrap().push((call, lifecycle::state()));
+    }
+}
+
+impl Plugin for Recorder {
+    fn activate(&mut self) -> Result<(), PluginError> {
+        self.record("activate");
+        Ok(())
+    }
+
+    fn run<'a>(&mut self, sample_count: usize, ports: &[&'a PortConnection<'a>]) {
+        self.record("run");
+        for x in ports[0].unwrap_audio_mut()[..sample_count].iter_mut() {
+            *x = 1.0;
+        }
+    }
+
+    fn deactivate(&mut self) {
+        self.record("deactivate");
+    }
+}
+
+fn recorder(calls: Calls) -> PluginDescriptor {
+    PluginDescriptor {
+        unique_id: 1,
+        label: "recorder".into(),
+        properties: ladspa::PROP_NONE,
+        name: "Recorder".into(),
+        maker: "".into(),
+        copyright: "".into(),
+        ports: vec![Port { name: "Output".into(), desc: PortDescriptor::AudioOutput, ..Default::default() }],
+        new: Box::new(move |_: &PluginDescriptor, _| {
+            Ok(Box::new(Recorder { calls: calls.clone() }) as Box<Plugin + Send>)
+        }),
+        presets: vec![],
+    }
+}
+
+#[test]
+fn host_errors() {
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let hook_reports = reports.clone();
+    diagnostics::set_hook(Box::new(move |msg: &str| hook_reports.lock().unwrap().push(msg.to_string())));
+
+    let desc = Descriptor::from_native(recorder(calls.clone()));
+    let raw: &ladspa_h::Descriptor = desc.as_raw();
+    let (activate, deactivate) = (raw.activate.unwrap(), raw.deactivate.unwrap());
+    let mut output = [0.5 as Data; 4];
+    let handle = (raw.instantiate)(raw, 48000);
+
+    // Not every port is connected yet.
+    (raw.run)(handle, 4);
+    (raw.connect_port)(handle, 0, output.as_mut_ptr());
+    (raw.connect_port)(handle, 1, output.as_mut_ptr());
+    // Not activated yet.
+    (raw.run)(handle, 4);
+    let unactivated = output[0];
+    activate(handle);
+    activate(handle);
+    (raw.run)(handle, 4);
+    deactivate(handle);
+    deactivate(handle);
+    activate(handle);
+    (raw.cleanup)(handle);
+    diagnostics::clear_hook();
+
+    let calls = calls.lock().unwrap().clone();
+    let reports = reports.lock().unwrap().clone();
+    assert!(reports.iter().all(|msg| msg.starts_with("host error in ")), "{:?}", reports);
+    let reported = |what: &str| reports.iter().any(|msg| msg.contains(what));
+    if lifecycle::STRICT {
+        assert_eq!(unactivated, 0.0);
+        assert_eq!(calls, vec![
+            ("activate", Some(State::Instantiated)),
+            ("run", Some(State::Active)),
+            ("deactivate", Some(State::Active)),
+            ("activate", Some(State::Deactivated)),
+            ("deactivate", Some(State::Active)),
+        ]);
+        assert_eq!(reports.len(), 6, "{:?}", reports);
+        assert!(reported("run called before every port was connected"));
+        assert!(reported("connect_port called for port 1 of 1"));
+        assert!(reported("run called on an instance which is instantiated"));
+        assert!(reported("activate called twice without deactivate"));
+        assert!(reported("deactivate called on an instance which is deactivated"));
+        assert!(reported("cleanup called without deactivate"));
+    } else {
+        assert_eq!(unactivated, 1.0);
+        // The instance was already active when the host first activated it.
+        assert_eq!(calls.iter().map(|call| call.0).collect::<Vec<_>>(),
+                   ["activate", "run", "deactivate", "activate", "deactivate", "activate", "run", "deactivate",
+                    "activate", "deactivate"]);
+        assert!(reports.is_empty(), "{:?}", reports);
+    }
+    assert_eq!(lifecycle::state(), None);
+}
@@ -0,0 +1,85 @@
+//! Checks meter readings on signals whose levels are known.
+
+extern crate ladspa;
+
+use std::f32::consts::PI;
+
+use ladspa::Data;
+use ladspa::meter::{self, GainReduction, Meter, Peak, Rms, TruePeak, MIN_DB};
+
+fn close(a: Data, b: Data) -> bool {
+    (a - b).abs() < 1e-4
+}
+
+fn sine(sample_rate: u64, freq: f32, phase: f32, len: usize) -> Vec<Data> {
+    (0..len).map(|k| (2.0 * PI * freq * k as f32 / sample_rate as f32 + phase).sin()).collect()
+}
+
+#[test]
+fn decibels() {
+    assert_eq!(meter::to_db(1.0), 0.0);
+    assert!(close(meter::to_db(0.1), -20.0));
+    assert_eq!(meter::to_db(0.0), MIN_DB);
+    assert_eq!(meter::to_db(1e-9), MIN_DB);
+}
+
+#[test]
+fn peak() {
+    // Held for 2 samples, then falling by 2dB a sample.
+    let mut peak = Peak::new(10, 0.2, 20.0);
+    peak.process(&[0.5, -1.0]);
+    assert_eq!(peak.value(), 1.0);
+    let mut readings = Vec::new();
+    for _ in 0..4 {
+        peak.process(&[0.0]);
+        readings.push(meter::to_db(peak.value()));
+    }
+    assert!(close(readings[0], 0.0) && close(readings[1], 0.0), "{:?}", readings);
+    assert!(close(readings[2], -2.0) && close(readings[3], -4.0), "{:?}", readings);
+    // A louder sample is picked up straight away, but a NaN isn't.
+    peak.process(&[Data::NAN, 0.75]);
+    assert_eq!(peak.value(), 0.75);
+    peak.reset();
+    assert_eq!(peak.value(), 0.0);
+}
+
+#[test]
+fn rms() {
+    // Ten periods of a sine fill the window exactly.
+    let mut rms = Rms::new(48000, 0.01);
+    rms.process(&sine(48000, 1000.0, 0.0, 480));
+    assert!(close(rms.value(), 0.5f32.sqrt()), "{}", rms.value());
+    rms.process(&[0.5; 480]);
+    assert!(close(rms.value(), 0.5), "{}", rms.value());
+
+    // Samples which aren't finite are left out, and don't upset later readings.
+    rms.process(&[Data::NAN, Data::INFINITY, 1e30]);
+    assert!(rms.value().is_finite());
+    rms.process(&[0.25; 480]);
+    assert!(close(rms.value(), 0.25), "{}", rms.value());
+    rms.reset();
+    assert_eq!(rms.value(), 0.0);
+}
+
+#[test]
+fn true_peak() {
+    // At a quarter of the sample rate, with this phase every sample misses the peaks.
+    let signal = sine(48000, 12000.0, PI / 4.0, 256);
+    let mut peak = Peak::new(48000, 1.0, 20.0);
+    let mut true_peak = TruePeak::new(48000, 1.0, 20.0);
+    peak.process(&signal);
+    true_peak.process(&signal);
+    assert!(close(peak.value(), 0.5f32.sqrt()), "{}", peak.value());
+    assert!(true_peak.value() > 0.95 && true_peak.value() < 1.05, "{}", true_peak.value());
+}
+
+#[test]
+fn gain_reduction() {
+    // Recovering by 20dB a second, or 1dB a sample.
+    let mut reduction = GainReduction::new(20, 20.0);
+    assert_eq!(reduction.value(), 1.0);
+    reduction.process(&[1.0, 0.1, 1.0, 1.0]);
+    assert!(close(meter::to_db(reduction.value()), -18.0), "{}", reduction.value());
+    reduction.process(&[1.0; 40]);
+    assert_eq!(reduction.value(), 1.0);
+}
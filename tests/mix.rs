@@ -0,0 +1,56 @@
+//! Checks the dry/wet and output gain controls added by the mix adapter.
+
+extern crate ladspa;
+
+use ladspa::{block, expr, mix, Data, PluginDescriptor};
+use ladspa::host::{Descriptor, Instance};
+
+fn double() -> PluginDescriptor {
+    expr::descriptor(1, "double".into(), "Double".into(), "".into(), "".into(), "
+        input in
+        out = in * 2
+    ").unwrap()
+}
+
+fn run(instance: &mut Instance, mix: Data, gain_db: Data, input: &[Data]) -> Vec<Data> {
+    instance.set_control(2, mix);
+    instance.set_control(3, gain_db);
+    instance.audio_mut(0).copy_from_slice(input);
+    instance.run(input.len());
+    instance.audio(1).to_vec()
+}
+
+#[test]
+fn ports() {
+    let desc = Descriptor::from_native(mix::wrap(double(), 2, "mixed_double".into()));
+    assert_eq!((desc.unique_id(), desc.label()), (2, "mixed_double".to_string()));
+    assert_eq!((desc.port_name(2), desc.port_name(3)),
+               ("Dry/Wet".to_string(), "Output Gain (dB)".to_string()));
+    assert_eq!(desc.port_bounds(3, 48000), (Some(mix::MIN_GAIN_DB), Some(mix::MAX_GAIN_DB)));
+}
+
+#[test]
+fn ramps() {
+    let desc = Descriptor::from_native(mix::wrap(double(), 2, "mixed_double".into()));
+    let mut instance = Instance::new(&desc, 48000, 4).unwrap();
+    instance.activate();
+    // The first run after activation starts at the requested values.
+    assert_eq!(run(&mut instance, 1.0, 0.0, &[1.0; 4]), [2.0; 4]);
+    // Later changes are ramped over the whole call.
+    assert_eq!(run(&mut instance, 0.0, 0.0, &[1.0; 4]), [1.75, 1.5, 1.25, 1.0]);
+    assert_eq!(run(&mut instance, 0.0, 0.0, &[1.0; 4]), [1.0; 4]);
+
+    let output = run(&mut instance, 0.0, -20.0, &[1.0; 4]);
+    assert!((output[3] - 0.1).abs() < 1e-6, "{:?}", output);
+}
+
+#[test]
+fn latency() {
+    // Running on blocks of 4 delays the processed signal by 3 samples, and the dry one with it.
+    let latent = block::wrap(double(), 2, "blocks".into(), 4, 4);
+    let desc = Descriptor::from_native(mix::wrap(latent, 3, "mixed_blocks".into()));
+    let mut instance = Instance::new(&desc, 48000, 8).unwrap();
+    instance.activate();
+    assert_eq!(run(&mut instance, 0.5, 0.0, &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0]),
+               [0.0, 0.0, 0.0, 1.5, 3.0, 4.5, 6.0, 7.5]);
+}
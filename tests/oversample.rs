@@ -0,0 +1,116 @@
+//! Switches the oversampling factor while running, and checks the latency and ports that result.
+
+// Written in the same style as the crate itself.
+#![allow(bare_trait_objects)]
+
+extern crate ladspa;
+
+use std::sync::{Arc, Mutex};
+
+use ladspa::{diagnostics, oversample, Data, Plugin, PluginDescriptor, PluginError, Port,
+             PortConnection, PortDescriptor, PROP_NONE};
+use ladspa::host::{Descriptor, Instance};
+use ladspa::oversample::Factor;
+
+// Outputs the sample rate it was instantiated at, and can't be activated above 96kHz.
+struct Rate {
+    sample_rate: u64,
+}
+
+impl Plugin for Rate {
+    fn activate(&mut self) -> Result<(), PluginError> {
+        if self.sample_rate > 96000 {
+            return Err(format!("{}Hz is too fast", self.sample_rate).into());
+        }
+        Ok(())
+    }
+
+    fn run<'a>(&mut self, _: usize, ports: &[&'a PortConnection<'a>]) {
+        **ports[0].unwrap_control_mut() = self.sample_rate as Data;
+    }
+}
+
+// The rate plugin, with an extra control input already called "Oversampling".
+fn rate() -> PluginDescriptor {
+    let port = |name: &'static str, kind| {
+        Port { name: name.into(), desc: kind, ..Default::default() }
+    };
+    PluginDescriptor {
+        unique_id: 1,
+        label: "rate".into(),
+        properties: PROP_NONE,
+        name: "Rate".into(),
+        maker: "".into(),
+        copyright: "".into(),
+        ports: vec![port("Rate", PortDescriptor::ControlOutput),
+                    port("Oversampling", PortDescriptor::ControlInput)],
+        new: Box::new(|_: &PluginDescriptor, instance_rate| {
+            Ok(Box::new(Rate { sample_rate: instance_rate }) as Box<Plugin + Send>)
+        }),
+        presets: vec![],
+    }
+}
+
+fn instance(factor: Factor) -> Instance {
+    let desc = oversample::wrap(rate(), 2, "oversampled".into(), factor).with_latency_port();
+    Instance::new(&Descriptor::from_native(desc), 48000, 16).unwrap()
+}
+
+#[test]
+fn ports() {
+    let desc = Descriptor::from_native(oversample::wrap(rate(), 2, "oversampled".into(),
+                                                        Factor::Control { max: 8 }));
+    let names: Vec<_> = (0..desc.port_count()).map(|port| desc.port_name(port)).collect();
+    assert_eq!(names, ["Rate", "Oversampling", "Oversampling 2"]);
+    assert_eq!(desc.port_bounds(2, 48000), (Some(1.0), Some(8.0)));
+}
+
+#[test]
+fn latency() {
+    assert_eq!(oversample::latency(1), 0);
+    for &factor in &[1, 2] {
+        let mut instance = instance(Factor::Fixed(factor));
+        instance.activate();
+        instance.run(16);
+        assert_eq!(instance.control(0), (48000 * factor) as Data);
+        assert_eq!(instance.latency(), Some(oversample::latency(factor)));
+    }
+}
+
+#[test]
+fn switching() {
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let hook_reports = reports.clone();
+    diagnostics::set_hook(Box::new(move |msg: &str| {
+        hook_reports.lock().unwrap().push(msg.to_string())
+    }));
+
+    // Factors are rounded down to a power of two, and change the latency with them.
+    let mut instance = instance(Factor::Control { max: 4 });
+    instance.activate();
+    let mut rates = Vec::new();
+    for &value in &[1.0, 3.0, 2.0, 0.0] {
+        instance.set_control(2, value);
+        instance.run(16);
+        rates.push(instance.control(0));
+        assert_eq!(instance.latency(), Some(oversample::latency(value as usize)));
+    }
+    assert_eq!(rates, [48000.0, 96000.0, 96000.0, 48000.0]);
+    assert!(reports.lock().unwrap().is_empty());
+
+    // The plugin can't be activated at 4x, so it carries on at the old factor, and isn't tried
+    // again until the next activation.
+    instance.set_control(2, 4.0);
+    instance.run(16);
+    instance.run(16);
+    assert_eq!(instance.control(0), 48000.0);
+    assert_eq!(instance.latency(), Some(0));
+    assert_eq!(*reports.lock().unwrap(),
+               ["can't switch to 4x oversampling, so staying at 1x: 192000Hz is too fast"]);
+    instance.deactivate();
+    instance.activate();
+    instance.run(16);
+    assert_eq!(instance.control(0), 48000.0);
+    assert_eq!(reports.lock().unwrap().len(), 2);
+    diagnostics::clear_hook();
+}
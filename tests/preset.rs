@@ -0,0 +1,116 @@
+//! Captures presets from plugins and applies them to later versions.
+
+extern crate ladspa;
+
+use ladspa::{expr, FactoryPreset, PluginDescriptor};
+use ladspa::host::{Descriptor, Instance};
+use ladspa::preset::{Error, Preset};
+
+fn plugin(unique_id: u64, label: &'static str, source: &str) -> PluginDescriptor {
+    expr::descriptor(unique_id, label.into(), label.into(), "".into(), "".into(), source).unwrap()
+}
+
+fn version_1() -> Descriptor {
+    Descriptor::from_native(plugin(10, "amp", "
+        input in
+        control gain = 1 [0, 2]
+        control mix = 1 [0, 1]
+        out = in * gain * mix
+    "))
+}
+
+// Renames gain to level, adds a control before it and drops mix.
+fn version_2() -> Descriptor {
+    let mut desc = plugin(10, "amplifier", "
+        input in
+        control bias = 0 [-1, 1]
+        control level = 1 [0, 2]
+        out = in * level + bias
+    ");
+    desc.ports[2].aliases = vec!["gain".into()];
+    desc.presets = vec![FactoryPreset {
+        name: "Quiet".into(),
+        values: vec![("level".into(), 0.25)],
+    }];
+    Descriptor::from_native(desc)
+}
+
+fn captured() -> Preset {
+    let desc = version_1();
+    let mut instance = Instance::new(&desc, 48000, 16).unwrap();
+    instance.set_control(1, 1.5);
+    instance.set_control(2, 0.5);
+    Preset::capture(&instance, "Loud")
+}
+
+#[test]
+fn capture() {
+    let preset = captured();
+    assert_eq!(preset.name, "Loud");
+    assert_eq!((preset.unique_id, &*preset.label), (10, "amp"));
+    assert_eq!(preset.values.iter().map(|(name, &value)| (&**name, value)).collect::<Vec<_>>(),
+               [("gain", 1.5), ("mix", 0.5)]);
+}
+
+#[test]
+fn apply() {
+    let preset = captured();
+    let desc = version_1();
+    let mut instance = Instance::new(&desc, 48000, 16).unwrap();
+    preset.apply(&mut instance).unwrap();
+    assert_eq!((instance.control(1), instance.control(2)), (1.5, 0.5));
+
+    // A plugin with the same label but another unique ID is a different plugin.
+    let other = Descriptor::from_native(plugin(11, "amp", "control gain = 1 [0, 2]; out = gain"));
+    let mut instance = Instance::new(&other, 48000, 16).unwrap();
+    assert!(!preset.matches(&other));
+    match preset.apply(&mut instance) {
+        Err(Error::WrongPlugin) => {}
+        result => panic!("applied a preset to the wrong plugin: {:?}", result),
+    }
+    assert_eq!(instance.control(0), 1.0);
+}
+
+#[test]
+fn migrate() {
+    let preset = captured();
+    let desc = version_2();
+    assert!(preset.matches(&desc));
+    let migrated = preset.migrate(&desc);
+    assert_eq!((migrated.unique_id, &*migrated.label), (10, "amplifier"));
+    assert_eq!(migrated.values.iter().map(|(name, &value)| (&**name, value)).collect::<Vec<_>>(),
+               [("level", 1.5)]);
+
+    // Ports the preset doesn't mention keep their values.
+    let mut instance = Instance::new(&desc, 48000, 16).unwrap();
+    instance.set_control(1, 0.5);
+    preset.apply(&mut instance).unwrap();
+    assert_eq!((instance.control(1), instance.control(2)), (0.5, 1.5));
+
+    let factory = Preset::factory(&desc);
+    assert_eq!(factory.len(), 1);
+    factory[0].apply(&mut instance).unwrap();
+    assert_eq!(instance.control(2), 0.25);
+}
+
+#[test]
+fn find() {
+    let preset = captured();
+    let descriptors = vec![
+        Descriptor::from_native(plugin(9, "amp", "out = 0")),
+        version_2(),
+        version_1(),
+    ];
+    // The unique ID has to match, and the label picks between plugins which share it.
+    assert_eq!(preset.find(&descriptors).unwrap().label(), "amp");
+    assert_eq!(preset.find(&descriptors[..2]).unwrap().label(), "amplifier");
+    assert!(preset.find(&descriptors[..1]).is_none());
+}
+
+#[cfg(feature = "formats")]
+#[test]
+fn formats() {
+    let preset = captured();
+    assert_eq!(Preset::from_toml(&preset.to_toml()).unwrap(), preset);
+    assert_eq!(Preset::from_json(&preset.to_json()).unwrap(), preset);
+}
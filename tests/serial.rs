@@ -0,0 +1,75 @@
+//! Combines plugins into one which runs them in series, and checks its ports and output.
+
+extern crate ladspa;
+
+use ladspa::{block, expr, serial, PluginDescriptor, PortDescriptor};
+use ladspa::host::{Descriptor, Instance};
+use ladspa::serial::Stage;
+
+fn gain() -> PluginDescriptor {
+    let mut desc = expr::descriptor(1, "gain".into(), "Gain".into(), "".into(), "".into(), "
+        input in
+        control gain = 1 [0, 4]
+        out = in * gain
+    ").unwrap();
+    desc.ports[1].aliases = vec!["volume".into()];
+    desc
+}
+
+fn stage(prefix: &'static str, desc: PluginDescriptor) -> Stage {
+    Stage { prefix: prefix.into(), plugin: desc }
+}
+
+fn compose(stages: Vec<Stage>) -> PluginDescriptor {
+    serial::compose(10, "serial".into(), "Serial".into(), "".into(), "".into(), stages)
+}
+
+fn ports(desc: &Descriptor) -> Vec<(String, PortDescriptor)> {
+    (0..desc.port_count()).map(|port| (desc.port_name(port), desc.port_descriptor(port))).collect()
+}
+
+#[test]
+fn prefixes() {
+    let desc = compose(vec![stage("Pre", gain()), stage("Post", gain())]);
+    // Aliases only make sense within the stage.
+    assert!(desc.ports.iter().all(|port| port.aliases.is_empty()));
+    let desc = Descriptor::from_native(desc);
+    assert_eq!((desc.unique_id(), desc.label()), (10, "serial".to_string()));
+    // Only the first stage's audio inputs and the last stage's audio outputs are exposed.
+    assert_eq!(ports(&desc), [
+        ("in".to_string(), PortDescriptor::AudioInput),
+        ("Pre gain".to_string(), PortDescriptor::ControlInput),
+        ("Post gain".to_string(), PortDescriptor::ControlInput),
+        ("out".to_string(), PortDescriptor::AudioOutput),
+    ]);
+
+    let mut instance = Instance::new(&desc, 48000, 2).unwrap();
+    instance.set_control(1, 2.0);
+    instance.set_control(2, 3.0);
+    instance.audio_mut(0).copy_from_slice(&[1.0, 0.5]);
+    instance.activate();
+    instance.run(2);
+    assert_eq!(instance.audio(3), [6.0, 3.0]);
+}
+
+#[test]
+fn latency() {
+    let latent = |unique_id, block_size| {
+        block::wrap(gain(), unique_id, "blocks".into(), block_size, block_size).with_latency_port()
+    };
+    let stages = vec![stage("A", latent(2, 4)), stage("B", gain()), stage("C", latent(3, 8))];
+    let desc = Descriptor::from_native(compose(stages));
+    // The stages' latency ports are replaced by one reporting their sum.
+    let latency_ports = ports(&desc).iter().filter(|port| port.0.contains("latency")).count();
+    assert_eq!(latency_ports, 1);
+    let mut instance = Instance::new(&desc, 48000, 16).unwrap();
+    instance.activate();
+    instance.run(16);
+    assert_eq!(instance.latency(), Some(3 + 7));
+}
+
+#[test]
+#[should_panic(expected = "more than one stage has the prefix \"A\"")]
+fn duplicate_prefixes() {
+    compose(vec![stage("A", gain()), stage("B", gain()), stage("A", gain())]);
+}
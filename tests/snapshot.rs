@@ -0,0 +1,56 @@
+//! Compares plugin output with reference recordings in ```tests/snapshots```. Run with
+//! ```LADSPA_BLESS=1``` to update them.
+
+#![cfg(feature = "testing")]
+
+extern crate ladspa;
+
+use ladspa::{expr, PluginDescriptor};
+use ladspa::host::Descriptor;
+use ladspa::snapshot::{self, Render, Signal, Tolerance};
+
+fn filter() -> PluginDescriptor {
+    expr::descriptor(1, "filter".into(), "Filter".into(), "".into(), "".into(), "
+        input in
+        control cutoff = 1000 [20, 20000] log
+        out = out + (1 - exp(-2 * pi * cutoff / sr)) * (in - out)
+    ").unwrap()
+}
+
+#[test]
+fn filter_sweep() {
+    snapshot::assert_snapshot(filter(),
+                              concat!(env!("CARGO_MANIFEST_DIR"), "/tests/snapshots/filter_sweep.wav"),
+                              &Render {
+                                  length: 4096,
+                                  input: Signal::Sweep(20.0, 20000.0),
+                                  controls: vec![("cutoff".into(), 2000.0)],
+                                  ..Default::default()
+                              },
+                              &Tolerance {
+                                  max_error: Some(1e-5),
+                                  min_snr: Some(100.0),
+                                  max_spectral_difference: Some(0.01),
+                              });
+}
+
+#[test]
+fn tolerances() {
+    let render = Render { length: 4096, ..Default::default() };
+    let expected = snapshot::render(&Descriptor::from_native(filter()), &render).unwrap();
+    // About 0.09dB louder, with a signal-to-noise ratio of 40dB.
+    let louder: Vec<Vec<f32>> = expected.iter().map(|c| c.iter().map(|x| x * 1.01).collect()).collect();
+    let check = |tolerance: Tolerance| snapshot::compare(&expected, &louder, 48000, &tolerance).is_ok();
+    let none = Tolerance { max_error: None, min_snr: None, max_spectral_difference: None };
+
+    assert!(check(none));
+    assert!(!check(Tolerance::default()));
+    assert!(check(Tolerance { min_snr: Some(39.0), ..none }));
+    assert!(!check(Tolerance { min_snr: Some(41.0), ..none }));
+    assert!(check(Tolerance { max_spectral_difference: Some(0.1), ..none }));
+    assert!(!check(Tolerance { max_spectral_difference: Some(0.05), ..none }));
+
+    let path = concat!(env!("CARGO_TARGET_TMPDIR"), "/snapshot/tolerances.wav");
+    snapshot::write(path, 48000, &expected).unwrap();
+    assert_eq!(snapshot::read(path).unwrap(), (48000, expected));
+}
@@ -0,0 +1,134 @@
+//! Records a host session with a plugin and checks that replaying it reproduces the output.
+
+extern crate ladspa;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use ladspa::{expr, Data, PluginDescriptor};
+use ladspa::host::{Descriptor, Instance};
+use ladspa::trace::{self, Call};
+
+fn filter() -> PluginDescriptor {
+    expr::descriptor(1, "filter".into(), "Filter".into(), "".into(), "".into(), "
+        input in
+        control cutoff = 1000 [20, 20000] log
+        out = out + (1 - exp(-2 * pi * cutoff / sr)) * (in - out)
+    ").unwrap()
+}
+
+// Runs the filter in place on noise with a changing cutoff, returning the output.
+fn session(desc: &Descriptor) -> Vec<Data> {
+    let (input, cutoff, output) = (0, 1, 2);
+    let mut instance = Instance::new(desc, 44100, 256).unwrap();
+    instance.connect_in_place(output, input);
+    instance.activate();
+    let mut state = 1u32;
+    let mut rendered = Vec::new();
+    for (block, &size) in [256, 17, 1, 100, 256].iter().enumerate() {
+        instance.set_control(cutoff, 200.0 * (block / 2 + 1) as Data);
+        for x in instance.audio_mut(input) {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            *x = state as Data / u32::MAX as Data - 0.5;
+        }
+        instance.run(size);
+        rendered.extend_from_slice(&instance.audio(output)[..size]);
+    }
+    instance.deactivate();
+    rendered
+}
+
+// Builds a trace of the filter's three ports from the given calls.
+fn trace_bytes(calls: &[&[u32]]) -> Vec<u8> {
+    let mut bytes = b"LADSPATR\x01".to_vec();
+    bytes.extend_from_slice(&6u32.to_le_bytes());
+    bytes.extend_from_slice(b"filter");
+    bytes.extend_from_slice(&1u64.to_le_bytes());
+    bytes.extend_from_slice(&3u32.to_le_bytes());
+    bytes.extend_from_slice(&44100u64.to_le_bytes());
+    for call in calls {
+        bytes.push(call[0] as u8);
+        for value in &call[1..] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    bytes
+}
+
+// Records a session, returning the output and the trace.
+fn record(desc: &Descriptor, dir: &PathBuf, audio: bool) -> (Vec<Data>, PathBuf) {
+    let _ = fs::remove_dir_all(dir);
+    env::set_var(trace::TRACE_VAR, dir);
+    env::set_var(trace::AUDIO_VAR, if audio { "1" } else { "0" });
+    let rendered = session(desc);
+    env::remove_var(trace::TRACE_VAR);
+    env::remove_var(trace::AUDIO_VAR);
+    let mut traces: Vec<PathBuf> = fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+    assert_eq!(traces.len(), 1, "{:?}", traces);
+    (rendered, traces.pop().unwrap())
+}
+
+#[test]
+fn record_and_replay() {
+    let desc = Descriptor::from_native(filter());
+    let dir = PathBuf::from(concat!(env!("CARGO_TARGET_TMPDIR"), "/trace"));
+
+    let (rendered, path) = record(&desc, &dir, true);
+    let recorded = trace::read(&path).unwrap();
+    assert_eq!((&*recorded.label, recorded.unique_id, recorded.sample_rate), ("filter", 1, 44100));
+    assert_eq!(recorded.calls.iter().filter(|call| match **call {
+        Call::Run { ref controls, .. } => !controls.is_empty(),
+        _ => false,
+    }).count(), 3);
+    assert_eq!(recorded.calls.last(), Some(&Call::Cleanup));
+    assert_eq!(trace::replay(&desc, &recorded).unwrap(), vec![rendered.clone()]);
+
+    // A trace cut off part way through a call keeps the calls before it.
+    let bytes = fs::read(&path).unwrap();
+    fs::write(&path, &bytes[..bytes.len() - 3]).unwrap();
+    let truncated = trace::read(&path).unwrap();
+    assert_eq!(truncated.calls[..], recorded.calls[..recorded.calls.len() - 3]);
+
+    // Without audio, the inputs are replayed as silence.
+    let (_, path) = record(&desc, &dir, false);
+    let silent = trace::read(&path).unwrap();
+    assert!(silent.calls.iter().all(|call| match *call {
+        Call::Run { ref audio, .. } => audio.is_empty(),
+        _ => true,
+    }));
+    let replayed = trace::replay(&desc, &silent).unwrap();
+    assert_eq!(replayed[0].len(), rendered.len());
+    assert!(replayed[0].iter().all(|&x| x == 0.0));
+
+    let other = Descriptor::from_native(PluginDescriptor { unique_id: 2, ..filter() });
+    assert!(trace::replay(&other, &recorded).is_err());
+}
+
+#[test]
+fn damaged() {
+    let dir = PathBuf::from(concat!(env!("CARGO_TARGET_TMPDIR"), "/damaged"));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("damaged.trace");
+    let error = |calls: &[&[u32]]| {
+        fs::write(&path, trace_bytes(calls)).unwrap();
+        trace::read(&path).expect_err("the trace was read").to_string()
+    };
+    let (connect, activate, run) = (0, 1, 2);
+
+    // A trace which is fine apart from where noted.
+    fs::write(&path, trace_bytes(&[&[connect, 0, 0], &[connect, 2, 1], &[activate],
+                                   &[run, 256, 0, 0]])).unwrap();
+    let good = trace::read(&path).unwrap();
+    assert_eq!(good.calls[1], Call::ConnectPort { port: 2, buffer: 1 });
+    assert_eq!(trace::replay(&Descriptor::from_native(filter()), &good).unwrap()[0].len(), 256);
+
+    assert_eq!(error(&[&[connect, 0, 5]]), "invalid trace: buffer 5 is connected before buffer 0");
+    assert_eq!(error(&[&[connect, 0, 0], &[connect, 2, 2]]),
+               "invalid trace: buffer 2 is connected before buffer 1");
+    assert_eq!(error(&[&[connect, 0, 0], &[connect, 2, 1], &[run, u32::MAX, 0, 0]]),
+               "invalid trace: 2 buffers of 4294967295 samples are too large to replay");
+    assert_eq!(error(&[&[connect, 3, 0]]), "invalid trace: port 3 of a plugin with 3 ports");
+}
@@ -0,0 +1,58 @@
+//! Loads and unloads a plugin library repeatedly in one process. The library must free its
+//! descriptors as it is unloaded, rather than leaving a callback to run at exit.
+
+extern crate ladspa;
+extern crate libc;
+
+use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use ladspa::host::{Instance, Library};
+
+// Builds the delay example, returning the path to the library.
+fn build_delay() -> PathBuf {
+    let manifest = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/delay/Cargo.toml");
+    let target = env!("CARGO_TARGET_TMPDIR");
+    let status = Command::new(option_env!("CARGO").unwrap_or("cargo"))
+        .args(["build", "--quiet", "--manifest-path", manifest, "--target-dir", target])
+        .status()
+        .expect("failed to run cargo");
+    assert!(status.success(), "failed to build the delay example");
+    PathBuf::from(target).join("debug").join(format!("{}rustdelay{}", DLL_PREFIX, DLL_SUFFIX))
+}
+
+// Whether the library is still mapped into the process, without loading it if it isn't.
+fn is_loaded(path: &Path) -> bool {
+    let path = CString::new(path.as_os_str().as_bytes()).unwrap();
+    unsafe {
+        let handle = libc::dlopen(path.as_ptr(), libc::RTLD_LAZY | libc::RTLD_NOLOAD);
+        if handle.is_null() {
+            return false;
+        }
+        libc::dlclose(handle);
+        true
+    }
+}
+
+#[test]
+fn load_and_unload() {
+    let path = build_delay();
+    for _ in 0..100 {
+        let lib = Library::open(&path).unwrap();
+        let descriptors = Library::descriptors(&lib);
+        assert_eq!(descriptors.iter().map(|d| d.label()).collect::<Vec<_>>(),
+                   ["stereo_delay", "mono_delay", "linked_stereo_delay"]);
+
+        let mut instance = Instance::new(&descriptors[1], 44100, 64).unwrap();
+        instance.activate();
+        instance.run(64);
+        drop(instance);
+        drop(descriptors);
+        assert!(is_loaded(&path));
+        drop(lib);
+        assert!(!is_loaded(&path), "the library was not unloaded");
+    }
+}
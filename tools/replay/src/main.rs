@@ -0,0 +1,70 @@
+//! Replays a trace recorded by a plugin, to reproduce what a host did to it.
+//!
+//! Usage: ladspa-replay [--output FILE.wav] LIBRARY TRACE
+//!
+//! Traces are written by plugins built with this crate when the ```LADSPA_TRACE``` environment
+//! variable names a directory; see ```ladspa::trace```. The plugin the trace was recorded from is
+//! found in the library by its label and unique ID, and driven through the same calls, in the same
+//! order, with the same block sizes, control values and buffers. With ```--output```, everything
+//! the plugin wrote to its audio outputs is saved as a 32-bit float WAV file.
+//!
+//! The exit status is 1 if the plugin panicked or the trace couldn't be replayed. A plugin which
+//! crashes takes the replay down with it, just as it did the host, so run it under a debugger.
+
+extern crate ladspa;
+
+use std::env;
+use std::process;
+
+use ladspa::{snapshot, trace};
+use ladspa::host::{self, Library};
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let output = match args.iter().position(|arg| arg == "--output") {
+        Some(i) if i + 1 < args.len() => {
+            let output = args.remove(i + 1);
+            args.remove(i);
+            Some(output)
+        }
+        _ => None,
+    };
+    if args.len() != 2 || args.iter().any(|arg| arg.starts_with("--")) {
+        eprintln!("usage: ladspa-replay [--output FILE.wav] LIBRARY TRACE");
+        process::exit(2);
+    }
+
+    let recorded = match trace::read(&args[1]) {
+        Ok(trace) => trace,
+        Err(err) => fail(&err.to_string()),
+    };
+    println!("{}", recorded);
+    let path = host::find_library(&args[0]);
+    let lib = match Library::open(&path) {
+        Ok(lib) => lib,
+        Err(err) => fail(&format!("{}: {}", path.display(), err)),
+    };
+    let desc = match Library::descriptors(&lib)
+        .into_iter()
+        .find(|desc| desc.label() == recorded.label && desc.unique_id() == recorded.unique_id) {
+        Some(desc) => desc,
+        None => fail(&format!("{} has no plugin {} ({})", path.display(), recorded.label, recorded.unique_id)),
+    };
+
+    let rendered = match trace::replay(&desc, &recorded) {
+        Ok(rendered) => rendered,
+        Err(err) => fail(&err.to_string()),
+    };
+    println!("replayed {} calls", recorded.calls.len());
+    if let Some(output) = output {
+        if let Err(err) = snapshot::write(&output, recorded.sample_rate, &rendered) {
+            fail(&err.to_string());
+        }
+        println!("wrote {} channels to {}", rendered.len(), output);
+    }
+}
+
+fn fail(msg: &str) -> ! {
+    eprintln!("ladspa-replay: {}", msg);
+    process::exit(1);
+}
@@ -0,0 +1,176 @@
+//! A plugin library which re-exports installed third-party LADSPA plugins with added dry/wet,
+//! output gain and bypass controls.
+//!
+//! The plugins to export are listed in a TOML file with the same name as the library but a
+//! ```.toml``` extension, in the same directory, so ```libladspa_shell.so``` reads
+//! ```libladspa_shell.toml```:
+//!
+//! ```toml
+//! [[plugin]]
+//! library = "cmt.so"
+//! label = "amp_mono"
+//! unique_id = 4500
+//! export_label = "studio_amp_mono"
+//! name = "Studio Amp (Mono)"
+//!
+//! [[plugin]]
+//! library = "/usr/lib/ladspa/delay.so"
+//! id = 1043
+//! unique_id = 4501
+//! ```
+//!
+//! Plugins are found by ```label``` or by ```id``` as in chain files, and library paths without a
+//! directory are searched for in ```LADSPA_PATH``` and the usual system locations. Each exported
+//! plugin needs a new ```unique_id```, and keeps the original label unless ```export_label``` is
+//! given. Its ports are those of the original followed by "Dry/Wet", "Output Gain (dB)" and
+//! "Bypass", and every call is forwarded to an instance of the original.
+//!
+//! The list is read the first time a host asks for a descriptor. Plugins which can't be loaded,
+//! or which clash with an earlier one, are reported through ```ladspa::diagnostics``` and
+//! skipped.
+
+// Written in the same style as the crate itself, for the same minimum Rust version.
+#![allow(clippy::unnecessary_map_or)]
+
+extern crate ladspa;
+extern crate serde;
+#[macro_use] extern crate serde_derive;
+extern crate toml;
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+
+use ladspa::{bypass, definitions, diagnostics, foreign, mix, PluginDescriptor};
+use ladspa::host::{self, Descriptor, Library};
+
+// How long switching the bypass on or off takes, in seconds.
+const BYPASS_FADE: f32 = 0.01;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    #[serde(default, rename = "plugin")]
+    plugins: Vec<Target>,
+}
+
+// A third-party plugin to export.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Target {
+    library: PathBuf,
+    #[serde(default)]
+    label: Option<String>,
+    #[serde(default)]
+    id: Option<u64>,
+    unique_id: u64,
+    #[serde(default)]
+    export_label: Option<String>,
+    #[serde(default)]
+    name: Option<String>,
+}
+
+// The plugins to export, each with the label it is exported under.
+static PLUGINS: OnceLock<Vec<(Target, String, Descriptor)>> = OnceLock::new();
+
+#[no_mangle]
+pub fn get_ladspa_descriptor(index: u64) -> Option<PluginDescriptor> {
+    PLUGINS.get_or_init(load).get(index as usize).map(|(target, label, desc)| {
+        // Only the outermost wrapper is seen by hosts, but each gets the exported ID and label.
+        let plugin = mix::wrap(foreign::wrap(desc), target.unique_id, label.clone().into());
+        let mut plugin = bypass::wrap(plugin, target.unique_id, label.clone().into(), BYPASS_FADE);
+        if let Some(ref name) = target.name {
+            plugin.name = name.clone().into();
+        }
+        plugin
+    })
+}
+
+fn load() -> Vec<(Target, String, Descriptor)> {
+    // The list is found in the same way as plugin definitions.
+    let path = match definitions::definitions_path() {
+        Some(path) => path,
+        None => {
+            diagnostics::report("ladspa-shell: could not find the path of the shell library");
+            return vec![];
+        }
+    };
+    let config = match read_config(&path) {
+        Ok(config) => config,
+        Err(err) => {
+            diagnostics::report(&format!("ladspa-shell: {}: {}", path.display(), err));
+            return vec![];
+        }
+    };
+
+    let shell = definitions::library_path().and_then(|path| path.canonicalize().ok());
+    let mut libraries: Vec<(PathBuf, Arc<Library>)> = Vec::new();
+    let mut labels = HashSet::new();
+    let mut ids = HashSet::new();
+    let mut plugins = Vec::new();
+    for target in config.plugins {
+        let desc = match find(&target, shell.as_deref(), &mut libraries) {
+            Ok(desc) => desc,
+            Err(err) => {
+                diagnostics::report(&format!("ladspa-shell: {}: {}", target.library.display(), err));
+                continue;
+            }
+        };
+        let label = target.export_label.clone().unwrap_or_else(|| desc.label());
+        if !labels.insert(label.clone()) {
+            diagnostics::report(&format!("ladspa-shell: {}: label is used by an earlier plugin", label));
+            continue;
+        }
+        if !ids.insert(target.unique_id) {
+            diagnostics::report(&format!("ladspa-shell: {}: unique_id {} is used by an earlier plugin",
+                                         label,
+                                         target.unique_id));
+            continue;
+        }
+        plugins.push((target, label, desc));
+    }
+    plugins
+}
+
+fn read_config(path: &Path) -> Result<Config, String> {
+    let mut text = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut text))
+        .map_err(|e| e.to_string())?;
+    toml::from_str(&text).map_err(|e| e.to_string())
+}
+
+// Finds the plugin a target refers to, opening its library unless an earlier target already has.
+fn find(target: &Target,
+        shell: Option<&Path>,
+        libraries: &mut Vec<(PathBuf, Arc<Library>)>)
+        -> Result<Descriptor, String> {
+    if target.label.is_none() && target.id.is_none() {
+        return Err("plugin needs a label or id".to_string());
+    }
+    let path = host::find_library(&target.library);
+    // Opening the shell from inside its own descriptor table would never finish.
+    if shell.is_some() && path.canonicalize().ok().as_deref() == shell {
+        return Err("the shell can't export its own plugins".to_string());
+    }
+    let lib = match libraries.iter().position(|x| x.0 == path) {
+        Some(idx) => libraries[idx].1.clone(),
+        None => {
+            let lib = Library::open(&path).map_err(|e| e.to_string())?;
+            libraries.push((path, lib.clone()));
+            lib
+        }
+    };
+    Library::descriptors(&lib)
+        .into_iter()
+        .find(|desc| {
+            target.label.as_ref().map_or(true, |label| desc.label() == *label) &&
+            target.id.map_or(true, |id| desc.unique_id() == id)
+        })
+        .ok_or_else(|| {
+            let name = target.label.clone().unwrap_or_else(|| target.id.unwrap().to_string());
+            format!("plugin {} not found", name)
+        })
+}
@@ -0,0 +1,108 @@
+//! Exports the delay example through the shell, from a plugin list with mistakes in it.
+
+extern crate ladspa;
+extern crate libc;
+
+use std::env;
+use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+use std::fs::{self, File};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::process::Command;
+
+use ladspa::PortDescriptor;
+use ladspa::host::Library;
+
+// Builds the delay example, returning the path to the library.
+fn build_delay() -> PathBuf {
+    let manifest = concat!(env!("CARGO_MANIFEST_DIR"), "/../../examples/delay/Cargo.toml");
+    let target = env!("CARGO_TARGET_TMPDIR");
+    let status = Command::new(option_env!("CARGO").unwrap_or("cargo"))
+        .args(["build", "--quiet", "--manifest-path", manifest, "--target-dir", target])
+        .status()
+        .expect("failed to run cargo");
+    assert!(status.success(), "failed to build the delay example");
+    PathBuf::from(target).join("debug").join(format!("{}rustdelay{}", DLL_PREFIX, DLL_SUFFIX))
+}
+
+#[test]
+fn export() {
+    let delay = build_delay();
+    // The shell library is built next to this test, and reads the list next to itself.
+    let shell = env::current_exe()
+        .unwrap()
+        .with_file_name(format!("{}ladspa_shell{}", DLL_PREFIX, DLL_SUFFIX));
+    let list = shell.with_extension("toml");
+    fs::write(&list, format!(r#"
+        [[plugin]]
+        library = "{delay}"
+        label = "mono_delay"
+        unique_id = 4500
+        export_label = "shell_delay"
+        name = "Shell Delay"
+
+        [[plugin]]
+        library = "{delay}"
+        id = 400
+        unique_id = 4501
+
+        [[plugin]]
+        library = "{delay}"
+        unique_id = 4502
+
+        [[plugin]]
+        library = "{delay}"
+        label = "reverb"
+        unique_id = 4503
+
+        [[plugin]]
+        library = "missing.so"
+        label = "amp"
+        unique_id = 4504
+
+        [[plugin]]
+        library = "{delay}"
+        label = "stereo_delay"
+        unique_id = 4505
+
+        [[plugin]]
+        library = "{delay}"
+        label = "linked_stereo_delay"
+        unique_id = 4500
+    "#, delay = delay.display())).unwrap();
+
+    // The shell has its own copy of ladspa, so its reports are read from standard error.
+    let log = list.with_extension("log");
+    let plugins = unsafe {
+        let saved = libc::dup(2);
+        libc::dup2(File::create(&log).unwrap().as_raw_fd(), 2);
+        let plugins = Library::open(&shell).map(|lib| Library::descriptors(&lib));
+        libc::dup2(saved, 2);
+        libc::close(saved);
+        plugins.unwrap()
+    };
+    let reports: Vec<_> = fs::read_to_string(&log).unwrap().lines().map(String::from).collect();
+    fs::remove_file(&list).unwrap();
+    fs::remove_file(&log).unwrap();
+
+    let exported: Vec<_> = plugins.iter()
+        .map(|desc| (desc.unique_id(), desc.label(), desc.name()))
+        .collect();
+    assert_eq!(exported, [
+        (4500, "shell_delay".to_string(), "Shell Delay".to_string()),
+        (4501, "stereo_delay".to_string(), "Stereo Delay".to_string()),
+    ]);
+    let ports: Vec<_> = (0..plugins[0].port_count()).map(|port| plugins[0].port_name(port)).collect();
+    // The delay has a dry/wet control of its own, so the added one is numbered.
+    assert_eq!(ports[ports.len() - 3..], ["Dry/Wet 2", "Output Gain (dB)", "Bypass"]);
+    assert_eq!(plugins[0].port_descriptor(ports.len() - 1), PortDescriptor::ControlInput);
+
+    let delay = delay.display();
+    assert_eq!(reports.len(), 5, "{:?}", reports);
+    assert_eq!(reports[0], format!("ladspa.rs: ladspa-shell: {}: plugin needs a label or id", delay));
+    assert_eq!(reports[1], format!("ladspa.rs: ladspa-shell: {}: plugin reverb not found", delay));
+    assert!(reports[2].starts_with("ladspa.rs: ladspa-shell: missing.so: "), "{}", reports[2]);
+    assert_eq!(reports[3], "ladspa.rs: ladspa-shell: stereo_delay: label is used by an earlier plugin");
+    assert_eq!(reports[4],
+               "ladspa.rs: ladspa-shell: linked_stereo_delay: unique_id 4500 is used by an earlier plugin");
+}
@@ -0,0 +1,267 @@
+//! Checks that the plugins in LADSPA libraries honour the specification, whatever language they
+//! are written in.
+//!
+//! Usage: ladspa-validate LIBRARY...
+//!
+//! Library paths without a directory are searched for in ```LADSPA_PATH``` and the usual system
+//! locations. Each library's descriptors are checked as described by ```ladspa::conformance```,
+//! and every plugin is then run through instantiate, activate, run, deactivate and cleanup at a
+//! range of sample rates and block sizes, checking that its output stays finite. Each plugin runs
+//! in a separate process, so one which crashes or hangs is reported without stopping the rest.
+//!
+//! A report listing every failure is printed, and the exit status is 1 if any plugin failed.
+
+extern crate ladspa;
+
+use std::env;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{self, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ladspa::{conformance, ffi, Data, PortDescriptor};
+use ladspa::host::{self, Descriptor, Instance, Library};
+
+// The hidden option which makes the validator check a single plugin in a child process.
+const CHILD: &str = "--plugin";
+
+// How long a plugin may take to be checked before it is considered to have hung.
+const TIMEOUT: Duration = Duration::from_secs(120);
+
+const SAMPLE_RATES: [u64; 5] = [22050, 44100, 48000, 96000, 192000];
+const BLOCK_SIZES: [usize; 5] = [1, 7, 64, 1024, 4096];
+
+// How many seconds of noise are run at each sample rate and block size.
+const DURATION: f64 = 0.25;
+
+// Lines written by the child process start with one of these, to tell them apart from anything
+// the plugin prints.
+const FAILURE: &str = "ladspa-validate failure: ";
+const STEP: &str = "ladspa-validate step: ";
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() == 4 && args[1] == CHILD {
+        let index = args[3].parse().expect("invalid plugin index");
+        process::exit(if check_plugin(Path::new(&args[2]), index) { 0 } else { 1 });
+    }
+    if args.len() < 2 {
+        eprintln!("usage: {} LIBRARY...", args[0]);
+        process::exit(2);
+    }
+
+    let (mut passed, mut total, mut failed_libraries) = (0, 0, 0);
+    for name in &args[1..] {
+        let path = host::find_library(name);
+        println!("{}", path.display());
+        let lib = match Library::open(&path) {
+            Ok(lib) => lib,
+            Err(err) => {
+                println!("  FAIL  {}", err);
+                failed_libraries += 1;
+                continue;
+            }
+        };
+        let descs = Library::descriptors(&lib);
+        if descs.is_empty() {
+            println!("  FAIL  the library has no plugins");
+            failed_libraries += 1;
+        }
+        let errors = conformance::check_library(&descs);
+        for err in &errors {
+            println!("  FAIL  {}", err);
+        }
+        if !errors.is_empty() {
+            failed_libraries += 1;
+        }
+        for (index, desc) in descs.iter().enumerate() {
+            let failures = run_child(&path, index);
+            // The label of a broken descriptor can't be read without crashing the validator too.
+            let title = if conformance::readable(desc) {
+                format!("{} {}", desc.unique_id(), desc.label())
+            } else {
+                format!("index {}", index)
+            };
+            if failures.is_empty() {
+                println!("  pass  {}", title);
+                passed += 1;
+            } else {
+                println!("  FAIL  {}", title);
+                for failure in failures {
+                    println!("          {}", failure);
+                }
+            }
+            total += 1;
+        }
+    }
+    println!("{} of {} plugins passed", passed, total);
+    if passed < total || failed_libraries > 0 {
+        process::exit(1);
+    }
+}
+
+// Checks a plugin in a child process, returning its failures.
+fn run_child(path: &Path, index: usize) -> Vec<String> {
+    let exe = env::current_exe().expect("can't find the validator's own executable");
+    let mut child = match Command::new(exe)
+        .arg(CHILD)
+        .arg(path)
+        .arg(index.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn() {
+        Ok(child) => child,
+        Err(err) => return vec![format!("could not start a process to check the plugin: {}", err)],
+    };
+
+    // Read the output on another thread, so that a plugin which hangs can be killed.
+    let stdout = child.stdout.take().unwrap();
+    let reader = thread::spawn(move || {
+        let (mut failures, mut step) = (Vec::new(), String::new());
+        for line in BufReader::new(stdout).split(b'\n').map_while(Result::ok) {
+            let line = String::from_utf8_lossy(&line);
+            if let Some(failure) = line.strip_prefix(FAILURE) {
+                failures.push(failure.to_string());
+            } else if let Some(s) = line.strip_prefix(STEP) {
+                step = s.to_string();
+            }
+        }
+        (failures, step)
+    });
+    let start = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break Some(status),
+            Ok(None) if start.elapsed() > TIMEOUT => {
+                let _ = child.kill();
+                let _ = child.wait();
+                break None;
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(10)),
+            Err(err) => return vec![format!("could not wait for the plugin's process: {}", err)],
+        }
+    };
+    let (mut failures, step) = reader.join().unwrap();
+    match status {
+        None => failures.push(format!("hung while {}", step)),
+        Some(status) => {
+            if let Some(signal) = signal(&status) {
+                failures.push(format!("crashed with signal {} while {}", signal, step));
+            } else if !status.success() && failures.is_empty() {
+                failures.push(format!("exited with {} while {}", status, step));
+            }
+        }
+    }
+    failures
+}
+
+#[cfg(unix)]
+fn signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn signal(_: &ExitStatus) -> Option<i32> {
+    None
+}
+
+// Checks a single plugin, writing each failure to standard output. Returns whether it passed.
+fn check_plugin(path: &Path, index: usize) -> bool {
+    let lib = Library::open(path).expect("the library could not be reopened");
+    let desc = Library::descriptors(&lib).into_iter().nth(index).expect("the plugin disappeared");
+    let mut passed = true;
+    let mut fail = |msg: &str| {
+        println!("{}{}", FAILURE, msg);
+        let _ = io::stdout().flush();
+        passed = false;
+    };
+
+    // Report problems with the descriptor first, in case running the plugin crashes.
+    step("checking the descriptor");
+    let descriptor_errors = conformance::check_descriptor(&desc);
+    for err in &descriptor_errors {
+        fail(&err.to_string());
+    }
+    step("running the conformance checks");
+    for err in conformance::check(&desc).into_iter().skip(descriptor_errors.len()) {
+        fail(&err.to_string());
+    }
+    // Only the first failure is reported, as the rest usually fail in the same way.
+    'rates: for &sample_rate in &SAMPLE_RATES {
+        for &block_size in &BLOCK_SIZES {
+            step(&format!("running at {}Hz in blocks of {} samples", sample_rate, block_size));
+            if let Err(msg) = stress(&desc, sample_rate, block_size) {
+                fail(&format!("at {}Hz in blocks of {} samples: {}", sample_rate, block_size, msg));
+                break 'rates;
+            }
+        }
+    }
+    step("cleaning up");
+    drop(lib);
+    passed
+}
+
+fn step(description: &str) {
+    println!("{}{}", STEP, description);
+    let _ = io::stdout().flush();
+}
+
+// Runs a plugin on noise through its whole lifecycle, checking that its output stays finite.
+fn stress(desc: &Descriptor, sample_rate: u64, block_size: usize) -> Result<(), String> {
+    let instance = Instance::new(desc, sample_rate, block_size);
+    suppressed()?;
+    let mut instance = instance.map_err(|err| err.to_string())?;
+    let ports = |kind| (0..desc.port_count()).filter(|&i| desc.port_descriptor(i) == kind).collect::<Vec<_>>();
+    let (inputs, outputs) = (ports(PortDescriptor::AudioInput), ports(PortDescriptor::AudioOutput));
+    let controls = ports(PortDescriptor::ControlOutput);
+    let blocks = ((sample_rate as f64 * DURATION) as usize / block_size).max(4);
+    let mut state = 1u32;
+
+    // Run twice, to check that the plugin still works after being deactivated.
+    for pass in 0..2 {
+        instance.activate();
+        suppressed()?;
+        for block in 0..blocks {
+            for &port in &inputs {
+                for x in instance.audio_mut(port) {
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    *x = (state as Data / u32::MAX as Data - 0.5) * 1.5;
+                }
+            }
+            instance.run(block_size);
+            suppressed()?;
+            for &port in &outputs {
+                if let Some(k) = instance.audio(port).iter().position(|x| !x.is_finite()) {
+                    return Err(format!("{} is {} at sample {} of block {} after activation {}",
+                                       desc.port_name(port),
+                                       instance.audio(port)[k],
+                                       k,
+                                       block,
+                                       pass + 1));
+                }
+            }
+            for &port in &controls {
+                if !instance.control(port).is_finite() {
+                    return Err(format!("{} is {} after block {}", desc.port_name(port), instance.control(port), block));
+                }
+            }
+        }
+        instance.deactivate();
+        suppressed()?;
+    }
+    drop(instance);
+    suppressed()
+}
+
+// Fails if a plugin written with this crate panicked during the last call.
+fn suppressed() -> Result<(), String> {
+    match ffi::take_suppressed_panic() {
+        Some(msg) => Err(msg),
+        None => Ok(()),
+    }
+}